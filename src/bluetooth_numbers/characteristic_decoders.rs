@@ -0,0 +1,517 @@
+use super::company_ids;
+use super::compare_uuid::compare_uuid;
+use std::sync::Arc;
+
+use crate::utils::print_bytes;
+
+/// Decode the value of a characteristic into a human readable text
+type Decoder = fn(&[u8]) -> Result<String, String>;
+
+/// Decoders of the characteristics defined in the GATT specification supplement
+const DECODERS: [(&str, Decoder); 10] = [
+    ("2A01", decode_appearance),
+    ("2A08", decode_date_time),
+    ("2A19", decode_battery_level),
+    ("2A1C", decode_temperature_measurement),
+    ("2A35", decode_blood_pressure_measurement),
+    ("2A37", decode_heart_rate_measurement),
+    ("2A38", decode_body_sensor_location),
+    ("2A50", decode_pnp_id),
+    ("2A53", decode_rsc_measurement),
+    ("2A5B", decode_csc_measurement),
+];
+
+/// Little endian reader over a characteristic value
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < self.pos + n {
+            return Err(format!(
+                "value too short ({} bytes, at least {} expected)",
+                self.bytes.len(),
+                self.pos + n
+            ));
+        }
+        self.pos += n;
+        Ok(&self.bytes[self.pos - n..self.pos])
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+}
+
+/// Format mantissa * 10^exponent with as many decimals as the exponent gives
+fn format_decimal(mantissa: i32, exponent: i8) -> String {
+    let value = mantissa as f64 * 10f64.powi(exponent as i32);
+    format!("{:.*}", (-exponent).max(0) as usize, value)
+}
+
+/// IEEE-11073 32-bit FLOAT
+fn ieee11073_float(raw: u32) -> String {
+    let mantissa = (raw & 0x00ff_ffff) as i32;
+    let exponent = (raw >> 24) as i8;
+
+    match mantissa {
+        0x7f_ffff => "NaN".to_owned(),
+        0x80_0000 => "NRes".to_owned(),
+        0x7f_fffe => "+INF".to_owned(),
+        0x80_0002 => "-INF".to_owned(),
+        0x80_0001 => "Reserved".to_owned(),
+        _ => format_decimal((mantissa << 8) >> 8, exponent),
+    }
+}
+
+/// IEEE-11073 16-bit SFLOAT
+fn ieee11073_sfloat(raw: u16) -> String {
+    let mantissa = (raw & 0x0fff) as i16;
+    let exponent = ((raw as i16) >> 12) as i8;
+
+    match mantissa {
+        0x07ff => "NaN".to_owned(),
+        0x0800 => "NRes".to_owned(),
+        0x07fe => "+INF".to_owned(),
+        0x0802 => "-INF".to_owned(),
+        0x0801 => "Reserved".to_owned(),
+        _ => format_decimal(((mantissa << 4) >> 4) as i32, exponent),
+    }
+}
+
+fn read_date_time(r: &mut Reader) -> Result<String, String> {
+    let year = r.u16()?;
+    let month = r.u8()?;
+    let day = r.u8()?;
+    let hours = r.u8()?;
+    let minutes = r.u8()?;
+    let seconds = r.u8()?;
+
+    Ok(format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, hours, minutes, seconds
+    ))
+}
+
+fn decode_battery_level(bytes: &[u8]) -> Result<String, String> {
+    Ok(format!("{} %", Reader::new(bytes).u8()?))
+}
+
+fn decode_date_time(bytes: &[u8]) -> Result<String, String> {
+    read_date_time(&mut Reader::new(bytes))
+}
+
+fn decode_body_sensor_location(bytes: &[u8]) -> Result<String, String> {
+    let location = Reader::new(bytes).u8()?;
+
+    Ok(match location {
+        0 => "Other",
+        1 => "Chest",
+        2 => "Wrist",
+        3 => "Finger",
+        4 => "Hand",
+        5 => "Ear Lobe",
+        6 => "Foot",
+        _ => return Ok(format!("Reserved ({})", location)),
+    }
+    .to_owned())
+}
+
+fn decode_heart_rate_measurement(bytes: &[u8]) -> Result<String, String> {
+    let mut r = Reader::new(bytes);
+    let flags = r.u8()?;
+    let mut out = Vec::new();
+
+    let heart_rate = if flags & 0x01 != 0 {
+        r.u16()?
+    } else {
+        r.u8()? as u16
+    };
+    out.push(format!("heart_rate={} bpm", heart_rate));
+
+    if flags & 0x04 != 0 {
+        out.push(format!(
+            "contact={}",
+            if flags & 0x02 != 0 {
+                "detected"
+            } else {
+                "not detected"
+            }
+        ));
+    }
+
+    if flags & 0x08 != 0 {
+        out.push(format!("energy_expended={} kJ", r.u16()?));
+    }
+
+    if flags & 0x10 != 0 {
+        let mut rr_intervals = Vec::new();
+        while !r.is_empty() {
+            rr_intervals.push(format!("{:.3} s", r.u16()? as f64 / 1024.0));
+        }
+        out.push(format!("rr_intervals=[{}]", rr_intervals.join(", ")));
+    }
+
+    Ok(out.join(", "))
+}
+
+fn decode_temperature_measurement(bytes: &[u8]) -> Result<String, String> {
+    let mut r = Reader::new(bytes);
+    let flags = r.u8()?;
+    let unit = if flags & 0x01 != 0 { "°F" } else { "°C" };
+    let mut out = vec![format!(
+        "temperature={} {}",
+        ieee11073_float(r.u32()?),
+        unit
+    )];
+
+    if flags & 0x02 != 0 {
+        out.push(format!("timestamp={}", read_date_time(&mut r)?));
+    }
+
+    if flags & 0x04 != 0 {
+        let location = match r.u8()? {
+            1 => "Armpit".to_owned(),
+            2 => "Body".to_owned(),
+            3 => "Ear".to_owned(),
+            4 => "Finger".to_owned(),
+            5 => "Gastro-intestinal Tract".to_owned(),
+            6 => "Mouth".to_owned(),
+            7 => "Rectum".to_owned(),
+            8 => "Toe".to_owned(),
+            9 => "Tympanum".to_owned(),
+            n => format!("Reserved ({})", n),
+        };
+        out.push(format!("type={}", location));
+    }
+
+    Ok(out.join(", "))
+}
+
+fn decode_blood_pressure_measurement(bytes: &[u8]) -> Result<String, String> {
+    let mut r = Reader::new(bytes);
+    let flags = r.u8()?;
+    let unit = if flags & 0x01 != 0 { "kPa" } else { "mmHg" };
+
+    let mut out = vec![
+        format!("systolic={} {}", ieee11073_sfloat(r.u16()?), unit),
+        format!("diastolic={} {}", ieee11073_sfloat(r.u16()?), unit),
+        format!(
+            "mean_arterial_pressure={} {}",
+            ieee11073_sfloat(r.u16()?),
+            unit
+        ),
+    ];
+
+    if flags & 0x02 != 0 {
+        out.push(format!("timestamp={}", read_date_time(&mut r)?));
+    }
+
+    if flags & 0x04 != 0 {
+        out.push(format!("pulse_rate={} bpm", ieee11073_sfloat(r.u16()?)));
+    }
+
+    if flags & 0x08 != 0 {
+        out.push(format!("user_id={}", r.u8()?));
+    }
+
+    if flags & 0x10 != 0 {
+        const STATUS: [&str; 6] = [
+            "BODY_MOVEMENT",
+            "CUFF_TOO_LOOSE",
+            "IRREGULAR_PULSE",
+            "PULSE_RATE_EXCEEDS_UPPER_LIMIT",
+            "PULSE_RATE_LESS_THAN_LOWER_LIMIT",
+            "IMPROPER_MEASUREMENT_POSITION",
+        ];
+        let status = r.u16()?;
+        let mut set: Vec<&str> = Vec::new();
+
+        // bits 3 and 4 are the pulse rate range: 0b01 exceeds upper limit, 0b10 less than lower
+        for (bit, name) in STATUS.iter().enumerate() {
+            if status & (1 << bit) != 0 {
+                set.push(name);
+            }
+        }
+        out.push(format!("status=[{}]", set.join(", ")));
+    }
+
+    Ok(out.join(", "))
+}
+
+fn decode_csc_measurement(bytes: &[u8]) -> Result<String, String> {
+    let mut r = Reader::new(bytes);
+    let flags = r.u8()?;
+    let mut out = Vec::new();
+
+    if flags & 0x01 != 0 {
+        out.push(format!("wheel_revolutions={}", r.u32()?));
+        out.push(format!(
+            "last_wheel_event_time={:.3} s",
+            r.u16()? as f64 / 1024.0
+        ));
+    }
+
+    if flags & 0x02 != 0 {
+        out.push(format!("crank_revolutions={}", r.u16()?));
+        out.push(format!(
+            "last_crank_event_time={:.3} s",
+            r.u16()? as f64 / 1024.0
+        ));
+    }
+
+    Ok(out.join(", "))
+}
+
+fn decode_rsc_measurement(bytes: &[u8]) -> Result<String, String> {
+    let mut r = Reader::new(bytes);
+    let flags = r.u8()?;
+
+    let mut out = vec![
+        format!("speed={:.2} m/s", r.u16()? as f64 / 256.0),
+        format!("cadence={} rpm", r.u8()?),
+    ];
+
+    if flags & 0x01 != 0 {
+        out.push(format!("stride_length={:.2} m", r.u16()? as f64 / 100.0));
+    }
+
+    if flags & 0x02 != 0 {
+        out.push(format!("total_distance={:.1} m", r.u32()? as f64 / 10.0));
+    }
+
+    out.push(format!(
+        "status={}",
+        if flags & 0x04 != 0 {
+            "running"
+        } else {
+            "walking"
+        }
+    ));
+
+    Ok(out.join(", "))
+}
+
+fn decode_pnp_id(bytes: &[u8]) -> Result<String, String> {
+    let mut r = Reader::new(bytes);
+    let source = r.u8()?;
+    let vendor_id = r.u16()?;
+    let product_id = r.u16()?;
+    let product_version = r.u16()?;
+
+    let (source_name, vendor_name) = match source {
+        1 => (
+            "Bluetooth SIG".to_owned(),
+            company_ids::get_company_name_from_id(vendor_id as usize),
+        ),
+        2 => ("USB Implementer's Forum".to_owned(), None),
+        n => (format!("Reserved ({})", n), None),
+    };
+
+    let vendor = match vendor_name {
+        Some(name) => format!("{:#06x} ({})", vendor_id, name),
+        None => format!("{:#06x}", vendor_id),
+    };
+
+    Ok(format!(
+        "vendor_id_source={}, vendor_id={}, product_id={:#06x}, product_version={:#06x}",
+        source_name, vendor, product_id, product_version
+    ))
+}
+
+/// Name of an appearance category (the upper 10 bits of the appearance)
+fn appearance_category(category: u16) -> Option<&'static str> {
+    Some(match category {
+        0x00 => "Unknown",
+        0x01 => "Phone",
+        0x02 => "Computer",
+        0x03 => "Watch",
+        0x04 => "Clock",
+        0x05 => "Display",
+        0x06 => "Remote Control",
+        0x07 => "Eye-glasses",
+        0x08 => "Tag",
+        0x09 => "Keyring",
+        0x0a => "Media Player",
+        0x0b => "Barcode Scanner",
+        0x0c => "Thermometer",
+        0x0d => "Heart Rate Sensor",
+        0x0e => "Blood Pressure",
+        0x0f => "Human Interface Device",
+        0x10 => "Glucose Meter",
+        0x11 => "Running Walking Sensor",
+        0x12 => "Cycling",
+        0x13 => "Control Device",
+        0x14 => "Network Device",
+        0x15 => "Sensor",
+        0x16 => "Light Fixtures",
+        0x17 => "Fan",
+        0x18 => "HVAC",
+        0x19 => "Air Conditioning",
+        0x1a => "Humidifier",
+        0x1b => "Heating",
+        0x1c => "Access Control",
+        0x1d => "Motorized Device",
+        0x1e => "Power Device",
+        0x1f => "Light Source",
+        0x20 => "Window Covering",
+        0x21 => "Audio Sink",
+        0x22 => "Audio Source",
+        0x23 => "Motorized Vehicle",
+        0x24 => "Domestic Appliance",
+        0x25 => "Wearable Audio Device",
+        0x26 => "Aircraft",
+        0x27 => "AV Equipment",
+        0x28 => "Display Equipment",
+        0x29 => "Hearing aid",
+        0x2a => "Gaming",
+        0x2b => "Signage",
+        // 0x2c to 0x30 aren't assigned
+        0x31 => "Pulse Oximeter",
+        0x32 => "Weight Scale",
+        0x33 => "Personal Mobility Device",
+        0x34 => "Continuous Glucose Monitor",
+        0x35 => "Insulin Pump",
+        0x36 => "Medication Delivery",
+        0x37 => "Spirometer",
+        0x51 => "Outdoor Sports Activity",
+        _ => return None,
+    })
+}
+
+fn decode_appearance(bytes: &[u8]) -> Result<String, String> {
+    let appearance = Reader::new(bytes).u16()?;
+    let category = appearance >> 6;
+    let subcategory = appearance & 0x3f;
+
+    let name = match appearance_category(category) {
+        Some(name) if subcategory == 0 => name.to_string(),
+        Some(name) => format!("{}, subcategory {}", name, subcategory),
+        None => format!("Category {}, subcategory {}", category, subcategory),
+    };
+
+    Ok(format!("{} ({:#06x})", name, appearance))
+}
+
+pub fn get_characteristic_decoder(uuid: &str) -> Option<Decoder> {
+    DECODERS
+        .iter()
+        .find(|(short_uuid, _)| compare_uuid(&uuid.to_uppercase(), short_uuid))
+        .map(|(_, decoder)| *decoder)
+}
+
+/// Formatter decoding the values of a standard characteristic, the hex value is printed if
+/// decoding fails
+pub fn get_characteristic_formatter(uuid: &str) -> Option<print_bytes::Formatter> {
+    let decoder = get_characteristic_decoder(uuid)?;
+
+    Some(Arc::new(move |bytes: &[u8]| match decoder(bytes) {
+        Ok(text) => text,
+        Err(e) => format!(
+            "{} (decode error: {})",
+            print_bytes::bytes_to_str(&bytes.to_vec(), "hex"),
+            e
+        ),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(uuid: &str, bytes: &[u8]) -> String {
+        get_characteristic_decoder(uuid).unwrap()(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_ieee11073() {
+        assert_eq!(ieee11073_float(0xff00_016c), "36.4");
+        assert_eq!(ieee11073_float(0x00ff_fffe), "-2");
+        assert_eq!(ieee11073_float(0x007f_ffff), "NaN");
+        assert_eq!(ieee11073_sfloat(0x0078), "120");
+        assert_eq!(ieee11073_sfloat(0xf16c), "36.4");
+        assert_eq!(ieee11073_sfloat(0x07ff), "NaN");
+    }
+
+    #[test]
+    fn test_get_characteristic_decoder() {
+        assert!(get_characteristic_decoder("2a19").is_some());
+        assert!(get_characteristic_decoder("00002a37-0000-1000-8000-00805f9b34fb").is_some());
+        assert!(get_characteristic_decoder("2a24").is_none());
+    }
+
+    #[test]
+    fn test_decoders() {
+        assert_eq!(decode("2A19", &[85]), "85 %");
+        assert_eq!(
+            decode("2A37", &[0x16, 72, 0x00, 0x04, 0x00, 0x02]),
+            "heart_rate=72 bpm, contact=detected, rr_intervals=[1.000 s, 0.500 s]"
+        );
+        assert_eq!(
+            decode("2A37", &[0x09, 0x2c, 0x01, 0x0c, 0x00]),
+            "heart_rate=300 bpm, energy_expended=12 kJ"
+        );
+        assert_eq!(
+            decode(
+                "2A1C",
+                &[0x06, 0x6c, 0x01, 0x00, 0xff, 0xe8, 0x07, 1, 31, 12, 30, 0, 6]
+            ),
+            "temperature=36.4 °C, timestamp=2024-01-31 12:30:00, type=Mouth"
+        );
+        assert_eq!(
+            decode(
+                "2A35",
+                &[0x14, 0x78, 0x00, 0x50, 0x00, 0x5d, 0x00, 0x48, 0x00, 0x05, 0x00]
+            ),
+            "systolic=120 mmHg, diastolic=80 mmHg, mean_arterial_pressure=93 mmHg, pulse_rate=72 bpm, status=[BODY_MOVEMENT, IRREGULAR_PULSE]"
+        );
+        assert_eq!(
+            decode("2A5B", &[0x03, 10, 0, 0, 0, 0x00, 0x04, 5, 0, 0x00, 0x02]),
+            "wheel_revolutions=10, last_wheel_event_time=1.000 s, crank_revolutions=5, last_crank_event_time=0.500 s"
+        );
+        assert_eq!(
+            decode("2A53", &[0x07, 0x80, 0x02, 160, 0x64, 0x00, 0xe8, 0x03, 0, 0]),
+            "speed=2.50 m/s, cadence=160 rpm, stride_length=1.00 m, total_distance=100.0 m, status=running"
+        );
+        assert_eq!(
+            decode("2A08", &[0xe8, 0x07, 2, 29, 23, 59, 1]),
+            "2024-02-29 23:59:01"
+        );
+        assert_eq!(
+            decode("2A50", &[0x01, 0x59, 0x00, 0x34, 0x12, 0x00, 0x01]),
+            "vendor_id_source=Bluetooth SIG, vendor_id=0x0059 (Nordic Semiconductor ASA), product_id=0x1234, product_version=0x0100"
+        );
+        assert_eq!(decode("2A01", &[0x40, 0x03]), "Heart Rate Sensor (0x0340)");
+        assert_eq!(
+            decode("2A01", &[0xc1, 0x00]),
+            "Watch, subcategory 1 (0x00c1)"
+        );
+        assert_eq!(decode("2A01", &[0x40, 0x0c]), "Pulse Oximeter (0x0c40)");
+        assert_eq!(decode("2A01", &[0x80, 0x0c]), "Weight Scale (0x0c80)");
+        assert_eq!(
+            decode("2A01", &[0x00, 0x0b]),
+            "Category 44, subcategory 0 (0x0b00)"
+        );
+        assert_eq!(decode("2A38", &[2]), "Wrist");
+
+        assert!(get_characteristic_decoder("2A37").unwrap()(&[0x01, 72]).is_err());
+    }
+}
@@ -9,3 +9,34 @@ pub fn compare_uuid(uuid_to_check: &str, uuid_ref: &str) -> bool {
 
     false
 }
+
+/// Full lowercase form of an uuid, 16 and 32 bits uuids are expanded with the Bluetooth base
+/// uuid: '2A19' -> '00002a19-0000-1000-8000-00805f9b34fb'
+pub fn normalize_uuid(uuid: &str) -> String {
+    let uuid = uuid.trim().to_lowercase();
+    let uuid = uuid.strip_prefix("0x").unwrap_or(&uuid);
+
+    if uuid.chars().all(|c| c.is_ascii_hexdigit()) && (uuid.len() == 4 || uuid.len() == 8) {
+        format!("{:0>8}-0000-1000-8000-00805f9b34fb", uuid)
+    } else {
+        uuid.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_uuid() {
+        let full = "00002a19-0000-1000-8000-00805f9b34fb";
+        assert_eq!(normalize_uuid("2A19"), full);
+        assert_eq!(normalize_uuid("0x2a19"), full);
+        assert_eq!(normalize_uuid("00002A19"), full);
+        assert_eq!(normalize_uuid("00002A19-0000-1000-8000-00805F9B34FB"), full);
+        assert_eq!(
+            normalize_uuid("8E72BBE5-F777-5284-7849-B4A0B2AC70D2"),
+            "8e72bbe5-f777-5284-7849-b4a0b2ac70d2"
+        );
+    }
+}
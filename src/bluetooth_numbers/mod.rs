@@ -1,5 +1,6 @@
+pub mod characteristic_decoders;
 pub mod characteristic_uuids;
 pub mod company_ids;
-mod compare_uuid;
+pub mod compare_uuid;
 // pub mod descriptor_uuids; // Not needed for now
 pub mod services_uuids;
@@ -0,0 +1,74 @@
+use serde::Serialize;
+use std::path;
+
+use bluerepl::config::Config;
+use bluerepl::preset::Preset;
+
+/// Result of the validation of a preset file
+#[derive(Serialize, Debug)]
+struct Diagnostic {
+    file: String,
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn check_file(config: &Config, fp: &path::Path) -> Diagnostic {
+    let result = Preset::load(config.find_preset(fp));
+
+    Diagnostic {
+        file: fp.to_string_lossy().into_owned(),
+        valid: result.is_ok(),
+        error: result.err(),
+    }
+}
+
+/// Validate preset files and print a diagnostic for each of them as text or json, return the
+/// exit code of the program
+pub fn run(config: &Config, files: &[path::PathBuf], format: &str) -> i32 {
+    let diagnostics: Vec<Diagnostic> = files.iter().map(|fp| check_file(config, fp)).collect();
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&diagnostics).unwrap());
+    } else {
+        for d in &diagnostics {
+            match &d.error {
+                None => println!("{}: OK", d.file),
+                Some(e) => println!("{}: error: {}", d.file, e),
+            }
+        }
+    }
+
+    if diagnostics.iter().all(|d| d.valid) {
+        exitcode::OK
+    } else {
+        exitcode::DATAERR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let config = Config::default();
+
+        let d = check_file(&config, path::Path::new("presets/battery.toml"));
+        assert!(d.valid);
+        assert!(d.error.is_none());
+
+        let d = check_file(&config, path::Path::new("presets/missing.toml"));
+        assert!(!d.valid);
+        assert!(d.error.unwrap().contains("Could not read file"));
+
+        let files = [path::PathBuf::from("presets/nus.toml")];
+        assert_eq!(run(&config, &files, "json"), exitcode::OK);
+
+        let files = [
+            path::PathBuf::from("presets/nus.toml"),
+            path::PathBuf::from("presets/missing.toml"),
+        ];
+        assert_eq!(run(&config, &files, "text"), exitcode::DATAERR);
+    }
+}
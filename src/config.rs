@@ -0,0 +1,174 @@
+use serde::Deserialize;
+
+use crate::controllers::gatt_cache;
+
+use std::{fs, path};
+
+/// Formats available to print values read from characteristics
+pub const FORMATS: [&str; 5] = ["bin", "hex", "dec", "text", "hexdump"];
+
+/// Global configuration, loaded from ~/.config/bluerepl/config.toml by default
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// File where the repl history is saved
+    pub history_file: path::PathBuf,
+
+    /// Scan duration in seconds when none is specified
+    pub scan_timeout: usize,
+
+    /// Format used to print values when none is specified
+    pub format: String,
+
+    /// Ble lib to use
+    pub backend: String,
+
+    /// Index, name or beginning of the name of the BLE adapter to use
+    pub adapter: Option<String>,
+
+    /// Prompt of the repl
+    pub prompt: String,
+
+    /// Enable colors in the repl
+    pub color: bool,
+
+    /// Directories where presets are searched when their path is not found
+    pub preset_dirs: Vec<path::PathBuf>,
+
+    /// Commands run when the repl starts
+    pub startup_commands: Vec<String>,
+
+    /// Save the gatt of the peripherals on disk to reuse it when reconnecting and offline
+    pub gatt_cache: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            history_file: path::PathBuf::from(".bluerepl_history.txt"),
+            scan_timeout: 5,
+            format: "hex".to_owned(),
+            backend: "btleplug".to_owned(),
+            adapter: None,
+            prompt: ">> ".to_owned(),
+            color: true,
+            preset_dirs: Vec::new(),
+            startup_commands: Vec::new(),
+            gatt_cache: true,
+        }
+    }
+}
+
+/// Replace a leading ~ with the home directory
+fn expand_home(fp: &path::Path) -> path::PathBuf {
+    if let Ok(rest) = fp.strip_prefix("~") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    fp.to_path_buf()
+}
+
+impl Config {
+    pub fn default_path() -> Option<path::PathBuf> {
+        dirs::home_dir().map(|home| home.join(".config/bluerepl/config.toml"))
+    }
+
+    fn parse(contents: &str) -> Result<Config, String> {
+        let mut config: Config = toml::from_str(contents).map_err(|e| e.to_string())?;
+
+        if !FORMATS.contains(&config.format.as_str()) {
+            return Err(format!(
+                "invalid format: '{}'. It must be one of {:?}",
+                config.format, FORMATS
+            ));
+        }
+
+        config.history_file = expand_home(&config.history_file);
+        config.preset_dirs = config.preset_dirs.iter().map(|d| expand_home(d)).collect();
+
+        Ok(config)
+    }
+
+    /// Load the configuration file, the file given with --config must exist but the default
+    /// one is optional
+    pub fn load(fp: Option<path::PathBuf>) -> Result<Config, String> {
+        let fp = match fp {
+            Some(fp) => fp,
+            None => match Self::default_path() {
+                Some(fp) if fp.exists() => fp,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let contents = fs::read_to_string(&fp)
+            .map_err(|_| format!("Could not read file: '{}'", fp.to_string_lossy()))?;
+
+        Self::parse(&contents).map_err(|e| {
+            format!(
+                "Unable to load config from: {:?}: {}",
+                fp.to_string_lossy(),
+                e
+            )
+        })
+    }
+
+    /// Directory of the gatt cache, None if it is disabled
+    pub fn gatt_cache_dir(&self) -> Option<path::PathBuf> {
+        if self.gatt_cache {
+            gatt_cache::default_dir()
+        } else {
+            None
+        }
+    }
+
+    /// Look for a preset in the current directory then in the preset directories
+    pub fn find_preset(&self, fp: &path::Path) -> path::PathBuf {
+        if fp.exists() || fp.is_absolute() {
+            return fp.to_path_buf();
+        }
+
+        self.preset_dirs
+            .iter()
+            .map(|d| d.join(fp))
+            .find(|p| p.exists())
+            .unwrap_or_else(|| fp.to_path_buf())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.scan_timeout, 5);
+        assert_eq!(config.format, "hex");
+        assert_eq!(config.prompt, ">> ");
+
+        let config = Config::parse(
+            r#"
+            scan_timeout = 2
+            format = "text"
+            color = false
+            gatt_cache = false
+            preset_dirs = ["presets"]
+            startup_commands = ["scan 1", "scan -l"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.scan_timeout, 2);
+        assert_eq!(config.format, "text");
+        assert!(!config.color);
+        assert!(config.gatt_cache_dir().is_none());
+        assert_eq!(config.startup_commands.len(), 2);
+        assert_eq!(
+            config.find_preset(path::Path::new("battery.toml")),
+            path::PathBuf::from("presets/battery.toml")
+        );
+
+        assert!(Config::parse("format = \"octal\"").is_err());
+        assert!(Config::parse("unknown_field = 1").is_err());
+    }
+}
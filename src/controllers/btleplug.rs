@@ -1,22 +1,26 @@
 use super::{
     BleController, BlePeripheral, BlePeripheralInfo, Characteristic, CharacteristicProperties,
-    Service,
+    Descriptor, NotificationPrinter, Service,
 };
 
 use async_trait::async_trait;
 use futures::executor::block_on;
 use futures::stream::StreamExt;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
+use tokio::sync::broadcast;
 use tokio::time;
 
+use super::gatt_cache::{self, CachedGatt};
+use crate::bluetooth_numbers::compare_uuid::normalize_uuid;
 use crate::utils;
 
 use btleplug::api::{Central, Manager as _, Peripheral, ScanFilter};
 use btleplug::platform::{Adapter, Manager};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::atomic;
 use std::sync::{Arc, Mutex};
 
@@ -25,13 +29,37 @@ pub struct BtleplugController {
     scan_list: Vec<BlePeripheral>,
     peripheral: Option<Box<btleplug::platform::Peripheral>>,
     notifications_thread_running: Arc<atomic::AtomicBool>,
-    notifications_formats: Arc<Mutex<HashMap<String, String>>>,
+    notifications_printers: Arc<Mutex<HashMap<String, Option<NotificationPrinter>>>>,
+    notifications_sender: broadcast::Sender<(String, Vec<u8>)>,
+
+    /// Notifications received since the first write following the last wait, the response to a
+    /// request can arrive before it is waited for
+    notifications_receiver: Option<broadcast::Receiver<(String, Vec<u8>)>>,
+    /// Notifications received while waiting for another characteristic
+    notifications_pending: VecDeque<(String, Vec<u8>)>,
+    notifications_waited: bool,
+    gatt_cache: Option<GattCache>,
+    gatt_changed: Arc<atomic::AtomicBool>,
+    gatt_cache_dir: Option<PathBuf>,
+    stored_gatt: Option<CachedGatt>,
 }
 
+/// Characteristics of the connected peripheral by normalised service and characteristic uuids
+type GattCache = HashMap<(String, String), btleplug::api::Characteristic>;
+
+/// Notifications kept for wait_notification
+const NOTIFICATIONS_CAPACITY: usize = 256;
+
+/// Characteristic indicating that the gatt of the peripheral changed
+const SERVICE_CHANGED_UUID: &str = "00002a05-0000-1000-8000-00805f9b34fb";
+
+/// Characteristic whose value changes with the gatt of the peripheral
+const DATABASE_HASH_UUID: &str = "00002b2a-0000-1000-8000-00805f9b34fb";
+
 #[async_trait]
 impl BleController for BtleplugController {
     async fn scan(&mut self, scan_time_s: usize) -> Result<(), Box<dyn Error>> {
-        println!("Scanning for {} seconds...", scan_time_s);
+        eprintln!("Scanning for {} seconds...", scan_time_s);
 
         // stop previous scan, return values doesn't matter
         let _ = self.adapter.stop_scan().await;
@@ -60,6 +88,8 @@ impl BleController for BtleplugController {
                 rssi,
                 id: index,
                 company_id: company_code,
+                services: properties.services.iter().map(|u| u.to_string()).collect(),
+                manufacturer_data: properties.manufacturer_data,
             });
         }
         self.scan_list = periph_vec;
@@ -77,21 +107,16 @@ impl BleController for BtleplugController {
 
     async fn write(
         &mut self,
-        _service: &str,
+        service: &str,
         characteristic: &str,
         payload: &[u8],
         response: bool,
     ) -> Result<(), Box<dyn Error>> {
-        if let Some(p) = &self.peripheral {
-            p.discover_services().await.unwrap();
-
-            let c = p
-                .characteristics()
-                .into_iter()
-                .find(|c| c.uuid.to_string() == characteristic);
+        let c = self.get_characteristic(service, characteristic).await?;
+        self.record_notifications();
 
+        if let Some(p) = &self.peripheral {
             if let Some(c) = c {
-                println!("Writing {:?} to characteristic {}", payload, c.uuid);
                 p.write(
                     &c,
                     payload,
@@ -114,19 +139,14 @@ impl BleController for BtleplugController {
 
     async fn read(
         &mut self,
-        _service: &str,
+        service: &str,
         characteristic: &str,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        if let Some(p) = &self.peripheral {
-            p.discover_services().await.unwrap();
-
-            let c = p
-                .characteristics()
-                .into_iter()
-                .find(|c| c.uuid.to_string() == characteristic);
+        let c = self.get_characteristic(service, characteristic).await?;
 
+        if let Some(p) = &self.peripheral {
             if let Some(c) = c {
-                println!("Reading characteristic {} ...", c.uuid);
+                eprintln!("Reading characteristic {} ...", c.uuid);
                 let content = p.read(&c).await?;
                 return Ok(content);
             } else {
@@ -137,18 +157,53 @@ impl BleController for BtleplugController {
         }
     }
 
+    async fn read_descriptor(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        descriptor: &str,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let c = self.get_characteristic(service, characteristic).await?;
+
+        let Some(p) = &self.peripheral else {
+            Err("You must be connected to read")?
+        };
+        let Some(c) = c else {
+            Err(format!("Characteristic: {} not found", characteristic))?
+        };
+        let descriptor = normalize_uuid(descriptor);
+        match c
+            .descriptors
+            .iter()
+            .find(|d| normalize_uuid(&d.uuid.to_string()) == descriptor)
+        {
+            Some(d) => Ok(p.read_descriptor(d).await?),
+            None => Err(format!("Descriptor: {} not found", descriptor))?,
+        }
+    }
+
+    async fn write_handle(
+        &mut self,
+        _handle: u16,
+        _payload: &[u8],
+        _response: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        Err("Addressing attributes by handle isn't supported by btleplug")?
+    }
+
+    async fn read_handle(&mut self, _handle: u16) -> Result<Vec<u8>, Box<dyn Error>> {
+        Err("Addressing attributes by handle isn't supported by btleplug")?
+    }
+
     async fn notify(
         &mut self,
-        _service: &str,
+        service: &str,
         characteristic: &str,
-        format: &str,
+        printer: Option<NotificationPrinter>,
     ) -> Result<(), Box<dyn Error>> {
-        if let Some(p) = &self.peripheral {
-            let c = p
-                .characteristics()
-                .into_iter()
-                .find(|c| c.uuid.to_string() == characteristic);
+        let c = self.get_characteristic(service, characteristic).await?;
 
+        if let Some(p) = &self.peripheral {
             if let Some(c) = c {
                 if !c.properties.contains(btleplug::api::CharPropFlags::NOTIFY) {
                     Err(format!(
@@ -156,16 +211,19 @@ impl BleController for BtleplugController {
                         c.uuid
                     ))?;
                 }
-                println!("Subscribing to characteristic {} notifications ...", c.uuid);
+                if self.replace_printer(&c, printer.clone()) {
+                    return Ok(());
+                }
+                eprintln!("Subscribing to characteristic {} notifications ...", c.uuid);
 
                 p.subscribe(&c).await?;
 
-                let ntf = self.notifications_formats.clone();
-                ntf.lock()
+                self.notifications_printers
+                    .lock()
                     .unwrap()
-                    .insert(characteristic.to_owned(), format.to_owned());
+                    .insert(c.uuid.to_string(), printer);
 
-                println!("OK");
+                eprintln!("OK");
                 Ok(())
             } else {
                 Err(format!("Characteristic {} not found", characteristic))?
@@ -177,16 +235,13 @@ impl BleController for BtleplugController {
 
     async fn indicate(
         &mut self,
-        _service: &str,
+        service: &str,
         characteristic: &str,
-        format: &str,
+        printer: Option<NotificationPrinter>,
     ) -> Result<(), Box<dyn Error>> {
-        if let Some(p) = &self.peripheral {
-            let c = p
-                .characteristics()
-                .into_iter()
-                .find(|c| c.uuid.to_string() == characteristic);
+        let c = self.get_characteristic(service, characteristic).await?;
 
+        if let Some(p) = &self.peripheral {
             if let Some(c) = c {
                 if !c
                     .properties
@@ -197,14 +252,17 @@ impl BleController for BtleplugController {
                         c.uuid
                     ))?;
                 }
-                println!("Subscribing to characteristic {} indications ...", c.uuid);
+                if self.replace_printer(&c, printer.clone()) {
+                    return Ok(());
+                }
+                eprintln!("Subscribing to characteristic {} indications ...", c.uuid);
 
                 p.subscribe(&c).await?;
-                let ntf = self.notifications_formats.clone();
-                ntf.lock()
+                self.notifications_printers
+                    .lock()
                     .unwrap()
-                    .insert(characteristic.to_owned(), format.to_owned());
-                println!("OK");
+                    .insert(c.uuid.to_string(), printer);
+                eprintln!("OK");
                 Ok(())
             } else {
                 Err(format!("Characteristic {} not found", characteristic))?
@@ -216,15 +274,12 @@ impl BleController for BtleplugController {
 
     async fn unsubscribe(
         &mut self,
-        _service: &str,
+        service: &str,
         characteristic: &str,
     ) -> Result<(), Box<dyn Error>> {
-        if let Some(p) = &self.peripheral {
-            let c = p
-                .characteristics()
-                .into_iter()
-                .find(|c| c.uuid.to_string() == characteristic);
+        let c = self.get_characteristic(service, characteristic).await?;
 
+        if let Some(p) = &self.peripheral {
             if let Some(c) = c {
                 if !c.properties.contains(btleplug::api::CharPropFlags::NOTIFY)
                     && !c
@@ -236,14 +291,14 @@ impl BleController for BtleplugController {
                         c.uuid
                     ))?;
                 }
-                println!(
+                eprintln!(
                     "Unsubscribing from characteristic {} notifications ...",
                     c.uuid
                 );
                 p.unsubscribe(&c).await?;
-                let ntf = self.notifications_formats.clone();
-                ntf.lock().unwrap().remove(characteristic);
-                println!("OK");
+                let ntf = self.notifications_printers.clone();
+                ntf.lock().unwrap().remove(&c.uuid.to_string());
+                eprintln!("OK");
                 Ok(())
             } else {
                 Err(format!("Characteristic {} not found", characteristic))?
@@ -253,66 +308,77 @@ impl BleController for BtleplugController {
         }
     }
 
-    async fn get_peripheral_infos(&self) -> Result<BlePeripheralInfo, Box<dyn Error>> {
-        if let Some(p) = &self.peripheral {
-            p.discover_services().await.unwrap();
-
-            let services = p.services();
-            let properties = p.properties().await?.unwrap();
-
-            let mut infos = BlePeripheralInfo {
-                periph_name: properties
-                    .local_name
-                    .unwrap_or_else(|| String::from("unknown")),
-                periph_mac: self.get_address_or_uuid(p).await?,
-                rssi: properties.rssi.unwrap_or(0),
-                services: Vec::new(),
-            };
-
-            for s in services {
-                let mut ser = Service {
-                    uuid: s.uuid.to_string(),
-                    characteriscics: Vec::new(),
-                };
-
-                for c in s.characteristics {
-                    let mut car = Characteristic {
-                        uuid: c.uuid.to_string(),
-                        properties: CharacteristicProperties::UNKNOWN,
-                    };
+    async fn wait_notification(
+        &mut self,
+        _service: &str,
+        characteristic: &str,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.peripheral.is_none() {
+            Err("You must be connected to wait for a notification")?
+        }
+        let characteristic = normalize_uuid(characteristic);
+
+        if !self
+            .notifications_printers
+            .lock()
+            .unwrap()
+            .contains_key(&characteristic)
+        {
+            Err(format!(
+                "Not subscribed to characteristic {} notifications",
+                characteristic
+            ))?
+        }
 
-                    if c.properties.contains(btleplug::api::CharPropFlags::WRITE) {
-                        car.properties |= CharacteristicProperties::WRITE;
-                    }
+        self.notifications_waited = true;
+        let pending = &mut self.notifications_pending;
+        if let Some(index) = pending.iter().position(|(uuid, _)| *uuid == characteristic) {
+            return Ok(pending.remove(index).unwrap().1);
+        }
 
-                    if c.properties.contains(btleplug::api::CharPropFlags::READ) {
-                        car.properties |= CharacteristicProperties::READ;
-                    }
+        let sender = &self.notifications_sender;
+        let receiver = self
+            .notifications_receiver
+            .get_or_insert_with(|| sender.subscribe());
 
-                    if c.properties
-                        .contains(btleplug::api::CharPropFlags::WRITE_WITHOUT_RESPONSE)
-                    {
-                        car.properties |= CharacteristicProperties::WRITE_WITHOUT_RESPONSE;
+        let wait = async {
+            loop {
+                match receiver.recv().await {
+                    Ok((uuid, value)) if uuid == characteristic => {
+                        return Ok::<Vec<u8>, broadcast::error::RecvError>(value)
                     }
-
-                    if c.properties.contains(btleplug::api::CharPropFlags::NOTIFY) {
-                        car.properties |= CharacteristicProperties::NOTIFY;
+                    Ok(notification) => {
+                        if pending.len() == NOTIFICATIONS_CAPACITY {
+                            pending.pop_front();
+                        }
+                        pending.push_back(notification);
                     }
-
-                    if c.properties
-                        .contains(btleplug::api::CharPropFlags::INDICATE)
-                    {
-                        car.properties |= CharacteristicProperties::INDICATE;
-                    }
-
-                    ser.characteriscics.push(car);
+                    Err(broadcast::error::RecvError::Lagged(_)) => (),
+                    Err(e) => return Err(e),
                 }
-                infos.services.push(ser);
             }
-            Ok(infos)
-        } else {
+        };
+
+        match time::timeout(timeout, wait).await {
+            Ok(value) => Ok(value?),
+            Err(_) => Err(format!(
+                "No notification from characteristic {} after {} ms",
+                characteristic,
+                timeout.as_millis()
+            ))?,
+        }
+    }
+
+    async fn get_peripheral_infos(&mut self) -> Result<BlePeripheralInfo, Box<dyn Error>> {
+        if self.peripheral.is_none() {
             Err("You must be connected to get peripheral infos")?
         }
+
+        if self.gatt_cache.is_none() || self.gatt_changed() {
+            self.discover_gatt().await?;
+        }
+        self.discovered_infos().await
     }
 
     async fn connect(&mut self, uuid: &str) -> Result<(), Box<dyn Error>> {
@@ -323,13 +389,24 @@ impl BleController for BtleplugController {
                 .unwrap_or_else(|| String::from("unknown"));
 
             if uuid == self.get_address_or_uuid(p).await? {
-                println!(
+                eprintln!(
                     "Connecting to {} with uuid: {}",
                     name,
                     self.get_address_or_uuid(p).await?
                 );
                 p.connect().await?;
                 self.peripheral = Some(Box::new(p.clone()));
+                self.reset_notifications();
+                self.gatt_cache = None;
+                self.gatt_changed.store(false, atomic::Ordering::Relaxed);
+
+                // btleplug only reads and writes the characteristics it discovered, so the gatt
+                // is always discovered, the stored one tells if it changed since it was saved
+                self.stored_gatt = self
+                    .gatt_cache_dir
+                    .as_ref()
+                    .and_then(|dir| gatt_cache::load(dir, uuid));
+                self.discover_gatt().await?;
                 self.notifications_thread_running
                     .store(true, atomic::Ordering::Relaxed);
                 self.start_notifications_thread().await?;
@@ -346,7 +423,7 @@ impl BleController for BtleplugController {
             let name = properties
                 .local_name
                 .unwrap_or_else(|| String::from("unknown"));
-            println!(
+            eprintln!(
                 "Disconnecting from {} with uuid: {} ... ",
                 name,
                 self.get_address_or_uuid(p).await?
@@ -358,51 +435,120 @@ impl BleController for BtleplugController {
             Err("You must be connected to disconnect")?
         }
         self.peripheral = None;
+        self.reset_notifications();
+        self.gatt_cache = None;
+        self.stored_gatt = None;
         Ok(())
     }
 
+    async fn refresh_gatt(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.peripheral.is_none() {
+            Err("You must be connected to refresh the gatt")?
+        }
+        eprintln!("Discovering services ...");
+        self.discover_gatt().await
+    }
+
+    // btleplug doesn't expose the MTU, the platforms negotiate it themselves
+    async fn get_mtu(&mut self) -> Result<Option<u16>, Box<dyn Error>> {
+        if self.peripheral.is_none() {
+            Err("You must be connected to get the MTU")?
+        }
+        Ok(None)
+    }
+
+    async fn request_mtu(&mut self, _mtu: u16) -> Result<u16, Box<dyn Error>> {
+        Err("Requesting an MTU isn't supported by btleplug")?
+    }
+
     fn is_connected(&self) -> bool {
         self.peripheral.is_some()
     }
 }
 
 impl BtleplugController {
-    pub async fn new() -> BtleplugController {
-        let manager = match Manager::new().await {
-            Ok(m) => m,
-            Err(e) => panic!("{:?}", e),
-        };
+    /// Replace the printer of a characteristic already subscribed to, false if it isn't
+    fn replace_printer(
+        &self,
+        c: &btleplug::api::Characteristic,
+        printer: Option<NotificationPrinter>,
+    ) -> bool {
+        match self
+            .notifications_printers
+            .lock()
+            .unwrap()
+            .get_mut(&c.uuid.to_string())
+        {
+            Some(current) => {
+                *current = printer;
+                true
+            }
+            None => false,
+        }
+    }
 
-        let adapter_list = match manager.adapters().await {
-            Ok(v) => v,
-            Err(e) => panic!("{:?}", e),
-        };
+    /// Keep the notifications received from now on so that a wait doesn't miss the response to
+    /// a write, the ones of earlier writes are kept until a wait happened
+    fn record_notifications(&mut self) {
+        if self.notifications_receiver.is_none() || self.notifications_waited {
+            self.notifications_receiver = Some(self.notifications_sender.subscribe());
+            self.notifications_pending.clear();
+            self.notifications_waited = false;
+        }
+    }
 
-        let adapter = match adapter_list.len() {
-            0 => panic!("No adapter available"),
-            1 => &adapter_list[0],
-            _ => {
-                println!("Found multiple adapters, select the one to use:");
+    fn reset_notifications(&mut self) {
+        self.notifications_printers.lock().unwrap().clear();
+        self.notifications_receiver = None;
+        self.notifications_pending.clear();
+        self.notifications_waited = false;
+    }
+
+    /// The gatt of the peripherals is saved in gatt_cache_dir when it is given
+    pub async fn new(
+        adapter_name: Option<&str>,
+        gatt_cache_dir: Option<PathBuf>,
+    ) -> Result<BtleplugController, Box<dyn Error>> {
+        let manager = Manager::new().await?;
+        let adapter_list = manager.adapters().await?;
+
+        let adapter = match (adapter_list.len(), adapter_name) {
+            (0, _) => Err("No adapter available")?,
+            (_, Some(adapter_name)) => {
+                let mut infos = Vec::new();
+                for ad in &adapter_list {
+                    infos.push(ad.adapter_info().await.unwrap_or_default());
+                }
+                &adapter_list[select_adapter(adapter_name, &infos)?]
+            }
+            (1, None) => &adapter_list[0],
+            (_, None) => {
+                eprintln!("Found multiple adapters, select the one to use:");
                 for (index, ad) in adapter_list.iter().enumerate() {
-                    println!("[{}]: {:?}", index, ad);
+                    eprintln!("[{}]: {:?}", index, ad);
                 }
                 let n = utils::get_usize_input(">>");
                 &adapter_list[n]
             }
         };
 
-        println!(
-            "Using BLE adapter: {:?}",
-            adapter.adapter_info().await.unwrap()
-        );
+        eprintln!("Using BLE adapter: {:?}", adapter.adapter_info().await?);
 
-        BtleplugController {
+        Ok(BtleplugController {
             adapter: adapter.clone(),
             scan_list: Vec::new(),
             peripheral: None,
             notifications_thread_running: Arc::new(atomic::AtomicBool::new(false)),
-            notifications_formats: Arc::new(Mutex::new(HashMap::new())),
-        }
+            notifications_printers: Arc::new(Mutex::new(HashMap::new())),
+            notifications_sender: broadcast::channel(NOTIFICATIONS_CAPACITY).0,
+            notifications_receiver: None,
+            notifications_pending: VecDeque::new(),
+            notifications_waited: false,
+            gatt_cache: None,
+            gatt_changed: Arc::new(atomic::AtomicBool::new(false)),
+            gatt_cache_dir,
+            stored_gatt: None,
+        })
     }
 
     async fn get_address_or_uuid(
@@ -418,26 +564,198 @@ impl BtleplugController {
         }
     }
 
+    /// Discover the services of the connected peripheral and fill the gatt cache, subscribe to
+    /// the Service Changed characteristic to know when the cache must be discovered again. The
+    /// gatt is also saved on disk with the Database Hash of the peripheral
+    async fn discover_gatt(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(p) = &self.peripheral else {
+            return Ok(());
+        };
+        p.discover_services().await?;
+
+        let cache: GattCache = p
+            .characteristics()
+            .into_iter()
+            .map(|c| {
+                let key = (
+                    normalize_uuid(&c.service_uuid.to_string()),
+                    normalize_uuid(&c.uuid.to_string()),
+                );
+                (key, c)
+            })
+            .collect();
+
+        if let Some(c) = cache.values().find(|c| {
+            c.uuid.to_string() == SERVICE_CHANGED_UUID
+                && c.properties
+                    .contains(btleplug::api::CharPropFlags::INDICATE)
+        }) {
+            // some platforms handle it themselves and don't allow it
+            let _ = p.subscribe(c).await;
+        }
+
+        let database_hash = match cache.values().find(|c| {
+            c.uuid.to_string() == DATABASE_HASH_UUID
+                && c.properties.contains(btleplug::api::CharPropFlags::READ)
+        }) {
+            Some(c) => p.read(c).await.ok(),
+            None => None,
+        };
+
+        self.gatt_cache = Some(cache);
+        self.gatt_changed.store(false, atomic::Ordering::Relaxed);
+
+        if let Some(dir) = self.gatt_cache_dir.clone() {
+            self.save_gatt(&dir, database_hash).await?;
+        }
+        Ok(())
+    }
+
+    /// Save the discovered gatt in the cache directory, the previous one is replaced
+    async fn save_gatt(
+        &mut self,
+        dir: &Path,
+        database_hash: Option<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(stored) = &self.stored_gatt {
+            if stored.database_hash.is_some() && stored.database_hash != database_hash {
+                eprintln!("The gatt of the peripheral changed since it was cached");
+            }
+        }
+
+        let gatt = CachedGatt::new(&self.discovered_infos().await?, database_hash);
+        if let Err(e) = gatt_cache::save(dir, &gatt) {
+            eprintln!("Could not cache the gatt: {}", e);
+        }
+        self.stored_gatt = Some(gatt);
+        Ok(())
+    }
+
+    /// Infos of the connected peripheral with the services discovered
+    async fn discovered_infos(&self) -> Result<BlePeripheralInfo, Box<dyn Error>> {
+        let Some(p) = &self.peripheral else {
+            Err("You must be connected to get peripheral infos")?
+        };
+        let properties = p.properties().await?.unwrap();
+
+        let mut infos = BlePeripheralInfo {
+            periph_name: properties
+                .local_name
+                .unwrap_or_else(|| String::from("unknown")),
+            periph_mac: self.get_address_or_uuid(p).await?,
+            rssi: properties.rssi.unwrap_or(0),
+            mtu: None,
+            services: Vec::new(),
+        };
+
+        for s in p.services() {
+            // btleplug doesn't expose the ATT handles
+            let mut ser = Service {
+                uuid: s.uuid.to_string(),
+                characteristics: Vec::new(),
+                handles: None,
+            };
+
+            for c in s.characteristics {
+                let mut car = Characteristic {
+                    uuid: c.uuid.to_string(),
+                    properties: CharacteristicProperties::UNKNOWN,
+                    descriptors: c
+                        .descriptors
+                        .iter()
+                        .map(|d| Descriptor {
+                            uuid: d.uuid.to_string(),
+                            handle: None,
+                        })
+                        .collect(),
+                    handle: None,
+                };
+
+                if c.properties.contains(btleplug::api::CharPropFlags::WRITE) {
+                    car.properties |= CharacteristicProperties::WRITE;
+                }
+
+                if c.properties.contains(btleplug::api::CharPropFlags::READ) {
+                    car.properties |= CharacteristicProperties::READ;
+                }
+
+                if c.properties
+                    .contains(btleplug::api::CharPropFlags::WRITE_WITHOUT_RESPONSE)
+                {
+                    car.properties |= CharacteristicProperties::WRITE_WITHOUT_RESPONSE;
+                }
+
+                if c.properties.contains(btleplug::api::CharPropFlags::NOTIFY) {
+                    car.properties |= CharacteristicProperties::NOTIFY;
+                }
+
+                if c.properties
+                    .contains(btleplug::api::CharPropFlags::INDICATE)
+                {
+                    car.properties |= CharacteristicProperties::INDICATE;
+                }
+
+                ser.characteristics.push(car);
+            }
+            infos.services.push(ser);
+        }
+        Ok(infos)
+    }
+
+    fn gatt_changed(&self) -> bool {
+        self.gatt_changed.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Characteristic of the connected peripheral from the gatt cache, it is discovered again if
+    /// the gatt changed
+    async fn get_characteristic(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+    ) -> Result<Option<btleplug::api::Characteristic>, Box<dyn Error>> {
+        if self.peripheral.is_none() {
+            return Ok(None);
+        }
+        if self.gatt_cache.is_none() || self.gatt_changed() {
+            self.discover_gatt().await?;
+        }
+
+        let key = (normalize_uuid(service), normalize_uuid(characteristic));
+        Ok(self
+            .gatt_cache
+            .as_ref()
+            .and_then(|cache| cache.get(&key))
+            .cloned())
+    }
+
     async fn start_notifications_thread(&self) -> Result<(), Box<dyn Error>> {
-        println!("Starting notifications thread");
+        eprintln!("Starting notifications thread");
 
         if let Some(p) = &self.peripheral {
             let mut notification_stream = p.notifications().await?;
             let atomic_is_running = self.notifications_thread_running.clone();
-            let all_formats = self.notifications_formats.clone();
+            let all_printers = self.notifications_printers.clone();
+            let sender = self.notifications_sender.clone();
+            let gatt_changed = self.gatt_changed.clone();
 
             thread::spawn(move || loop {
                 if let Some(data) = block_on(notification_stream.next()) {
-                    let formats_map = all_formats.lock().unwrap();
-                    let fmt = formats_map.get(&data.uuid.to_string()).unwrap();
-                    println!(
-                        "Notification from [{:?}]: {}",
-                        data.uuid,
-                        utils::print_bytes::bytes_to_str(&data.value, fmt)
-                    );
+                    let printers = all_printers.lock().unwrap();
+
+                    if data.uuid.to_string() == SERVICE_CHANGED_UUID {
+                        eprintln!("The gatt of the peripheral changed");
+                        gatt_changed.store(true, atomic::Ordering::Relaxed);
+                    } else if let Some(printer) = printers.get(&data.uuid.to_string()) {
+                        if let Some(print) = printer {
+                            print(&data.uuid.to_string(), &data.value);
+                        }
+
+                        // there are no receivers when nothing is waiting for a notification
+                        let _ = sender.send((data.uuid.to_string(), data.value));
+                    }
                 }
                 if !atomic_is_running.load(atomic::Ordering::Relaxed) {
-                    println!("Stopping notifications thread");
+                    eprintln!("Stopping notifications thread");
                     return;
                 }
                 thread::sleep(Duration::from_millis(1));
@@ -446,3 +764,47 @@ impl BtleplugController {
         Ok(())
     }
 }
+
+/// Index of the adapter to use: a number is an index in the list, anything else is the name of
+/// the adapter or its beginning (e.g. hci0)
+fn select_adapter(name: &str, infos: &[String]) -> Result<usize, String> {
+    let found = match name.parse::<usize>() {
+        Ok(index) => Some(index).filter(|i| *i < infos.len()),
+        Err(_) => infos
+            .iter()
+            .position(|i| i == name)
+            .or_else(|| infos.iter().position(|i| i.starts_with(name))),
+    };
+
+    found.ok_or_else(|| {
+        format!(
+            "Adapter '{}' not found, the adapters available are: {}",
+            name,
+            infos
+                .iter()
+                .enumerate()
+                .map(|(index, i)| format!("[{}] {}", index, i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_adapter() {
+        let infos = vec![
+            "hci0 (usb:v1D6Bp0246d0540)".to_owned(),
+            "hci1 (usb:v0A12p0001d8891)".to_owned(),
+        ];
+        assert_eq!(select_adapter("1", &infos), Ok(1));
+        assert_eq!(select_adapter("hci1", &infos), Ok(1));
+        assert_eq!(select_adapter("hci0 (usb:v1D6Bp0246d0540)", &infos), Ok(0));
+        assert!(select_adapter("2", &infos).is_err());
+        assert!(select_adapter("usb", &infos).is_err());
+        assert!(select_adapter("hci2", &infos[..1]).is_err());
+    }
+}
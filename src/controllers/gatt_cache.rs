@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path};
+
+use super::{BlePeripheralInfo, Service};
+
+/// Gatt of a peripheral saved on disk so it can be used without connecting
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedGatt {
+    pub name: String,
+    pub address: String,
+
+    /// Value of the Database Hash characteristic (0x2B2A) when the peripheral has one, it
+    /// changes with the gatt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_hash: Option<Vec<u8>>,
+
+    pub services: Vec<Service>,
+}
+
+impl CachedGatt {
+    pub fn new(infos: &BlePeripheralInfo, database_hash: Option<Vec<u8>>) -> CachedGatt {
+        CachedGatt {
+            name: infos.periph_name.clone(),
+            address: infos.periph_mac.clone(),
+            database_hash,
+            services: infos.services.clone(),
+        }
+    }
+
+    /// Peripheral infos from the cache, the rssi and the MTU are unknown
+    pub fn to_infos(&self) -> BlePeripheralInfo {
+        BlePeripheralInfo {
+            periph_name: self.name.clone(),
+            periph_mac: self.address.clone(),
+            services: self.services.clone(),
+            rssi: 0,
+            mtu: None,
+        }
+    }
+}
+
+/// Default directory of the gatt cache: ~/.cache/bluerepl/gatt on linux
+pub fn default_dir() -> Option<path::PathBuf> {
+    dirs::cache_dir().map(|d| d.join("bluerepl").join("gatt"))
+}
+
+/// File of a peripheral in the cache, named after its address without separators
+fn file_path(dir: &path::Path, address: &str) -> path::PathBuf {
+    let name: String = address
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    dir.join(format!("{}.json", name.to_lowercase()))
+}
+
+/// Gatt of a peripheral seen before, None if it isn't cached or the file can't be read
+pub fn load(dir: &path::Path, address: &str) -> Option<CachedGatt> {
+    let contents = fs::read_to_string(file_path(dir, address)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+pub fn save(dir: &path::Path, gatt: &CachedGatt) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Could not create {:?}: {}", dir, e))?;
+
+    let fp = file_path(dir, &gatt.address);
+    let contents = serde_json::to_string_pretty(gatt).map_err(|e| e.to_string())?;
+    fs::write(&fp, contents).map_err(|e| format!("Could not write {:?}: {}", fp, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{Characteristic, CharacteristicProperties};
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("bluerepl_gatt_{}", std::process::id()));
+        let infos = BlePeripheralInfo {
+            periph_name: "Thermometer".to_owned(),
+            periph_mac: "AA:BB:CC:DD:EE:FF".to_owned(),
+            rssi: -60,
+            mtu: None,
+            services: vec![Service {
+                uuid: "0000180f-0000-1000-8000-00805f9b34fb".to_owned(),
+                characteristics: vec![Characteristic {
+                    uuid: "00002a19-0000-1000-8000-00805f9b34fb".to_owned(),
+                    properties: CharacteristicProperties::READ | CharacteristicProperties::NOTIFY,
+                    descriptors: vec![],
+                    handle: Some(0x002a),
+                }],
+                handles: Some((0x0028, 0x002c)),
+            }],
+        };
+
+        assert!(load(&dir, "AA:BB:CC:DD:EE:FF").is_none());
+        save(&dir, &CachedGatt::new(&infos, Some(vec![1, 2]))).unwrap();
+
+        let cached = load(&dir, "aa:bb:cc:dd:ee:ff").unwrap();
+        assert_eq!(cached.database_hash, Some(vec![1, 2]));
+
+        let cached = cached.to_infos();
+        assert_eq!(cached.periph_name, "Thermometer");
+        assert_eq!(cached.rssi, 0);
+        assert_eq!(cached.services[0].handles, Some((0x0028, 0x002c)));
+        assert_eq!(
+            cached.services[0].characteristics[0].properties,
+            CharacteristicProperties::READ | CharacteristicProperties::NOTIFY
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
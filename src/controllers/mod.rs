@@ -1,12 +1,18 @@
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 
 use bitflags::bitflags;
 
 pub mod btleplug;
+pub mod gatt_cache;
 pub mod simpleble;
+pub mod simulated;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BlePeripheral {
     /// Internal id used by ble implementations
     pub id: usize,
@@ -22,10 +28,16 @@ pub struct BlePeripheral {
 
     /// company_id
     pub company_id: usize,
+
+    /// UUIDs of the services in the advertising data
+    pub services: Vec<String>,
+
+    /// Manufacturer specific data by company id
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
 }
 
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
     pub struct CharacteristicProperties: u8 {
         const UNKNOWN                = 0b00000000;
         const READ                   = 0b00000001;
@@ -36,22 +48,43 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Descriptor {
+    /// Descriptor UUID
+    pub uuid: String,
+
+    /// ATT handle, None when the backend doesn't report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u16>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Characteristic {
     /// Characteristic UUID
     pub uuid: String,
 
     /// Characteristic properties (READ, WRITE ...)
     pub properties: CharacteristicProperties,
+
+    /// Characteristic descriptors
+    pub descriptors: Vec<Descriptor>,
+
+    /// ATT handle of the value, None when the backend doesn't report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u16>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Service {
     /// Service UUID
     pub uuid: String,
 
     /// Service characteristics
-    pub characteriscics: Vec<Characteristic>,
+    pub characteristics: Vec<Characteristic>,
+
+    /// Range of ATT handles of the service (start, end), None when the backend doesn't report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handles: Option<(u16, u16)>,
 }
 
 #[derive(Debug)]
@@ -67,8 +100,14 @@ pub struct BlePeripheralInfo {
 
     /// Peripheral rssi
     pub rssi: i16,
+
+    /// ATT MTU negotiated with the peripheral, None when the backend doesn't report it
+    pub mtu: Option<u16>,
 }
 
+/// Prints the values notified or indicated by a subscribed characteristic, given its uuid
+pub type NotificationPrinter = Arc<dyn Fn(&str, &[u8]) + Send + Sync>;
+
 #[async_trait]
 pub trait BleController {
     async fn scan(&mut self, scan_time_s: usize) -> Result<(), Box<dyn Error>>;
@@ -77,7 +116,7 @@ pub trait BleController {
 
     async fn get_adapter_infos(&self) -> Result<String, Box<dyn Error>>;
 
-    async fn get_peripheral_infos(&self) -> Result<BlePeripheralInfo, Box<dyn Error>>;
+    async fn get_peripheral_infos(&mut self) -> Result<BlePeripheralInfo, Box<dyn Error>>;
 
     async fn connect(&mut self, uuid: &str) -> Result<(), Box<dyn Error>>;
 
@@ -97,18 +136,34 @@ pub trait BleController {
         characteristic: &str,
     ) -> Result<Vec<u8>, Box<dyn Error>>;
 
+    /// Write to the characteristic value with this ATT handle, it addresses characteristics whose
+    /// uuid isn't unique
+    async fn write_handle(
+        &mut self,
+        handle: u16,
+        payload: &[u8],
+        response: bool,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Read the characteristic value with this ATT handle
+    async fn read_handle(&mut self, handle: u16) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Subscribe to the notifications of a characteristic, they are printed with the printer
+    /// given and can be waited for. Subscribing again only replaces the printer
     async fn notify(
         &mut self,
         service: &str,
         characteristic: &str,
-        format: &str,
+        printer: Option<NotificationPrinter>,
     ) -> Result<(), Box<dyn Error>>;
 
+    /// Subscribe to the indications of a characteristic, they are printed with the printer
+    /// given and can be waited for. Subscribing again only replaces the printer
     async fn indicate(
         &mut self,
         service: &str,
         characteristic: &str,
-        format: &str,
+        printer: Option<NotificationPrinter>,
     ) -> Result<(), Box<dyn Error>>;
 
     async fn unsubscribe(
@@ -117,5 +172,35 @@ pub trait BleController {
         characteristic: &str,
     ) -> Result<(), Box<dyn Error>>;
 
+    /// Value of a descriptor of a characteristic
+    async fn read_descriptor(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        descriptor: &str,
+    ) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Wait for the next value notified or indicated by a subscribed characteristic.
+    ///
+    /// The values are recorded from the first write following the previous wait, so the responses
+    /// to the requests written before waiting aren't missed, even when several requests are
+    /// written before their responses are waited for. The values of other characteristics received
+    /// meanwhile are kept for the next waits. Values received before that write are dropped
+    async fn wait_notification(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Discover the gatt of the connected peripheral again
+    async fn refresh_gatt(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// ATT MTU negotiated with the connected peripheral, None when the backend doesn't report it
+    async fn get_mtu(&mut self) -> Result<Option<u16>, Box<dyn Error>>;
+
+    /// Ask the connected peripheral for a new ATT MTU and return the one negotiated
+    async fn request_mtu(&mut self, mtu: u16) -> Result<u16, Box<dyn Error>>;
+
     fn is_connected(&self) -> bool;
 }
@@ -0,0 +1,550 @@
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::time::Duration;
+
+use super::{
+    BleController, BlePeripheral, BlePeripheralInfo, CharacteristicProperties, NotificationPrinter,
+    Service,
+};
+use crate::bluetooth_numbers::compare_uuid::normalize_uuid;
+
+/// Behaviour of a simulated peripheral, the uuids given are normalised
+pub trait SimulatedDevice: Send + Sync {
+    /// Value of a readable characteristic
+    fn read(&mut self, service: &str, characteristic: &str) -> Result<Vec<u8>, String>;
+
+    /// Handle a write and return the values notified or indicated in response, with the uuid of
+    /// their characteristic
+    fn write(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        value: &[u8],
+    ) -> Result<Vec<(String, Vec<u8>)>, String>;
+
+    /// Value of the readable characteristic with this value handle, to tell apart characteristics
+    /// with the same uuid
+    fn read_handle(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        _handle: u16,
+    ) -> Result<Vec<u8>, String> {
+        self.read(service, characteristic)
+    }
+
+    /// Handle a write to the characteristic with this value handle, like write
+    fn write_handle(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        _handle: u16,
+        value: &[u8],
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        self.write(service, characteristic, value)
+    }
+
+    /// Value of a descriptor, the simulated peripherals have none by default
+    fn read_descriptor(
+        &mut self,
+        _service: &str,
+        characteristic: &str,
+        descriptor: &str,
+    ) -> Result<Vec<u8>, String> {
+        Err(format!(
+            "Descriptor {} of {} not found",
+            descriptor, characteristic
+        ))
+    }
+}
+
+fn property_names(properties: CharacteristicProperties) -> String {
+    properties
+        .iter_names()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Give ATT handles to the attributes without one, numbered like in an attribute table: the
+/// service declaration, then the declaration, the value and the descriptors of each characteristic
+fn assign_handles(services: &mut [Service]) {
+    let mut next: u16 = 1;
+    for s in services {
+        let start = next;
+        for c in &mut s.characteristics {
+            next += 2;
+            c.handle.get_or_insert(next);
+            for d in &mut c.descriptors {
+                next += 1;
+                d.handle.get_or_insert(next);
+            }
+        }
+        s.handles.get_or_insert((start, next));
+        next += 1;
+    }
+}
+
+/// Peripheral of the simulated backend
+pub struct SimulatedPeripheral {
+    pub name: String,
+    pub address: String,
+    pub rssi: i16,
+    pub services: Vec<Service>,
+
+    /// Largest ATT MTU supported, it is the one negotiated when connecting
+    pub mtu: u16,
+
+    pub device: Box<dyn SimulatedDevice>,
+}
+
+/// Backend without adapter whose peripherals are simulated, it runs the commands end-to-end in
+/// tests. Notifications are delivered as soon as the write causing them returns
+pub struct SimulatedController {
+    peripherals: Vec<SimulatedPeripheral>,
+    scan_list: Vec<BlePeripheral>,
+    connected: Option<usize>,
+    mtu: u16,
+    subscriptions: HashMap<String, Option<NotificationPrinter>>,
+    notifications: VecDeque<(String, Vec<u8>)>,
+    notifications_waited: bool,
+}
+
+impl SimulatedController {
+    pub fn new(mut peripherals: Vec<SimulatedPeripheral>) -> SimulatedController {
+        for p in &mut peripherals {
+            assign_handles(&mut p.services);
+        }
+        SimulatedController {
+            peripherals,
+            scan_list: Vec::new(),
+            connected: None,
+            mtu: 23,
+            subscriptions: HashMap::new(),
+            notifications: VecDeque::new(),
+            notifications_waited: false,
+        }
+    }
+
+    fn peripheral(&mut self) -> Result<&mut SimulatedPeripheral, Box<dyn Error>> {
+        match self.connected {
+            Some(index) => Ok(&mut self.peripherals[index]),
+            None => Err("You must be connected to a peripheral")?,
+        }
+    }
+
+    /// Normalised uuids and properties of a characteristic of the connected peripheral
+    fn characteristic(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+    ) -> Result<(String, String, CharacteristicProperties), Box<dyn Error>> {
+        let service = normalize_uuid(service);
+        let characteristic = normalize_uuid(characteristic);
+
+        self.peripheral()?
+            .services
+            .iter()
+            .filter(|s| normalize_uuid(&s.uuid) == service)
+            .flat_map(|s| &s.characteristics)
+            .find(|c| normalize_uuid(&c.uuid) == characteristic)
+            .map(|c| c.properties)
+            .map(|properties| (service, characteristic.clone(), properties))
+            .ok_or_else(|| format!("Characteristic {} not found", characteristic).into())
+    }
+
+    /// Normalised uuids and properties of the characteristic of the connected peripheral whose
+    /// value has this handle
+    fn characteristic_by_handle(
+        &mut self,
+        handle: u16,
+    ) -> Result<(String, String, CharacteristicProperties), Box<dyn Error>> {
+        self.peripheral()?
+            .services
+            .iter()
+            .flat_map(|s| s.characteristics.iter().map(move |c| (s, c)))
+            .find(|(_, c)| c.handle == Some(handle))
+            .map(|(s, c)| {
+                (
+                    normalize_uuid(&s.uuid),
+                    normalize_uuid(&c.uuid),
+                    c.properties,
+                )
+            })
+            .ok_or_else(|| format!("No characteristic value with handle 0x{:04x}", handle).into())
+    }
+
+    /// Write to a characteristic, by its handle when one is given, and keep the values notified
+    /// in response
+    fn write_characteristic(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        properties: CharacteristicProperties,
+        handle: Option<u16>,
+        payload: &[u8],
+        response: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let required = if response {
+            CharacteristicProperties::WRITE
+        } else {
+            CharacteristicProperties::WRITE_WITHOUT_RESPONSE
+        };
+        if !properties.contains(required) {
+            Err(format!(
+                "Characteristic {} doesn't have the {} attribute",
+                characteristic,
+                property_names(required)
+            ))?
+        }
+
+        // like the other backends, the notifications of the writes before the last wait are dropped
+        if self.notifications_waited {
+            self.notifications.clear();
+            self.notifications_waited = false;
+        }
+        let device = &mut self.peripheral()?.device;
+        let notified = match handle {
+            Some(handle) => device.write_handle(service, characteristic, handle, payload)?,
+            None => device.write(service, characteristic, payload)?,
+        };
+
+        for (uuid, value) in notified {
+            let uuid = normalize_uuid(&uuid);
+            if let Some(printer) = self.subscriptions.get(&uuid) {
+                if let Some(print) = printer {
+                    print(&uuid, &value);
+                }
+                self.notifications.push_back((uuid, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a characteristic, by its handle when one is given
+    fn read_characteristic(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        properties: CharacteristicProperties,
+        handle: Option<u16>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if !properties.contains(CharacteristicProperties::READ) {
+            Err(format!(
+                "Characteristic {} doesn't have the READ attribute",
+                characteristic
+            ))?
+        }
+        let device = &mut self.peripheral()?.device;
+        Ok(match handle {
+            Some(handle) => device.read_handle(service, characteristic, handle)?,
+            None => device.read(service, characteristic)?,
+        })
+    }
+
+    fn subscribe(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        property: CharacteristicProperties,
+        printer: Option<NotificationPrinter>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (_, characteristic, properties) = self.characteristic(service, characteristic)?;
+        if !properties.contains(property) {
+            Err(format!(
+                "Characteristic {} doesn't have the {} attribute",
+                characteristic,
+                property_names(property)
+            ))?
+        }
+        self.subscriptions.insert(characteristic, printer);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BleController for SimulatedController {
+    async fn scan(&mut self, _scan_time_s: usize) -> Result<(), Box<dyn Error>> {
+        self.scan_list = self
+            .peripherals
+            .iter()
+            .enumerate()
+            .map(|(index, p)| BlePeripheral {
+                id: index,
+                name: p.name.clone(),
+                address_uuid: p.address.clone(),
+                rssi: p.rssi,
+                company_id: usize::MAX,
+                services: p.services.iter().map(|s| s.uuid.clone()).collect(),
+                manufacturer_data: HashMap::new(),
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn get_scan_list(&self) -> Vec<BlePeripheral> {
+        self.scan_list.clone()
+    }
+
+    async fn get_adapter_infos(&self) -> Result<String, Box<dyn Error>> {
+        Ok("simulated".to_owned())
+    }
+
+    async fn get_peripheral_infos(&mut self) -> Result<BlePeripheralInfo, Box<dyn Error>> {
+        let mtu = self.mtu;
+        let p = self.peripheral()?;
+
+        Ok(BlePeripheralInfo {
+            periph_name: p.name.clone(),
+            periph_mac: p.address.clone(),
+            services: p.services.clone(),
+            rssi: p.rssi,
+            mtu: Some(mtu),
+        })
+    }
+
+    async fn connect(&mut self, uuid: &str) -> Result<(), Box<dyn Error>> {
+        let Some(index) = self.peripherals.iter().position(|p| p.address == uuid) else {
+            Err(format!("Peripheral with uuid {} not found", uuid))?
+        };
+        self.connected = Some(index);
+        self.mtu = self.peripherals[index].mtu;
+        self.subscriptions.clear();
+        self.notifications.clear();
+        self.notifications_waited = false;
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.connected.take().is_none() {
+            Err("You must be connected to disconnect")?
+        }
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        payload: &[u8],
+        response: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let (service, characteristic, properties) = self.characteristic(service, characteristic)?;
+        self.write_characteristic(
+            &service,
+            &characteristic,
+            properties,
+            None,
+            payload,
+            response,
+        )
+    }
+
+    async fn read(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (service, characteristic, properties) = self.characteristic(service, characteristic)?;
+        self.read_characteristic(&service, &characteristic, properties, None)
+    }
+
+    async fn write_handle(
+        &mut self,
+        handle: u16,
+        payload: &[u8],
+        response: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let (service, characteristic, properties) = self.characteristic_by_handle(handle)?;
+        self.write_characteristic(
+            &service,
+            &characteristic,
+            properties,
+            Some(handle),
+            payload,
+            response,
+        )
+    }
+
+    async fn read_handle(&mut self, handle: u16) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (service, characteristic, properties) = self.characteristic_by_handle(handle)?;
+        self.read_characteristic(&service, &characteristic, properties, Some(handle))
+    }
+
+    async fn read_descriptor(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        descriptor: &str,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (service, characteristic, _) = self.characteristic(service, characteristic)?;
+        Ok(self.peripheral()?.device.read_descriptor(
+            &service,
+            &characteristic,
+            &normalize_uuid(descriptor),
+        )?)
+    }
+
+    async fn notify(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        printer: Option<NotificationPrinter>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.subscribe(
+            service,
+            characteristic,
+            CharacteristicProperties::NOTIFY,
+            printer,
+        )
+    }
+
+    async fn indicate(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+        printer: Option<NotificationPrinter>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.subscribe(
+            service,
+            characteristic,
+            CharacteristicProperties::INDICATE,
+            printer,
+        )
+    }
+
+    async fn unsubscribe(
+        &mut self,
+        service: &str,
+        characteristic: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let (_, characteristic, _) = self.characteristic(service, characteristic)?;
+        self.subscriptions.remove(&characteristic);
+        Ok(())
+    }
+
+    /// The notifications are already there, the timeout is only used in the error
+    async fn wait_notification(
+        &mut self,
+        _service: &str,
+        characteristic: &str,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let characteristic = normalize_uuid(characteristic);
+        if !self.subscriptions.contains_key(&characteristic) {
+            Err(format!(
+                "Not subscribed to characteristic {} notifications",
+                characteristic
+            ))?
+        }
+
+        self.notifications_waited = true;
+        match self
+            .notifications
+            .iter()
+            .position(|(uuid, _)| *uuid == characteristic)
+        {
+            Some(index) => Ok(self.notifications.remove(index).unwrap().1),
+            None => Err(format!(
+                "No notification from characteristic {} after {} ms",
+                characteristic,
+                timeout.as_millis()
+            ))?,
+        }
+    }
+
+    async fn refresh_gatt(&mut self) -> Result<(), Box<dyn Error>> {
+        self.peripheral()?;
+        Ok(())
+    }
+
+    async fn get_mtu(&mut self) -> Result<Option<u16>, Box<dyn Error>> {
+        self.peripheral()?;
+        Ok(Some(self.mtu))
+    }
+
+    async fn request_mtu(&mut self, mtu: u16) -> Result<u16, Box<dyn Error>> {
+        let supported = self.peripheral()?.mtu;
+        self.mtu = mtu.min(supported);
+        Ok(self.mtu)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::Characteristic;
+
+    /// Notifies back the values written
+    struct Echo;
+
+    impl SimulatedDevice for Echo {
+        fn read(&mut self, _service: &str, _characteristic: &str) -> Result<Vec<u8>, String> {
+            Ok(Vec::new())
+        }
+
+        fn write(
+            &mut self,
+            _service: &str,
+            characteristic: &str,
+            value: &[u8],
+        ) -> Result<Vec<(String, Vec<u8>)>, String> {
+            Ok(vec![(characteristic.to_owned(), value.to_vec())])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_notification_after_writes() {
+        let mut bt = SimulatedController::new(vec![SimulatedPeripheral {
+            name: "echo".to_owned(),
+            address: "00:00:00:00:00:01".to_owned(),
+            rssi: -40,
+            services: vec![Service {
+                uuid: "180f".to_owned(),
+                characteristics: vec![Characteristic {
+                    uuid: "2a19".to_owned(),
+                    properties: CharacteristicProperties::WRITE | CharacteristicProperties::NOTIFY,
+                    descriptors: Vec::new(),
+                    handle: None,
+                }],
+                handles: None,
+            }],
+            mtu: 23,
+            device: Box::new(Echo),
+        }]);
+        bt.connect("00:00:00:00:00:01").await.unwrap();
+        bt.notify("180F", "2A19", None).await.unwrap();
+        let timeout = Duration::from_millis(10);
+
+        // both responses are kept until they are waited for
+        bt.write("180f", "2a19", &[1], true).await.unwrap();
+        bt.write("180f", "2a19", &[2], true).await.unwrap();
+        assert_eq!(
+            bt.wait_notification("180f", "2a19", timeout).await.unwrap(),
+            [1]
+        );
+        assert_eq!(
+            bt.wait_notification("180f", "2a19", timeout).await.unwrap(),
+            [2]
+        );
+
+        // the values not waited for are dropped by the next write
+        bt.write("180f", "2a19", &[3], true).await.unwrap();
+        bt.write("180f", "2a19", &[4], true).await.unwrap();
+        assert_eq!(
+            bt.wait_notification("180f", "2a19", timeout).await.unwrap(),
+            [3]
+        );
+        bt.write("180f", "2a19", &[5], true).await.unwrap();
+        assert_eq!(
+            bt.wait_notification("180f", "2a19", timeout).await.unwrap(),
+            [5]
+        );
+        assert!(bt.wait_notification("180f", "2a19", timeout).await.is_err());
+    }
+}
@@ -0,0 +1,469 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::bluetooth_numbers::compare_uuid::normalize_uuid;
+use crate::controllers::{BleController, BlePeripheral, BlePeripheralInfo};
+use crate::repl::commands;
+use crate::utils::print_bytes;
+
+mod package;
+
+pub use package::{Image, Package};
+
+/// Secure DFU service and characteristics
+pub const SERVICE_UUID: &str = "0000fe59-0000-1000-8000-00805f9b34fb";
+pub const CONTROL_POINT_UUID: &str = "8ec90001-f315-4f60-9fb8-838830daea50";
+pub const PACKET_UUID: &str = "8ec90002-f315-4f60-9fb8-838830daea50";
+
+/// Buttonless characteristics, without bonds the bootloader advertises with another address
+pub const BUTTONLESS_UUID: &str = "8ec90003-f315-4f60-9fb8-838830daea50";
+pub const BUTTONLESS_BONDS_UUID: &str = "8ec90004-f315-4f60-9fb8-838830daea50";
+
+/// Control point opcodes
+pub const OP_CREATE: u8 = 0x01;
+pub const OP_SET_PRN: u8 = 0x02;
+pub const OP_CALCULATE_CHECKSUM: u8 = 0x03;
+pub const OP_EXECUTE: u8 = 0x04;
+pub const OP_SELECT: u8 = 0x06;
+pub const OP_RESPONSE: u8 = 0x60;
+
+/// Buttonless opcodes
+pub const BUTTONLESS_ENTER_BOOTLOADER: u8 = 0x01;
+pub const BUTTONLESS_SET_NAME: u8 = 0x02;
+pub const BUTTONLESS_RESPONSE: u8 = 0x20;
+
+pub const RESULT_SUCCESS: u8 = 0x01;
+pub const RESULT_EXTENDED_ERROR: u8 = 0x0b;
+
+/// Objects of the control point
+pub const OBJECT_COMMAND: u8 = 0x01;
+pub const OBJECT_DATA: u8 = 0x02;
+
+fn result_message(result: u8) -> &'static str {
+    match result {
+        0x00 => "invalid code",
+        0x02 => "opcode not supported",
+        0x03 => "invalid parameter",
+        0x04 => "insufficient resources",
+        0x05 => "invalid object",
+        0x07 => "unsupported type",
+        0x08 => "operation not permitted",
+        0x0a => "operation failed",
+        _ => "unknown error",
+    }
+}
+
+fn extended_error_message(error: u8) -> &'static str {
+    match error {
+        0x02 => "wrong command format",
+        0x03 => "unknown command",
+        0x04 => "invalid init command",
+        0x05 => "firmware version failure",
+        0x06 => "hardware version failure",
+        0x07 => "softdevice version failure",
+        0x08 => "signature missing",
+        0x09 => "wrong hash type",
+        0x0a => "hash failed",
+        0x0b => "wrong signature type",
+        0x0c => "verification failed",
+        0x0d => "insufficient space",
+        _ => "unknown extended error",
+    }
+}
+
+/// Payload of a successful response to an opcode
+fn parse_response(response_op: u8, opcode: u8, response: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    match response {
+        [op, code, result, payload @ ..] if *op == response_op && *code == opcode => {
+            match *result {
+                RESULT_SUCCESS => Ok(payload.to_vec()),
+                RESULT_EXTENDED_ERROR => Err(format!(
+                    "DFU opcode 0x{:02x} failed: {}",
+                    opcode,
+                    extended_error_message(payload.first().copied().unwrap_or(0))
+                ))?,
+                result => Err(format!(
+                    "DFU opcode 0x{:02x} failed: {}",
+                    opcode,
+                    result_message(result)
+                ))?,
+            }
+        }
+        _ => Err(format!(
+            "Unexpected DFU response: {}",
+            print_bytes::bytes_to_str(&response.to_vec(), "hex")
+        ))?,
+    }
+}
+
+fn u32_at(payload: &[u8], index: usize) -> Result<u32, Box<dyn Error>> {
+    match payload.get(index..index + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err("DFU response too short")?,
+    }
+}
+
+fn has_characteristic(infos: &BlePeripheralInfo, characteristic: &str) -> bool {
+    infos
+        .services
+        .iter()
+        .filter(|s| normalize_uuid(&s.uuid) == SERVICE_UUID)
+        .flat_map(|s| &s.characteristics)
+        .any(|c| normalize_uuid(&c.uuid) == characteristic)
+}
+
+/// Options of a firmware update
+#[derive(Debug, Clone, Copy)]
+pub struct DfuOptions {
+    /// Number of packets after which the peripheral notifies its checksum, 0 to disable it
+    pub prn: u16,
+
+    /// Time waited for each response of the peripheral
+    pub timeout: Duration,
+
+    /// Scan duration when looking for the bootloader after a reboot
+    pub scan_timeout: usize,
+}
+
+impl Default for DfuOptions {
+    fn default() -> Self {
+        DfuOptions {
+            prn: 0,
+            timeout: Duration::from_secs(10),
+            scan_timeout: 5,
+        }
+    }
+}
+
+/// Offset and checksum of the data received by the bootloader for an object type
+struct ObjectInfo {
+    max_size: u32,
+    offset: u32,
+    crc: u32,
+}
+
+/// Transfer of the images to a peripheral running the bootloader
+struct Transfer<'a> {
+    bt: &'a mut dyn BleController,
+    options: DfuOptions,
+    chunk_size: usize,
+}
+
+impl Transfer<'_> {
+    async fn new(
+        bt: &mut dyn BleController,
+        options: DfuOptions,
+    ) -> Result<Transfer<'_>, Box<dyn Error>> {
+        bt.notify(SERVICE_UUID, CONTROL_POINT_UUID, None).await?;
+        let chunk_size = commands::write::mtu_chunk_size(bt).await;
+
+        let mut transfer = Transfer {
+            bt,
+            options,
+            chunk_size,
+        };
+        let [low, high] = options.prn.to_le_bytes();
+        transfer.request(&[OP_SET_PRN, low, high]).await?;
+        Ok(transfer)
+    }
+
+    /// Write a request to the control point and wait for its response
+    async fn request(&mut self, request: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.bt
+            .write(SERVICE_UUID, CONTROL_POINT_UUID, request, true)
+            .await?;
+        self.response(request[0]).await
+    }
+
+    async fn response(&mut self, opcode: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        let response = self
+            .bt
+            .wait_notification(SERVICE_UUID, CONTROL_POINT_UUID, self.options.timeout)
+            .await?;
+        parse_response(OP_RESPONSE, opcode, &response)
+    }
+
+    async fn select(&mut self, object_type: u8) -> Result<ObjectInfo, Box<dyn Error>> {
+        let payload = self.request(&[OP_SELECT, object_type]).await?;
+        Ok(ObjectInfo {
+            max_size: u32_at(&payload, 0)?,
+            offset: u32_at(&payload, 4)?,
+            crc: u32_at(&payload, 8)?,
+        })
+    }
+
+    async fn create(&mut self, object_type: u8, size: usize) -> Result<(), Box<dyn Error>> {
+        let mut request = vec![OP_CREATE, object_type];
+        request.extend((size as u32).to_le_bytes());
+        self.request(&request).await?;
+        Ok(())
+    }
+
+    async fn execute(&mut self) -> Result<(), Box<dyn Error>> {
+        self.request(&[OP_EXECUTE]).await?;
+        Ok(())
+    }
+
+    /// Check the offset and checksum of a checksum response
+    fn validate(payload: &[u8], offset: u32, crc: u32) -> Result<(), Box<dyn Error>> {
+        let (received_offset, received_crc) = (u32_at(payload, 0)?, u32_at(payload, 4)?);
+        if received_offset != offset || received_crc != crc {
+            Err(format!(
+                "Checksum mismatch: the peripheral has 0x{:08x} at offset {}, expected 0x{:08x} at offset {}",
+                received_crc, received_offset, crc, offset
+            ))?
+        }
+        Ok(())
+    }
+
+    /// Write data to the packet characteristic after the data already sent, the checksums
+    /// notified every PRN packets and the one at the end are checked
+    async fn stream(&mut self, data: &[u8], offset: u32, crc: u32) -> Result<u32, Box<dyn Error>> {
+        let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+        let mut offset = offset;
+        let prn = self.options.prn as usize;
+
+        for (index, chunk) in data.chunks(self.chunk_size).enumerate() {
+            self.bt
+                .write(SERVICE_UUID, PACKET_UUID, chunk, false)
+                .await?;
+            hasher.update(chunk);
+            offset += chunk.len() as u32;
+
+            if prn > 0 && (index + 1) % prn == 0 {
+                let payload = self.response(OP_CALCULATE_CHECKSUM).await?;
+                Self::validate(&payload, offset, hasher.clone().finalize())?;
+            }
+        }
+
+        let crc = hasher.finalize();
+        let payload = self.request(&[OP_CALCULATE_CHECKSUM]).await?;
+        Self::validate(&payload, offset, crc)?;
+        Ok(crc)
+    }
+
+    /// Send and execute the init packet, the one already received is executed if it is complete
+    async fn send_init_packet(&mut self, init_packet: &[u8]) -> Result<(), Box<dyn Error>> {
+        let info = self.select(OBJECT_COMMAND).await?;
+        let offset = info.offset as usize;
+
+        if offset > 0
+            && offset <= init_packet.len()
+            && info.crc == crc32fast::hash(&init_packet[..offset])
+        {
+            if offset < init_packet.len() {
+                self.stream(&init_packet[offset..], info.offset, info.crc)
+                    .await?;
+            }
+            return self.execute().await;
+        }
+
+        if init_packet.len() > info.max_size as usize {
+            Err(format!(
+                "The init packet is too big: {} bytes, the bootloader accepts {}",
+                init_packet.len(),
+                info.max_size
+            ))?
+        }
+        self.create(OBJECT_COMMAND, init_packet.len()).await?;
+        self.stream(init_packet, 0, 0).await?;
+        self.execute().await
+    }
+
+    /// Send the firmware in objects of the size given by the bootloader, the transfer resumes
+    /// after the data it already received when its checksum is valid
+    async fn send_firmware(&mut self, image: &Image) -> Result<(), Box<dyn Error>> {
+        let firmware = &image.firmware;
+        let info = self.select(OBJECT_DATA).await?;
+        let max_size = info.max_size as usize;
+        if max_size == 0 {
+            Err("The bootloader doesn't accept data objects")?
+        }
+
+        let mut offset = info.offset as usize;
+        let mut crc = info.crc;
+
+        if offset > firmware.len() {
+            offset = 0;
+            crc = 0;
+        } else if offset > 0 {
+            let remainder = offset % max_size;
+
+            if crc != crc32fast::hash(&firmware[..offset]) {
+                // the corrupted object is sent again
+                offset -= if remainder != 0 { remainder } else { max_size };
+                crc = crc32fast::hash(&firmware[..offset]);
+            } else {
+                if remainder != 0 && offset != firmware.len() {
+                    let end = (offset + max_size - remainder).min(firmware.len());
+                    crc = self
+                        .stream(&firmware[offset..end], offset as u32, crc)
+                        .await?;
+                    offset = end;
+                }
+                self.execute().await?;
+                eprintln!("Resuming the transfer at {} bytes", offset);
+            }
+        }
+
+        let start = Instant::now();
+        let resumed = offset;
+
+        while offset < firmware.len() {
+            let end = (offset + max_size).min(firmware.len());
+            self.create(OBJECT_DATA, end - offset).await?;
+            crc = self
+                .stream(&firmware[offset..end], offset as u32, crc)
+                .await?;
+            self.execute().await?;
+            offset = end;
+
+            eprint!(
+                "\r{}: {}/{} bytes ({}%)",
+                image.image_type,
+                offset,
+                firmware.len(),
+                offset * 100 / firmware.len()
+            );
+            let _ = io::stderr().flush();
+        }
+
+        let elapsed = start.elapsed().as_secs_f64();
+        eprintln!();
+        eprintln!(
+            "Sent {} bytes in {:.2} s ({:.0} bytes/s)",
+            offset - resumed,
+            elapsed,
+            (offset - resumed) as f64 / elapsed.max(f64::EPSILON)
+        );
+        Ok(())
+    }
+}
+
+/// Scan and connect to the bootloader after the peripheral rebooted
+async fn reconnect(
+    bt: &mut dyn BleController,
+    is_bootloader: &(dyn Fn(&BlePeripheral) -> bool + Send + Sync),
+    scan_timeout: usize,
+) -> Result<(), Box<dyn Error>> {
+    // the peripheral may have closed the connection already
+    let _ = bt.disconnect().await;
+
+    for _ in 0..3 {
+        commands::scan::run(bt, scan_timeout).await?;
+
+        if let Some(p) = bt.get_scan_list().into_iter().find(|p| is_bootloader(p)) {
+            eprintln!(
+                "Connecting to the bootloader {} ({})",
+                p.name, p.address_uuid
+            );
+            return bt.connect(&p.address_uuid).await;
+        }
+    }
+    Err("The bootloader wasn't found after rebooting the peripheral")?
+}
+
+/// Write a request to a buttonless characteristic and wait for its response
+async fn buttonless_request(
+    bt: &mut dyn BleController,
+    characteristic: &str,
+    request: &[u8],
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    bt.write(SERVICE_UUID, characteristic, request, true)
+        .await?;
+    let response = bt
+        .wait_notification(SERVICE_UUID, characteristic, timeout)
+        .await?;
+    parse_response(BUTTONLESS_RESPONSE, request[0], &response)?;
+    Ok(())
+}
+
+/// Name advertised by the bootloader after a buttonless reboot without bonds
+pub fn bootloader_name() -> String {
+    format!("Dfu{:05}", std::process::id() % 100_000)
+}
+
+/// Reboot a peripheral running its application in bootloader mode with the buttonless service
+/// and connect to the bootloader, its address is returned
+async fn enter_bootloader(
+    bt: &mut dyn BleController,
+    infos: &BlePeripheralInfo,
+    options: DfuOptions,
+) -> Result<String, Box<dyn Error>> {
+    let with_bonds = !has_characteristic(infos, BUTTONLESS_UUID);
+    let characteristic = match with_bonds {
+        false => BUTTONLESS_UUID,
+        true if has_characteristic(infos, BUTTONLESS_BONDS_UUID) => BUTTONLESS_BONDS_UUID,
+        true => Err("The peripheral doesn't have the Secure DFU service")?,
+    };
+
+    bt.indicate(SERVICE_UUID, characteristic, None).await?;
+
+    // without bonds the bootloader has another address, it is found by the name it advertises
+    let name = bootloader_name();
+    if !with_bonds {
+        let mut request = vec![BUTTONLESS_SET_NAME, name.len() as u8];
+        request.extend(name.as_bytes());
+        buttonless_request(bt, characteristic, &request, options.timeout).await?;
+    }
+
+    buttonless_request(
+        bt,
+        characteristic,
+        &[BUTTONLESS_ENTER_BOOTLOADER],
+        options.timeout,
+    )
+    .await?;
+    eprintln!("Rebooting the peripheral in bootloader mode");
+
+    let address = infos.periph_mac.clone();
+    if with_bonds {
+        reconnect(bt, &|p| p.address_uuid == address, options.scan_timeout).await?;
+    } else {
+        reconnect(bt, &|p| p.name == name, options.scan_timeout).await?;
+    }
+    Ok(bt.get_peripheral_infos().await?.periph_mac)
+}
+
+/// Update the firmware of the connected peripheral with Nordic Secure DFU. A peripheral running
+/// its application is rebooted in bootloader mode with the buttonless service first. Running it
+/// again after an interruption resumes the transfer
+pub async fn run(
+    bt: &mut dyn BleController,
+    package: &Package,
+    options: DfuOptions,
+) -> Result<(), Box<dyn Error>> {
+    if !bt.is_connected() {
+        Err("You must be connected to a peripheral to run this command")?;
+    }
+
+    let infos = bt.get_peripheral_infos().await?;
+    let address = if has_characteristic(&infos, CONTROL_POINT_UUID) {
+        infos.periph_mac
+    } else {
+        enter_bootloader(bt, &infos, options).await?
+    };
+
+    for (index, image) in package.images.iter().enumerate() {
+        if index > 0 {
+            // the bootloader resets after activating each image
+            reconnect(bt, &|p| p.address_uuid == address, options.scan_timeout).await?;
+        }
+
+        eprintln!(
+            "Sending {} ({} bytes)",
+            image.image_type,
+            image.firmware.len()
+        );
+        let mut transfer = Transfer::new(bt, options).await?;
+        transfer.send_init_packet(&image.init_packet).await?;
+        transfer.send_firmware(image).await?;
+    }
+
+    eprintln!("DFU complete, the peripheral restarts with the new firmware");
+    // the peripheral resets after the last image, the connection may be closed already
+    let _ = bt.disconnect().await;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek};
+use std::path;
+
+/// Images of a package in the order nrfutil sends them, the peripheral resets after each one
+const IMAGE_TYPES: [&str; 4] = [
+    "softdevice_bootloader",
+    "softdevice",
+    "bootloader",
+    "application",
+];
+
+#[derive(Deserialize)]
+struct Manifest {
+    manifest: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ManifestImage {
+    bin_file: String,
+    dat_file: String,
+}
+
+/// Firmware image with its init packet, the signed command describing it
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// Type of the image in the manifest (application, softdevice ...)
+    pub image_type: String,
+
+    pub init_packet: Vec<u8>,
+    pub firmware: Vec<u8>,
+}
+
+/// DFU package created by nrfutil: a zip with a manifest.json and a .dat and .bin file per image
+#[derive(Debug)]
+pub struct Package {
+    pub images: Vec<Image>,
+}
+
+fn read_entry<R: Read + Seek>(zip: &mut zip::ZipArchive<R>, name: &str) -> Result<Vec<u8>, String> {
+    let mut entry = zip
+        .by_name(name)
+        .map_err(|e| format!("Missing file '{}' in the package: {}", name, e))?;
+
+    let mut data = Vec::new();
+    entry
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Could not read '{}' in the package: {}", name, e))?;
+    Ok(data)
+}
+
+impl Package {
+    pub fn load(fp: &path::Path) -> Result<Package, String> {
+        let file = fs::File::open(fp)
+            .map_err(|e| format!("Could not open '{}': {}", fp.to_string_lossy(), e))?;
+        Package::from_reader(file)
+    }
+
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Package, String> {
+        let mut zip =
+            zip::ZipArchive::new(reader).map_err(|e| format!("Invalid package: {}", e))?;
+
+        let manifest = read_entry(&mut zip, "manifest.json")?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .map_err(|e| format!("Invalid manifest.json: {}", e))?;
+
+        let mut images = Vec::new();
+        for image_type in IMAGE_TYPES {
+            let Some(image) = manifest.manifest.get(image_type) else {
+                continue;
+            };
+            let image: ManifestImage = serde_json::from_value(image.clone())
+                .map_err(|e| format!("Invalid {} in manifest.json: {}", image_type, e))?;
+
+            images.push(Image {
+                image_type: image_type.to_owned(),
+                init_packet: read_entry(&mut zip, &image.dat_file)?,
+                firmware: read_entry(&mut zip, &image.bin_file)?,
+            });
+        }
+
+        if images.is_empty() {
+            return Err("The package doesn't contain any image".to_owned());
+        }
+        Ok(Package { images })
+    }
+}
@@ -0,0 +1,21 @@
+//! bluerepl is a BLE client running in the terminal. The library exposes the controllers used to
+//! talk to peripherals, the presets and the commands of the repl.
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod bluetooth_numbers;
+pub mod config;
+pub mod controllers;
+pub mod dfu;
+pub mod preset;
+pub mod repl;
+pub mod smp;
+pub mod utils;
+
+pub use preset::Preset;
+
+// the examples of the README are compiled with the doc tests
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+struct ReadmeDoctests;
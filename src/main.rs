@@ -1,21 +1,20 @@
 extern crate exitcode;
 
-#[macro_use]
-extern crate lazy_static;
+mod check;
+mod oneshot;
 
-mod bluetooth_numbers;
-mod controllers;
-mod preset;
-mod repl;
-mod utils;
+use clap::{Parser, Subcommand};
 
-use clap::Parser;
-
-use controllers::btleplug;
-use preset::Preset;
-use repl::Repl;
+use bluerepl::config::{self, Config};
+use bluerepl::controllers::btleplug;
+use bluerepl::dfu::DfuOptions;
+use bluerepl::preset::{self, Preset};
+use bluerepl::repl::commands::write;
+use bluerepl::repl::Repl;
+use bluerepl::utils::output::Output;
 use std::error::Error;
 use std::panic;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -23,43 +22,351 @@ struct Args {
     /// Path to the preset file to load
     preset_file: Option<std::path::PathBuf>,
 
-    #[clap(short, default_value = "btleplug")]
-    /// Ble lib to use :
+    #[clap(short)]
+    /// Ble lib to use [default: btleplug] :
     /// - btleplug
     /// - simpleble
     /// - bleuio
-    ble_lib: String,
+    ble_lib: Option<String>,
 
     /// autoconnect to peripheral described in preset
     #[clap(short, long)]
     autoconnect: bool,
+
+    /// Path to the configuration file [default: ~/.config/bluerepl/config.toml]
+    #[clap(long)]
+    config: Option<std::path::PathBuf>,
+
+    /// Print the results of the commands as json
+    #[clap(long)]
+    json: bool,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Validate preset files without using the BLE adapter
+    Check {
+        /// Preset files to validate
+        #[clap(required = true)]
+        presets: Vec<std::path::PathBuf>,
+
+        /// Format of the diagnostics
+        #[clap(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+    },
+
+    /// Print the JSON Schema of the preset format
+    Schema,
+
+    /// Search for BLE devices around
+    Scan {
+        /// Time to scan in seconds [default: scan_timeout in config]
+        #[clap(long)]
+        timeout: Option<usize>,
+
+        /// Show unnamed peripheral
+        #[clap(short, long)]
+        all: bool,
+    },
+
+    /// Connect to a peripheral and read the value of a characteristic
+    Read {
+        /// Index, mac address or name of the peripheral
+        #[clap(short, long)]
+        device: String,
+
+        /// Time to scan in seconds before connecting [default: scan_timeout in config]
+        #[clap(long)]
+        timeout: Option<usize>,
+
+        /// The service that contains the characteristic to read
+        service: String,
+
+        /// The characteristic to read
+        characteristic: String,
+
+        /// Format to print the read value
+        #[clap(short, long, value_parser = config::FORMATS)]
+        format: Option<String>,
+    },
+
+    /// Connect to a peripheral and write a value to a characteristic
+    Write {
+        /// Index, mac address or name of the peripheral
+        #[clap(short, long)]
+        device: String,
+
+        /// Time to scan in seconds before connecting [default: scan_timeout in config]
+        #[clap(long)]
+        timeout: Option<usize>,
+
+        /// Write with response (default write is write without response)
+        #[clap(short, long)]
+        resp: bool,
+
+        /// The service that contains the characteristic to write
+        service: String,
+
+        /// The characteristic to write
+        characteristic: String,
+
+        /// The payload to write
+        #[clap(required_unless_present_any = ["file", "stdin"])]
+        payload: Option<String>,
+
+        /// Write the content of a file in chunks instead of a payload
+        #[clap(long, conflicts_with_all = ["payload", "stdin"])]
+        file: Option<std::path::PathBuf>,
+
+        /// Write the data read from stdin in chunks instead of a payload
+        #[clap(long, conflicts_with = "payload")]
+        stdin: bool,
+
+        /// Size of the chunks written [default: the MTU minus 3, or 20]
+        #[clap(long)]
+        chunk_size: Option<usize>,
+
+        /// Time to wait between two chunks in milliseconds
+        #[clap(long, default_value_t = 0)]
+        delay: u64,
+    },
+
+    /// Connect to a peripheral and update its firmware with a Nordic DFU package
+    Dfu {
+        /// Index, mac address or name of the peripheral
+        #[clap(short, long)]
+        device: String,
+
+        /// Time to scan in seconds before connecting [default: scan_timeout in config]
+        #[clap(long)]
+        timeout: Option<usize>,
+
+        /// Path of the zip package created by nrfutil
+        package: std::path::PathBuf,
+
+        /// Number of packets between two checksums notified by the peripheral, 0 to disable it
+        #[clap(long, default_value_t = 0)]
+        prn: u16,
+
+        /// Time to wait for each response of the peripheral in seconds
+        #[clap(long, default_value_t = 10)]
+        response_timeout: u64,
+    },
+
+    /// Connect to a peripheral and run a function of a preset
+    Run {
+        /// Path to the preset file
+        preset: std::path::PathBuf,
+
+        /// The function to run
+        function: String,
+
+        /// Index, mac address or name of the peripheral [default: the device of the preset]
+        #[clap(short, long)]
+        device: Option<String>,
+
+        /// Time to scan in seconds before connecting [default: scan_timeout in config]
+        #[clap(long)]
+        timeout: Option<usize>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    println!("bluerepl Version: {}", env!("CARGO_PKG_VERSION"));
-
     panic::set_hook(Box::new(|e| {
         eprintln!("{}", e);
     }));
 
     let args = Args::parse();
 
-    let mut bt = match args.ble_lib.as_str() {
-        "btleplug" => btleplug::BtleplugController::new().await,
+    if let Some(Commands::Schema) = args.command {
+        print!("{}", preset::schema::preset_schema_json());
+        return Ok(());
+    }
+
+    let config = match Config::load(args.config) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exitcode::CONFIG);
+        }
+    };
+
+    if let Some(Commands::Check { presets, format }) = &args.command {
+        std::process::exit(check::run(&config, presets, format));
+    }
+
+    // the preset of the run command is checked before using the adapter
+    let run_preset = match &args.command {
+        Some(Commands::Run {
+            preset, function, ..
+        }) => match oneshot::load_preset(&config, preset, function) {
+            Ok(pr) => Some(pr),
+            Err(code) => std::process::exit(code),
+        },
+        _ => None,
+    };
+
+    // the data of the write command is read before using the adapter
+    let write_value = match &args.command {
+        Some(Commands::Write {
+            payload,
+            file,
+            stdin,
+            chunk_size,
+            delay,
+            ..
+        }) => {
+            let options = write::ChunkOptions {
+                size: *chunk_size,
+                delay: Duration::from_millis(*delay),
+            };
+            match oneshot::write_value(payload.as_ref(), file.as_deref(), *stdin, options) {
+                Ok(value) => Some(value),
+                Err(code) => std::process::exit(code),
+            }
+        }
+        _ => None,
+    };
+
+    // the package of the dfu command is loaded before using the adapter
+    let dfu_package = match &args.command {
+        Some(Commands::Dfu { package, .. }) => match oneshot::load_package(package) {
+            Ok(package) => Some(package),
+            Err(code) => std::process::exit(code),
+        },
+        _ => None,
+    };
+
+    if args.command.is_none() {
+        println!("bluerepl Version: {}", env!("CARGO_PKG_VERSION"));
+    }
+
+    let ble_lib = args.ble_lib.unwrap_or_else(|| config.backend.clone());
+
+    let mut bt = match ble_lib.as_str() {
+        "btleplug" => {
+            match btleplug::BtleplugController::new(
+                config.adapter.as_deref(),
+                config.gatt_cache_dir(),
+            )
+            .await
+            {
+                Ok(bt) => bt,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(exitcode::UNAVAILABLE);
+                }
+            }
+        }
         "simpleble" => todo!("simpleble support is not yet implemented"),
         "bleuio" => todo!("bleuio support is not yet implemented"),
         n => panic!("Unknown controller id {}", n),
     };
 
-    let mut repl = Repl::new(&mut bt).await;
+    let output = if args.json {
+        Output::Json
+    } else {
+        Output::Text
+    };
+
+    if let Some(command) = args.command {
+        let code = match command {
+            Commands::Scan { timeout, all } => {
+                let timeout = timeout.unwrap_or(config.scan_timeout);
+                oneshot::scan(&mut bt, timeout, all, output).await
+            }
+            Commands::Read {
+                device,
+                timeout,
+                service,
+                characteristic,
+                format,
+            } => {
+                let formatter = oneshot::formatter(&config, &characteristic, format.as_ref());
+                let timeout = timeout.unwrap_or(config.scan_timeout);
+                oneshot::read(
+                    &mut bt,
+                    &device,
+                    timeout,
+                    &service,
+                    &characteristic,
+                    formatter,
+                    output,
+                )
+                .await
+            }
+            Commands::Write {
+                device,
+                timeout,
+                resp,
+                service,
+                characteristic,
+                ..
+            } => {
+                let timeout = timeout.unwrap_or(config.scan_timeout);
+                let value = write_value.expect("the data is read before the adapter");
+                oneshot::write(
+                    &mut bt,
+                    &device,
+                    timeout,
+                    &service,
+                    &characteristic,
+                    &value,
+                    resp,
+                )
+                .await
+            }
+            Commands::Dfu {
+                device,
+                timeout,
+                prn,
+                response_timeout,
+                ..
+            } => {
+                let options = DfuOptions {
+                    prn,
+                    timeout: Duration::from_secs(response_timeout),
+                    scan_timeout: timeout.unwrap_or(config.scan_timeout),
+                };
+                let package = dfu_package.expect("the package is loaded before the adapter");
+                oneshot::dfu(&mut bt, &device, &package, options).await
+            }
+            Commands::Run {
+                function,
+                device,
+                timeout,
+                ..
+            } => {
+                let timeout = timeout.unwrap_or(config.scan_timeout);
+                let pr = run_preset.expect("the preset is loaded before the adapter");
+                oneshot::run_function(&mut bt, &pr, &function, device.as_ref(), timeout).await
+            }
+            Commands::Check { .. } | Commands::Schema => unreachable!(),
+        };
+        std::process::exit(code);
+    }
+
+    let preset_file = args.preset_file.map(|fp| config.find_preset(&fp));
 
-    if let Some(preset_file) = args.preset_file {
-        let mut pr = Preset::new(preset_file).unwrap();
+    let mut repl = Repl::new(&mut bt, config).await;
+    repl.set_output(output);
+
+    if let Some(preset_file) = preset_file {
+        let mut pr = match Preset::new(preset_file) {
+            Ok(pr) => pr,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        };
 
         if args.autoconnect {
             if !pr.is_autoconnect_possible() {
-                panic!("A name or an address must be in the preset file to use the autoconnect feature");
+                panic!("A name, an address or a matching rule must be in the preset file to use the autoconnect feature");
             }
             pr.device.as_mut().unwrap().autoconnect = Some(true);
         }
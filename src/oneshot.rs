@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::path;
+
+use bluerepl::bluetooth_numbers::characteristic_decoders;
+use bluerepl::config::Config;
+use bluerepl::controllers::BleController;
+use bluerepl::dfu::{self, DfuOptions, Package};
+use bluerepl::preset::Preset;
+use bluerepl::repl::commands;
+use bluerepl::repl::commands::write::{ChunkOptions, WriteValue};
+use bluerepl::utils::output::Output;
+use bluerepl::utils::print_bytes::{self, Formatter};
+
+/// Print an error and return the exit code of the program
+fn fail(e: Box<dyn Error>, code: i32) -> i32 {
+    eprintln!("{}", e);
+    code
+}
+
+/// Scan and connect to a peripheral given by its index, address or name
+async fn connect(
+    bt: &mut dyn BleController,
+    device: &str,
+    scan_timeout: usize,
+) -> Result<(), Box<dyn Error>> {
+    commands::scan::run(bt, scan_timeout).await?;
+    commands::connect::auto_detect_identifier(bt, device).await
+}
+
+/// Disconnect after running a command and return the exit code of the program
+async fn finish(bt: &mut dyn BleController, result: Result<(), Box<dyn Error>>) -> i32 {
+    if bt.is_connected() {
+        let _ = bt.disconnect().await;
+    }
+    match result {
+        Ok(()) => exitcode::OK,
+        Err(e) => fail(e, exitcode::IOERR),
+    }
+}
+
+pub async fn scan(bt: &mut dyn BleController, timeout: usize, all: bool, output: Output) -> i32 {
+    let result = commands::scan::run(bt, timeout).await;
+    match result.and_then(|list| commands::scan::print_scan_list(&list, all, output)) {
+        Ok(()) => exitcode::OK,
+        Err(e) => fail(e, exitcode::UNAVAILABLE),
+    }
+}
+
+/// Formatter of the values read: the format given, the decoder of a standard characteristic or
+/// the default format
+pub fn formatter(config: &Config, characteristic: &str, format: Option<&String>) -> Formatter {
+    match format {
+        Some(format) => print_bytes::formatter(format),
+        None => characteristic_decoders::get_characteristic_formatter(characteristic)
+            .unwrap_or_else(|| print_bytes::formatter(&config.format)),
+    }
+}
+
+pub async fn read(
+    bt: &mut dyn BleController,
+    device: &str,
+    scan_timeout: usize,
+    service: &str,
+    characteristic: &str,
+    formatter: Formatter,
+    output: Output,
+) -> i32 {
+    if let Err(e) = connect(bt, device, scan_timeout).await {
+        return fail(e, exitcode::UNAVAILABLE);
+    }
+
+    let result = commands::read::read(bt, service, characteristic, None)
+        .await
+        .map(|value| {
+            commands::read::print_value(service, characteristic, &value, &formatter, output)
+        });
+    finish(bt, result).await
+}
+
+pub async fn write(
+    bt: &mut dyn BleController,
+    device: &str,
+    scan_timeout: usize,
+    service: &str,
+    characteristic: &str,
+    value: &WriteValue,
+    response: bool,
+) -> i32 {
+    if let Err(e) = connect(bt, device, scan_timeout).await {
+        return fail(e, exitcode::UNAVAILABLE);
+    }
+
+    let result =
+        commands::write::write_value(bt, service, characteristic, None, value, response).await;
+    finish(bt, result).await
+}
+
+/// Value of the write command, the data of a file or stdin is read before using the adapter
+pub fn write_value(
+    payload: Option<&String>,
+    file: Option<&path::Path>,
+    stdin: bool,
+    options: ChunkOptions,
+) -> Result<WriteValue, i32> {
+    match payload {
+        Some(payload) => Ok(WriteValue::Payload(payload.clone())),
+        None => match commands::write::read_data(file, stdin) {
+            Ok(data) => Ok(WriteValue::Data(data, options)),
+            Err(e) => Err(fail(e, exitcode::NOINPUT)),
+        },
+    }
+}
+
+/// Load a preset to run one of its functions, it is checked before using the adapter
+pub fn load_preset(
+    config: &Config,
+    preset_file: &path::Path,
+    function: &str,
+) -> Result<Preset, i32> {
+    let pr = Preset::load(config.find_preset(preset_file))
+        .map_err(|e| fail(e.into(), exitcode::DATAERR))?;
+
+    if !pr.get_function_names().iter().any(|f| f == function) {
+        return Err(fail(
+            format!("Function '{}' not found in preset", function).into(),
+            exitcode::DATAERR,
+        ));
+    }
+    Ok(pr)
+}
+
+/// Run a function of a preset, the peripheral is the one given or the one matching the device of
+/// the preset
+pub async fn run_function(
+    bt: &mut dyn BleController,
+    pr: &Preset,
+    function: &str,
+    device: Option<&String>,
+    scan_timeout: usize,
+) -> i32 {
+    let connected = match device {
+        Some(device) => connect(bt, device, scan_timeout).await,
+        None if pr.is_autoconnect_possible() => pr.autoconnect(bt, scan_timeout).await,
+        None => Err("A device must be given with --device or described in the preset".into()),
+    };
+    if let Err(e) = connected {
+        return fail(e, exitcode::UNAVAILABLE);
+    }
+
+    let result = pr.run_function(bt, function).await;
+    finish(bt, result).await
+}
+
+/// Load the package of the dfu command, it is checked before using the adapter
+pub fn load_package(fp: &path::Path) -> Result<Package, i32> {
+    Package::load(fp).map_err(|e| fail(e.into(), exitcode::DATAERR))
+}
+
+pub async fn dfu(
+    bt: &mut dyn BleController,
+    device: &str,
+    package: &Package,
+    options: DfuOptions,
+) -> i32 {
+    if let Err(e) = connect(bt, device, options.scan_timeout).await {
+        return fail(e, exitcode::UNAVAILABLE);
+    }
+
+    let result = dfu::run(bt, package, options).await;
+    finish(bt, result).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_preset() {
+        let config = Config::default();
+        let fp = path::Path::new("presets/neopixel_controller.toml");
+
+        assert!(load_preset(&config, fp, "blink_rgb").is_ok());
+        assert_eq!(
+            load_preset(&config, fp, "unknown").err(),
+            Some(exitcode::DATAERR)
+        );
+        assert_eq!(
+            load_preset(&config, path::Path::new("missing.toml"), "blink_rgb").err(),
+            Some(exitcode::DATAERR)
+        );
+    }
+
+    #[test]
+    fn test_write_value() {
+        let options = ChunkOptions::default();
+
+        let value = write_value(Some(&"0x01".to_owned()), None, false, options).unwrap();
+        assert!(matches!(value, WriteValue::Payload(p) if p == "0x01"));
+
+        let fp = path::Path::new("presets/neopixel_controller.toml");
+        let value = write_value(None, Some(fp), false, options).unwrap();
+        assert!(matches!(value, WriteValue::Data(d, _) if d == std::fs::read(fp).unwrap()));
+
+        assert_eq!(
+            write_value(None, Some(path::Path::new("missing.bin")), false, options).err(),
+            Some(exitcode::NOINPUT)
+        );
+    }
+
+    #[test]
+    fn test_load_package() {
+        assert_eq!(
+            load_package(path::Path::new("missing.zip")).err(),
+            Some(exitcode::DATAERR)
+        );
+        assert_eq!(
+            load_package(path::Path::new("presets/neopixel_controller.toml")).err(),
+            Some(exitcode::DATAERR)
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::Preset;
+use crate::utils::print_bytes;
+
+/// Types available for the fields of a characteristic layout
+pub const FIELD_TYPES: [&str; 13] = [
+    "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "f64", "bool", "utf8", "bytes",
+];
+
+/// One field of the layout of a characteristic value
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+pub struct Field {
+    name: String,
+
+    /// One of FIELD_TYPES, numbers can have a 'le' (default) or 'be' suffix
+    #[serde(rename = "type")]
+    #[schemars(schema_with = "super::schema::field_type")]
+    field_type: String,
+
+    /// Length in bytes of utf8 and bytes fields, they take the rest of the value by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+
+    /// Names of the values of the field
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    enum_names: Option<HashMap<String, String>>,
+
+    /// Names of the bits of the field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bits: Option<HashMap<String, u8>>,
+}
+
+/// Split a type like 'u16be' into 'u16' and big endian
+fn split_type(field_type: &str) -> (&str, bool) {
+    if let Some(base) = field_type.strip_suffix("be") {
+        (base, true)
+    } else if let Some(base) = field_type.strip_suffix("le") {
+        (base, false)
+    } else {
+        (field_type, false)
+    }
+}
+
+/// Size in bytes of fixed size types
+fn type_size(base: &str) -> Option<usize> {
+    match base {
+        "u8" | "i8" | "bool" => Some(1),
+        "u16" | "i16" => Some(2),
+        "u32" | "i32" | "f32" => Some(4),
+        "u64" | "i64" | "f64" => Some(8),
+        _ => None,
+    }
+}
+
+/// Remove floating point noise (23.400000000000002 -> 23.4)
+fn round(v: f64) -> f64 {
+    (v * 1e6).round() / 1e6
+}
+
+impl Field {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn verify(&self) -> Result<(), String> {
+        let (base, _) = split_type(&self.field_type);
+
+        if !FIELD_TYPES.contains(&base) {
+            return Err(format!(
+                "field '{}' invalid type: '{}'. It must be one of {:?}",
+                self.name, self.field_type, FIELD_TYPES
+            ));
+        }
+
+        let is_integer = base.starts_with('u') || base.starts_with('i');
+
+        if let Some(bits) = &self.bits {
+            if !is_integer {
+                return Err(format!(
+                    "field '{}' bits can only be used with integers",
+                    self.name
+                ));
+            }
+            for (name, bit) in bits {
+                if *bit as usize >= type_size(base).unwrap() * 8 {
+                    return Err(format!(
+                        "field '{}' bit '{}' out of range: {}",
+                        self.name, name, bit
+                    ));
+                }
+            }
+        }
+
+        if let Some(enum_names) = &self.enum_names {
+            if !is_integer {
+                return Err(format!(
+                    "field '{}' enum can only be used with integers",
+                    self.name
+                ));
+            }
+            for value in enum_names.keys() {
+                if value.parse::<i128>().is_err() {
+                    return Err(format!(
+                        "field '{}' invalid enum value: '{}'",
+                        self.name, value
+                    ));
+                }
+            }
+        }
+
+        if self.length.is_some() && type_size(base).is_some() {
+            return Err(format!(
+                "field '{}' length can only be used with utf8 and bytes",
+                self.name
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Decode the field at the start of bytes, return its text and the number of bytes used
+    fn decode(&self, bytes: &[u8]) -> Result<(String, usize), String> {
+        let (base, big_endian) = split_type(&self.field_type);
+
+        let size = type_size(base).or(self.length).unwrap_or(bytes.len());
+
+        if bytes.len() < size {
+            return Err(format!(
+                "not enough bytes for field '{}' ({} < {})",
+                self.name,
+                bytes.len(),
+                size
+            ));
+        }
+
+        let mut raw = bytes[..size].to_vec();
+        if big_endian {
+            raw.reverse();
+        }
+
+        let text = match base {
+            "utf8" => format!("{:?}", String::from_utf8_lossy(&raw)),
+            "bytes" => print_bytes::bytes_to_str(&raw, "hex"),
+            "bool" => (raw[0] != 0).to_string(),
+            "f32" => self.format_number(f32::from_le_bytes(raw.try_into().unwrap()) as f64),
+            "f64" => self.format_number(f64::from_le_bytes(raw.try_into().unwrap())),
+            _ => {
+                let mut buf = [0u8; 16];
+                buf[..size].copy_from_slice(&raw);
+                let mut n = i128::from_le_bytes(buf);
+
+                // sign extension
+                if base.starts_with('i') {
+                    let shift = 128 - size * 8;
+                    n = (n << shift) >> shift;
+                }
+                self.format_integer(n)
+            }
+        };
+
+        Ok((format!("{}={}", self.name, text), size))
+    }
+
+    fn format_number(&self, v: f64) -> String {
+        let v = round(v * self.scale.unwrap_or(1.0) + self.offset.unwrap_or(0.0));
+
+        match &self.unit {
+            Some(unit) => format!("{} {}", v, unit),
+            None => v.to_string(),
+        }
+    }
+
+    fn format_integer(&self, n: i128) -> String {
+        if let Some(bits) = &self.bits {
+            let mut names: Vec<(&u8, &String)> = bits
+                .iter()
+                .filter(|(_, bit)| n & (1 << **bit) != 0)
+                .map(|(name, bit)| (bit, name))
+                .collect();
+            names.sort();
+
+            return format!(
+                "[{}]",
+                names
+                    .iter()
+                    .map(|(_, name)| name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+        }
+
+        if let Some(enum_names) = &self.enum_names {
+            if let Some((_, name)) = enum_names
+                .iter()
+                .find(|(value, _)| value.parse::<i128>() == Ok(n))
+            {
+                return name.to_owned();
+            }
+        }
+
+        if self.scale.is_some() || self.offset.is_some() {
+            return self.format_number(n as f64);
+        }
+
+        match &self.unit {
+            Some(unit) => format!("{} {}", n, unit),
+            None => n.to_string(),
+        }
+    }
+}
+
+/// Decode a value with a layout into a record like 'temperature=23.4 °C, flags=[LOW_BATT]', the
+/// value must have the length of the layout
+pub fn decode(fields: &[Field], bytes: &[u8]) -> Result<String, String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut pos = 0;
+
+    for f in fields {
+        let (text, size) = f.decode(&bytes[pos..])?;
+        out.push(text);
+        pos += size;
+    }
+
+    if pos < bytes.len() {
+        return Err(format!(
+            "the value has {} bytes but the fields use {}",
+            bytes.len(),
+            pos
+        ));
+    }
+    Ok(out.join(", "))
+}
+
+/// Formatter decoding values with a layout, the hex value is printed if decoding fails
+pub fn formatter(fields: &[Field]) -> print_bytes::Formatter {
+    let fields = fields.to_vec();
+
+    Arc::new(move |bytes: &[u8]| match decode(&fields, bytes) {
+        Ok(record) => record,
+        Err(e) => format!(
+            "{} (decode error: {})",
+            print_bytes::bytes_to_str(&bytes.to_vec(), "hex"),
+            e
+        ),
+    })
+}
+
+impl Preset {
+    /// Formatter for a characteristic that has a layout in the preset
+    pub fn get_formatter(&self, ser_uuid: &str, char_uuid: &str) -> Option<print_bytes::Formatter> {
+        self.get_characteristic_fields(ser_uuid, char_uuid)
+            .map(|fields| formatter(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_fields(s: &str) -> Vec<Field> {
+        #[derive(Deserialize)]
+        struct Layout {
+            fields: Vec<Field>,
+        }
+        let layout: Layout = toml::from_str(s).unwrap();
+        for f in &layout.fields {
+            f.verify().unwrap();
+        }
+        layout.fields
+    }
+
+    #[test]
+    fn test_decode() {
+        let fields = parse_fields(
+            r#"
+            fields = [
+                { name = "temperature", type = "i16", scale = 0.01, unit = "°C" },
+                { name = "flags", type = "u8", bits = { LOW_BATT = 0, CHARGING = 2 } },
+                { name = "mode", type = "u8", enum = { 0 = "idle", 1 = "run" } },
+                { name = "counter", type = "u32be" },
+                { name = "name", type = "utf8" },
+            ]
+            "#,
+        );
+
+        let bytes = [0x24, 0x09, 0b101, 1, 0, 0, 1, 0, b'a', b'b'];
+        assert_eq!(
+            decode(&fields, &bytes).unwrap(),
+            "temperature=23.4 °C, flags=[LOW_BATT, CHARGING], mode=run, counter=256, name=\"ab\""
+        );
+
+        let bytes = [0xfe, 0xff, 0, 7, 0, 0, 0, 0];
+        assert_eq!(
+            decode(&fields, &bytes).unwrap(),
+            "temperature=-0.02 °C, flags=[], mode=7, counter=0, name=\"\""
+        );
+
+        assert!(decode(&fields, &[0x24]).is_err());
+
+        // the bytes past the fields of a fixed size layout are reported
+        assert_eq!(
+            decode(&fields[..2], &[0x24, 0x09, 0, 0xff]),
+            Err("the value has 4 bytes but the fields use 3".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_preset_formatter() {
+        let pr = Preset::new(std::path::PathBuf::from("presets/color.toml")).unwrap();
+
+        let formatter = pr
+            .get_formatter(
+                "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx",
+                "XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX",
+            )
+            .unwrap();
+        assert_eq!(formatter(&[255, 0, 16]), "red=255, green=0, blue=16");
+        assert_eq!(
+            formatter(&[255]),
+            "[0xff] (decode error: not enough bytes for field 'green' (0 < 1))"
+        );
+
+        assert!(pr
+            .get_formatter("XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX", "1234")
+            .is_none());
+    }
+
+    #[test]
+    fn test_verify_fields() {
+        #[derive(Deserialize)]
+        struct Layout {
+            fields: Vec<Field>,
+        }
+        let layout: Layout = toml::from_str(
+            r#"
+            fields = [
+                { name = "a", type = "u24" },
+                { name = "b", type = "f32", bits = { X = 0 } },
+                { name = "c", type = "u8", bits = { X = 8 } },
+                { name = "d", type = "u8", enum = { x = "y" } },
+                { name = "e", type = "u8", length = 2 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        for f in &layout.fields {
+            assert!(f.verify().is_err(), "{:?}", f);
+        }
+    }
+}
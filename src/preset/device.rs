@@ -0,0 +1,191 @@
+use regex::Regex;
+use std::error::Error;
+
+use super::{Device, Preset};
+use crate::bluetooth_numbers::compare_uuid::compare_uuid;
+use crate::controllers::{BleController, BlePeripheral};
+use crate::repl::commands;
+use crate::utils::payload;
+
+impl Device {
+    /// Check that the matching rules can be used
+    pub(super) fn verify(&self) -> Result<(), String> {
+        if let Some(re) = &self.name_regex {
+            if let Err(e) = Regex::new(re) {
+                return Err(format!("In device invalid name_regex: {}", e));
+            }
+        }
+        if let Some(data) = &self.manufacturer_data {
+            if let Err(e) = payload::parse(data) {
+                return Err(format!("In device invalid manufacturer_data: {}", e));
+            }
+        }
+        Ok(())
+    }
+
+    /// A peripheral can only be found if the device has a rule identifying it, the rssi isn't
+    /// enough
+    pub fn is_identifiable(&self) -> bool {
+        self.name.is_some()
+            || self.address.is_some()
+            || self.name_regex.is_some()
+            || self.service_uuid.is_some()
+            || self.manufacturer_id.is_some()
+            || self.manufacturer_data.is_some()
+    }
+
+    /// Check that a scanned peripheral matches all the rules of the device
+    pub fn matches(&self, p: &BlePeripheral) -> bool {
+        if self.name.as_ref().is_some_and(|n| *n != p.name) {
+            return false;
+        }
+        if self
+            .address
+            .as_ref()
+            .is_some_and(|a| !a.eq_ignore_ascii_case(&p.address_uuid))
+        {
+            return false;
+        }
+        if let Some(re) = &self.name_regex {
+            if !Regex::new(re).is_ok_and(|re| re.is_match(&p.name)) {
+                return false;
+            }
+        }
+        if let Some(uuid) = &self.service_uuid {
+            if !p
+                .services
+                .iter()
+                .any(|s| compare_uuid(&s.to_uppercase(), &uuid.to_uppercase()))
+            {
+                return false;
+            }
+        }
+        if let Some(min_rssi) = self.min_rssi {
+            // 0 is used when the rssi is unknown
+            if p.rssi == 0 || p.rssi < min_rssi {
+                return false;
+            }
+        }
+        self.matches_manufacturer_data(p)
+    }
+
+    fn matches_manufacturer_data(&self, p: &BlePeripheral) -> bool {
+        let prefix = match &self.manufacturer_data {
+            Some(data) => match payload::parse(data) {
+                Ok(prefix) => prefix,
+                Err(_) => return false,
+            },
+            None => vec![],
+        };
+
+        match self.manufacturer_id {
+            Some(id) => p
+                .manufacturer_data
+                .get(&id)
+                .is_some_and(|d| d.starts_with(&prefix)),
+            None if self.manufacturer_data.is_some() => {
+                p.manufacturer_data.values().any(|d| d.starts_with(&prefix))
+            }
+            None => true,
+        }
+    }
+}
+
+impl Preset {
+    /// Scan and connect to the peripheral matching the device of the preset with the best rssi,
+    /// scans are retried when no peripheral matches
+    pub async fn autoconnect(
+        &self,
+        bt: &mut dyn BleController,
+        scan_timeout: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(device) = &self.device else {
+            Err("No device in the preset")?
+        };
+        let scan_timeout = device.scan_timeout.unwrap_or(scan_timeout);
+        let retries = device.retries.unwrap_or(0);
+
+        for attempt in 0..=retries {
+            commands::scan::run(bt, scan_timeout).await?;
+
+            let best = bt
+                .get_scan_list()
+                .into_iter()
+                .filter(|p| device.matches(p))
+                .max_by_key(|p| p.rssi);
+
+            if let Some(p) = best {
+                eprintln!("Connecting to {} ({})", p.name, p.address_uuid);
+                bt.connect(&p.address_uuid).await?;
+                eprintln!("Connected!");
+                return Ok(());
+            }
+
+            if attempt < retries {
+                eprintln!(
+                    "No peripheral matches the preset device, retrying ({}/{})",
+                    attempt + 1,
+                    retries
+                );
+            }
+        }
+        Err("No peripheral matches the preset device")?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn peripheral(name: &str, rssi: i16) -> BlePeripheral {
+        BlePeripheral {
+            id: 0,
+            name: name.to_owned(),
+            address_uuid: "AA:BB:CC:DD:EE:FF".to_owned(),
+            rssi,
+            company_id: 0x0059,
+            services: vec!["0000180f-0000-1000-8000-00805f9b34fb".to_owned()],
+            manufacturer_data: HashMap::from([(0x0059, vec![0x01, 0x02, 0x03])]),
+        }
+    }
+
+    #[test]
+    fn test_device_matches() {
+        let device: Device = toml::from_str(
+            r#"
+            name_regex = "^PROD-[0-9]+$"
+            service_uuid = "180F"
+            manufacturer_id = 0x0059
+            manufacturer_data = "0x01 0x02"
+            min_rssi = -70
+            "#,
+        )
+        .unwrap();
+        device.verify().unwrap();
+        assert!(device.is_identifiable());
+
+        assert!(device.matches(&peripheral("PROD-1234", -50)));
+        assert!(!device.matches(&peripheral("PROD-1234", -80)));
+        assert!(!device.matches(&peripheral("PROD-1234", 0)));
+        assert!(!device.matches(&peripheral("DEV-1234", -50)));
+
+        let mut p = peripheral("PROD-1234", -50);
+        p.manufacturer_data = HashMap::from([(0x0059, vec![0x01, 0x03])]);
+        assert!(!device.matches(&p));
+
+        let mut p = peripheral("PROD-1234", -50);
+        p.services.clear();
+        assert!(!device.matches(&p));
+    }
+
+    #[test]
+    fn test_device_verify() {
+        let device: Device = toml::from_str("name_regex = \"PROD-(\"").unwrap();
+        assert!(device.verify().is_err());
+
+        let device: Device = toml::from_str("min_rssi = -60").unwrap();
+        assert!(device.verify().is_ok());
+        assert!(!device.is_identifiable());
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use super::{Characteristic, Command, Device, Preset, Service};
+use crate::bluetooth_numbers::compare_uuid::normalize_uuid;
+use crate::bluetooth_numbers::{characteristic_uuids, services_uuids};
+use crate::controllers::{BleController, BlePeripheralInfo, CharacteristicProperties};
+
+/// Characteristic User Description descriptor, a text naming the characteristic
+pub const USER_DESCRIPTION_UUID: &str = "00002901-0000-1000-8000-00805f9b34fb";
+
+/// User descriptions of the characteristics by normalised service and characteristic uuids
+pub type UserDescriptions = HashMap<(String, String), String>;
+
+/// Read the user descriptions of the characteristics that have one, the ones that can't be read
+/// are skipped
+pub async fn read_user_descriptions(
+    bt: &mut dyn BleController,
+    infos: &BlePeripheralInfo,
+) -> UserDescriptions {
+    let mut descriptions = HashMap::new();
+
+    for s in &infos.services {
+        for c in &s.characteristics {
+            if !c
+                .descriptors
+                .iter()
+                .any(|d| normalize_uuid(&d.uuid) == USER_DESCRIPTION_UUID)
+            {
+                continue;
+            }
+            if let Ok(value) = bt
+                .read_descriptor(&s.uuid, &c.uuid, USER_DESCRIPTION_UUID)
+                .await
+            {
+                descriptions.insert(
+                    (normalize_uuid(&s.uuid), normalize_uuid(&c.uuid)),
+                    String::from_utf8_lossy(&value).into_owned(),
+                );
+            }
+        }
+    }
+    descriptions
+}
+
+/// Name from a user description: 'LED State' -> 'led_state'
+fn name_from_description(description: &str) -> Option<String> {
+    let name: String = description
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let name = name
+        .split('_')
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+
+    (!name.is_empty()).then_some(name)
+}
+
+/// Name from a SIG identifier: 'org.bluetooth.characteristic.battery_level' -> 'battery_level'
+fn name_from_identifier(identifier: Option<String>) -> Option<String> {
+    identifier.and_then(|i| i.rsplit('.').next().map(|n| n.to_owned()))
+}
+
+/// Add a suffix to a name until it isn't in the map
+fn unique_name<T>(map: &HashMap<String, T>, name: String) -> String {
+    if !map.contains_key(&name) {
+        return name;
+    }
+    (2..)
+        .map(|n| format!("{}_{}", name, n))
+        .find(|n| !map.contains_key(n))
+        .unwrap()
+}
+
+impl Preset {
+    /// Create a preset describing the gatt of a peripheral, with a read command for each readable
+    /// characteristic and a subscribe command for each one that notifies or indicates. The
+    /// characteristics are named after their user description when they have one
+    pub fn from_peripheral_infos(
+        infos: &BlePeripheralInfo,
+        descriptions: &UserDescriptions,
+    ) -> Preset {
+        let mut services: HashMap<String, Service> = HashMap::new();
+        let mut commands: HashMap<String, Command> = HashMap::new();
+
+        for (ser_index, s) in infos.services.iter().enumerate() {
+            let ser_name =
+                name_from_identifier(services_uuids::get_service_identifier_from_uuid(&s.uuid))
+                    .unwrap_or_else(|| format!("service_{}", ser_index));
+            let ser_name = unique_name(&services, ser_name);
+
+            let mut characteristics: HashMap<String, Characteristic> = HashMap::new();
+
+            for (char_index, c) in s.characteristics.iter().enumerate() {
+                let identifier =
+                    characteristic_uuids::get_characteristic_identifier_from_uuid(&c.uuid);
+                let is_string = identifier.as_ref().is_some_and(|i| i.contains("string"));
+
+                let char_name = descriptions
+                    .get(&(normalize_uuid(&s.uuid), normalize_uuid(&c.uuid)))
+                    .and_then(|d| name_from_description(d))
+                    .or_else(|| name_from_identifier(identifier))
+                    .unwrap_or_else(|| format!("characteristic_{}", char_index));
+                let char_name = unique_name(&characteristics, char_name);
+
+                let mut add_command = |prefix: &str, command_type: &str, format: Option<&str>| {
+                    let name = unique_name(&commands, format!("{}_{}", prefix, char_name));
+                    commands.insert(
+                        name,
+                        Command {
+                            command_type: command_type.to_owned(),
+                            service: ser_name.clone(),
+                            characteristic: char_name.clone(),
+                            payload: None,
+                            format: format.map(|f| f.to_owned()),
+                        },
+                    );
+                };
+
+                if c.properties.contains(CharacteristicProperties::READ) {
+                    add_command("read", "read", is_string.then_some("text"));
+                }
+                if c.properties.contains(CharacteristicProperties::NOTIFY) {
+                    add_command("sub", "notify", None);
+                } else if c.properties.contains(CharacteristicProperties::INDICATE) {
+                    add_command("sub", "indicate", None);
+                }
+
+                characteristics.insert(
+                    char_name,
+                    Characteristic {
+                        uuid: c.uuid.clone(),
+                        fields: None,
+                    },
+                );
+            }
+
+            services.insert(
+                ser_name,
+                Service {
+                    uuid: s.uuid.clone(),
+                    characteristics: Some(characteristics),
+                },
+            );
+        }
+
+        Preset {
+            fp: Default::default(),
+            include: None,
+            device: Some(Device {
+                name: Some(infos.periph_name.clone()),
+                address: Some(infos.periph_mac.clone()),
+                ..Default::default()
+            }),
+            services: Some(services),
+            commands: Some(commands),
+            functions: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers;
+    use crate::preset::format::PresetFormat;
+
+    #[test]
+    fn test_export() {
+        let characteristic = |uuid: &str, properties| controllers::Characteristic {
+            uuid: uuid.to_owned(),
+            properties,
+            descriptors: vec![],
+            handle: None,
+        };
+
+        let infos = BlePeripheralInfo {
+            periph_name: "my_device".to_owned(),
+            periph_mac: "AA:BB:CC:DD:EE:FF".to_owned(),
+            rssi: -50,
+            mtu: None,
+            services: vec![
+                controllers::Service {
+                    uuid: "0000180a-0000-1000-8000-00805f9b34fb".to_owned(),
+                    characteristics: vec![characteristic(
+                        "00002a29-0000-1000-8000-00805f9b34fb",
+                        CharacteristicProperties::READ,
+                    )],
+                    handles: None,
+                },
+                controllers::Service {
+                    uuid: "8e72bbe5-f777-5284-7849-b4a0b2ac70d2".to_owned(),
+                    characteristics: vec![
+                        characteristic(
+                            "0000beb6-0000-1000-8000-00805f9b34fb",
+                            CharacteristicProperties::WRITE,
+                        ),
+                        characteristic(
+                            "0000dac4-0000-1000-8000-00805f9b34fb",
+                            CharacteristicProperties::READ | CharacteristicProperties::INDICATE,
+                        ),
+                    ],
+                    handles: None,
+                },
+            ],
+        };
+
+        let pr = Preset::from_peripheral_infos(&infos, &UserDescriptions::new());
+        pr.verify().unwrap();
+
+        let mut commands = pr.get_command_names();
+        commands.sort();
+        assert_eq!(
+            commands,
+            vec![
+                "read_characteristic_1",
+                "read_manufacturer_name_string",
+                "sub_characteristic_1"
+            ]
+        );
+
+        let exported = pr.serialize_as(PresetFormat::Toml).unwrap();
+        assert!(exported.contains("[services.service_1.characteristics.characteristic_0]"));
+        assert!(exported.contains("format = \"text\""));
+
+        // the exported preset can be loaded again
+        let pr: Preset = toml::from_str(&exported).unwrap();
+        pr.verify().unwrap();
+        assert_eq!(pr.device.unwrap().name.unwrap(), "my_device");
+
+        let descriptions = HashMap::from([(
+            (
+                "8e72bbe5-f777-5284-7849-b4a0b2ac70d2".to_owned(),
+                "0000dac4-0000-1000-8000-00805f9b34fb".to_owned(),
+            ),
+            " LED State ".to_owned(),
+        )]);
+        let pr = Preset::from_peripheral_infos(&infos, &descriptions);
+        let mut commands = pr.get_command_names();
+        commands.sort();
+        assert_eq!(
+            commands,
+            vec![
+                "read_led_state",
+                "read_manufacturer_name_string",
+                "sub_led_state"
+            ]
+        );
+    }
+}
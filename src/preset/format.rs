@@ -0,0 +1,122 @@
+use std::path;
+
+use super::Preset;
+
+/// Languages presets can be written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PresetFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl PresetFormat {
+    /// Format of a file from its extension
+    pub fn from_path(fp: &path::Path) -> Option<PresetFormat> {
+        match fp.extension()?.to_str()?.to_lowercase().as_str() {
+            "toml" => Some(PresetFormat::Toml),
+            "json" => Some(PresetFormat::Json),
+            "yaml" | "yml" => Some(PresetFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Guess the format of a file without a known extension from its contents
+    fn detect(contents: &str) -> PresetFormat {
+        if contents.trim_start().starts_with('{') {
+            PresetFormat::Json
+        } else if toml::from_str::<toml::Table>(contents).is_ok() {
+            PresetFormat::Toml
+        } else {
+            PresetFormat::Yaml
+        }
+    }
+}
+
+impl Preset {
+    /// Parse a preset written in the format given by the extension of its file or by its
+    /// contents
+    pub(super) fn parse_str(contents: &str, fp: &path::Path) -> Result<Preset, String> {
+        let format = PresetFormat::from_path(fp).unwrap_or_else(|| PresetFormat::detect(contents));
+
+        match format {
+            PresetFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+            PresetFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            PresetFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Preset written in a format, tables are sorted by name
+    pub fn serialize_as(&self, format: PresetFormat) -> Result<String, String> {
+        match format {
+            PresetFormat::Toml => {
+                let value = toml::Value::try_from(self).map_err(|e| e.to_string())?;
+                toml::to_string(&value).map_err(|e| e.to_string())
+            }
+            PresetFormat::Json => {
+                let value = serde_json::to_value(self).map_err(|e| e.to_string())?;
+                serde_json::to_string_pretty(&value)
+                    .map(|s| s + "\n")
+                    .map_err(|e| e.to_string())
+            }
+            PresetFormat::Yaml => {
+                let value = serde_json::to_value(self).map_err(|e| e.to_string())?;
+                serde_yaml::to_string(&value).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Write the preset in the format given by the extension of the file
+    pub fn write_file(&self, fp: &path::Path) -> Result<(), String> {
+        let Some(format) = PresetFormat::from_path(fp) else {
+            return Err(format!(
+                "Unknown preset format for '{}', use a .toml, .json or .yaml file",
+                fp.to_string_lossy()
+            ));
+        };
+
+        std::fs::write(fp, self.serialize_as(format)?)
+            .map_err(|e| format!("Could not write file '{}': {}", fp.to_string_lossy(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(PresetFormat::detect("{\"device\": {}}"), PresetFormat::Json);
+        assert_eq!(
+            PresetFormat::detect("[device]\nname = \"a\""),
+            PresetFormat::Toml
+        );
+        assert_eq!(
+            PresetFormat::detect("device:\n  name: a\n"),
+            PresetFormat::Yaml
+        );
+        assert_eq!(
+            PresetFormat::from_path(path::Path::new("a/b.YML")),
+            Some(PresetFormat::Yaml)
+        );
+        assert_eq!(PresetFormat::from_path(path::Path::new("a/b")), None);
+    }
+
+    #[test]
+    fn test_convert() {
+        let pr = Preset::read_file(path::Path::new("presets/neopixel_controller.toml")).unwrap();
+
+        for format in [PresetFormat::Json, PresetFormat::Yaml, PresetFormat::Toml] {
+            let text = pr.serialize_as(format).unwrap();
+
+            // no extension, the format is detected from the contents
+            let converted = Preset::parse_str(&text, path::Path::new("preset")).unwrap();
+            assert_eq!(
+                converted.serialize_as(PresetFormat::Toml).unwrap(),
+                pr.serialize_as(PresetFormat::Toml).unwrap(),
+                "{:?}",
+                format
+            );
+        }
+    }
+}
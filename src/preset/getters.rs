@@ -1,6 +1,20 @@
+use super::decode::Field;
 use super::Preset;
+use crate::bluetooth_numbers::compare_uuid::normalize_uuid;
+use std::collections::HashMap;
+use std::path;
 
 impl Preset {
+    /// Path of the preset file
+    pub fn get_path(&self) -> &path::Path {
+        &self.fp
+    }
+
+    /// Address of the device of the preset, if it has one
+    pub fn get_device_address(&self) -> Option<String> {
+        self.device.as_ref().and_then(|d| d.address.clone())
+    }
+
     pub fn get_service_name_from_uuid(&self, uuid: &str) -> Option<String> {
         let Some(services) = &self.services else {
             return None;
@@ -78,6 +92,82 @@ impl Preset {
 
         None
     }
+
+    pub fn get_characteristic_fields(
+        &self,
+        ser_uuid: &str,
+        char_uuid: &str,
+    ) -> Option<&Vec<Field>> {
+        let services = self.services.as_ref()?;
+
+        for (_, ser_data) in services.iter() {
+            if normalize_uuid(&ser_data.uuid) != normalize_uuid(ser_uuid) {
+                continue;
+            }
+
+            for (_, char_data) in ser_data.characteristics.as_ref()?.iter() {
+                if normalize_uuid(&char_data.uuid) == normalize_uuid(char_uuid) {
+                    return char_data.fields.as_ref();
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get_command_names(&self) -> Vec<String> {
+        let Some(commands) = &self.commands else {
+            return Vec::new();
+        };
+
+        commands.keys().cloned().collect()
+    }
+
+    pub fn get_function_names(&self) -> Vec<String> {
+        let Some(functions) = &self.functions else {
+            return Vec::new();
+        };
+
+        functions.keys().cloned().collect()
+    }
+
+    /// Map the service names to their uuid
+    pub fn get_services_uuids(&self) -> HashMap<String, String> {
+        let Some(services) = &self.services else {
+            return HashMap::new();
+        };
+
+        services
+            .iter()
+            .map(|(name, data)| (name.to_owned(), data.uuid.to_owned()))
+            .collect()
+    }
+
+    /// Map (service name or uuid, characteristic name) to the characteristic uuid
+    pub fn get_characteristics_uuids(&self) -> HashMap<(String, String), String> {
+        let mut uuids = HashMap::new();
+
+        let Some(services) = &self.services else {
+            return uuids;
+        };
+
+        for (ser_name, ser_data) in services.iter() {
+            let Some(characteristics) = &ser_data.characteristics else {
+                continue;
+            };
+
+            for (char_name, char_data) in characteristics.iter() {
+                uuids.insert(
+                    (ser_name.to_owned(), char_name.to_owned()),
+                    char_data.uuid.to_owned(),
+                );
+                uuids.insert(
+                    (ser_data.uuid.to_owned(), char_name.to_owned()),
+                    char_data.uuid.to_owned(),
+                );
+            }
+        }
+        uuids
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,329 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path;
+
+use super::{Preset, Step};
+
+/// A preset included by another one, its names can be prefixed with a namespace
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum Include {
+    Path(path::PathBuf),
+    Namespaced {
+        path: path::PathBuf,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+    },
+}
+
+impl Include {
+    fn path(&self) -> &path::Path {
+        match self {
+            Include::Path(path) | Include::Namespaced { path, .. } => path,
+        }
+    }
+
+    fn namespace(&self) -> Option<&str> {
+        match self {
+            Include::Path(_) => None,
+            Include::Namespaced { namespace, .. } => namespace.as_deref(),
+        }
+    }
+}
+
+/// Presets already included, by canonical path and full namespace, and the chain of includes
+/// being resolved
+pub(super) struct Included {
+    presets: HashSet<(path::PathBuf, Option<String>)>,
+    stack: Vec<path::PathBuf>,
+}
+
+impl Included {
+    pub(super) fn new(fp: &path::Path) -> Included {
+        let fp = fp.canonicalize().unwrap_or_else(|_| fp.to_path_buf());
+        Included {
+            presets: HashSet::from([(fp.clone(), None)]),
+            stack: vec![fp],
+        }
+    }
+}
+
+/// Prefix a name with a namespace: 'dis' -> 'ns.dis'
+fn prefix(namespace: &str, name: &str) -> String {
+    format!("{}.{}", namespace, name)
+}
+
+fn prefix_keys<T>(namespace: &str, map: &mut Option<HashMap<String, T>>) {
+    if let Some(m) = map.take() {
+        *map = Some(
+            m.into_iter()
+                .map(|(k, v)| (prefix(namespace, &k), v))
+                .collect(),
+        );
+    }
+}
+
+/// Prefix the names of commands and functions used by steps
+fn prefix_steps(namespace: &str, steps: &mut [Step]) {
+    for step in steps {
+        match step {
+            // the command name is followed by the values of its parameters
+            Step::Command { command } => *command = prefix(namespace, command.trim_start()),
+            Step::Call { call } => *call = prefix(namespace, call),
+            Step::WaitNotification {
+                wait_notification, ..
+            } => *wait_notification = prefix(namespace, wait_notification),
+            Step::Delay { .. } => (),
+            Step::Repeat { steps, .. } => prefix_steps(namespace, steps),
+            Step::AssertRead {
+                assert_read,
+                on_success,
+                on_failure,
+                ..
+            } => {
+                *assert_read = prefix(namespace, assert_read);
+                prefix_steps(namespace, on_success.as_deref_mut().unwrap_or_default());
+                prefix_steps(namespace, on_failure.as_deref_mut().unwrap_or_default());
+            }
+        }
+    }
+}
+
+/// Add the entries of an included preset, a name can't be defined twice
+fn merge_map<T>(
+    kind: &str,
+    fp: &path::Path,
+    dest: &mut Option<HashMap<String, T>>,
+    src: Option<HashMap<String, T>>,
+) -> Result<(), String> {
+    let Some(src) = src else {
+        return Ok(());
+    };
+    let dest = dest.get_or_insert_with(HashMap::new);
+
+    for (name, value) in src {
+        if dest.contains_key(&name) {
+            return Err(format!(
+                "{} '{}' included from '{}' is already defined",
+                kind,
+                name,
+                fp.to_string_lossy()
+            ));
+        }
+        dest.insert(name, value);
+    }
+    Ok(())
+}
+
+impl Preset {
+    /// Prefix the names of services, commands and functions with a namespace
+    fn set_namespace(&mut self, namespace: &str) {
+        prefix_keys(namespace, &mut self.services);
+        prefix_keys(namespace, &mut self.commands);
+        prefix_keys(namespace, &mut self.functions);
+
+        for command in self.commands.iter_mut().flat_map(|c| c.values_mut()) {
+            command.service = prefix(namespace, &command.service);
+        }
+        for function in self.functions.iter_mut().flat_map(|f| f.values_mut()) {
+            prefix_steps(namespace, &mut function.steps);
+        }
+    }
+
+    fn merge(&mut self, other: Preset) -> Result<(), String> {
+        if self.device.is_none() {
+            self.device = other.device;
+        }
+        merge_map("Service", &other.fp, &mut self.services, other.services)?;
+        merge_map("Command", &other.fp, &mut self.commands, other.commands)?;
+        merge_map("Function", &other.fp, &mut self.functions, other.functions)?;
+        Ok(())
+    }
+
+    /// Merge the presets included by this one, paths are relative to the preset file. A file is
+    /// included only once for each full namespace, the one of the including presets followed by
+    /// its own
+    pub(super) fn resolve_includes(
+        &mut self,
+        namespace: Option<&str>,
+        included: &mut Included,
+    ) -> Result<(), String> {
+        let dir = self
+            .fp
+            .parent()
+            .unwrap_or(path::Path::new(""))
+            .to_path_buf();
+
+        for include in self.include.take().unwrap_or_default() {
+            let fp = dir.join(include.path());
+            let canonical = fp.canonicalize().unwrap_or_else(|_| fp.clone());
+            if included.stack.contains(&canonical) {
+                return Err(format!("Preset '{}' includes itself", fp.to_string_lossy()));
+            }
+
+            let full_namespace = match (namespace, include.namespace()) {
+                (Some(outer), Some(inner)) => Some(prefix(outer, inner)),
+                (outer, inner) => outer.or(inner).map(|n| n.to_owned()),
+            };
+            if !included
+                .presets
+                .insert((canonical.clone(), full_namespace.clone()))
+            {
+                continue;
+            }
+
+            included.stack.push(canonical);
+            let pr = Self::parse_file_with_includes(fp, full_namespace.as_deref(), included);
+            included.stack.pop();
+
+            let mut pr = pr?;
+            if let Some(namespace) = include.namespace() {
+                pr.set_namespace(namespace);
+            }
+            self.merge(pr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_include() {
+        let pr = Preset::new(path::PathBuf::from("presets/neopixel_controller.toml")).unwrap();
+
+        assert!(pr.get_service_uuid_from_name("dis").is_some());
+        assert!(pr
+            .get_command_names()
+            .contains(&"read_model_number".to_owned()));
+        assert!(pr.include.is_none());
+    }
+
+    #[test]
+    fn test_include_namespace_and_conflicts() {
+        let dir = std::env::temp_dir().join(format!("bluerepl_include_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+
+        std::fs::write(
+            dir.join("common/lib.toml"),
+            r#"
+            [services.svc]
+            uuid = "180f"
+            characteristics.level = { uuid = "2a19" }
+
+            [commands.read_level]
+            command_type = "read"
+            service = "svc"
+            characteristic = "level"
+
+            [functions.check]
+            steps = [{ command = "read_level" }, { call = "check2" }]
+
+            [functions.check2]
+            steps = [{ assert_read = "read_level", equals = "u8:1" }]
+            "#,
+        )
+        .unwrap();
+
+        std::fs::write(
+            dir.join("main.toml"),
+            r#"
+            include = [
+                "common/lib.toml",
+                { path = "common/lib.toml", namespace = "b" },
+                "common/lib.toml",
+            ]
+            "#,
+        )
+        .unwrap();
+        let pr = Preset::new(dir.join("main.toml")).unwrap();
+
+        let mut commands = pr.get_command_names();
+        commands.sort();
+        assert_eq!(commands, vec!["b.read_level", "read_level"]);
+        assert_eq!(
+            pr.commands.as_ref().unwrap()["b.read_level"].service,
+            "b.svc"
+        );
+        assert!(matches!(
+            &pr.functions.as_ref().unwrap()["b.check"].steps[..],
+            [Step::Command { command }, Step::Call { call }]
+                if command == "b.read_level" && call == "b.check2"
+        ));
+
+        std::fs::write(
+            dir.join("conflict.toml"),
+            r#"
+            include = ["common/lib.toml"]
+
+            [services.svc]
+            uuid = "180a"
+            "#,
+        )
+        .unwrap();
+        assert!(Preset::new(dir.join("conflict.toml")).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_diamond_and_cycle() {
+        let dir = std::env::temp_dir().join(format!("bluerepl_diamond_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(
+            dir.join("d.toml"),
+            r#"
+            [services.svc]
+            uuid = "180f"
+            characteristics.level = { uuid = "2a19" }
+
+            [commands.read_level]
+            command_type = "read"
+            service = "svc"
+            characteristic = "level"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b.toml"),
+            r#"
+            include = ["d.toml"]
+
+            [functions.check]
+            steps = [{ command = "read_level" }]
+            "#,
+        )
+        .unwrap();
+
+        // d is included by b in the namespace b, and by main without namespace, in both orders
+        for include in [
+            r#"["d.toml", { path = "b.toml", namespace = "b" }]"#,
+            r#"[{ path = "b.toml", namespace = "b" }, "d.toml"]"#,
+        ] {
+            std::fs::write(dir.join("main.toml"), format!("include = {}", include)).unwrap();
+            let pr = Preset::new(dir.join("main.toml")).unwrap();
+
+            let mut commands = pr.get_command_names();
+            commands.sort();
+            assert_eq!(commands, vec!["b.read_level", "read_level"]);
+            assert!(matches!(
+                &pr.functions.as_ref().unwrap()["b.check"].steps[..],
+                [Step::Command { command }] if command == "b.read_level"
+            ));
+        }
+
+        std::fs::write(
+            dir.join("d.toml"),
+            r#"include = [{ path = "b.toml", namespace = "x" }]"#,
+        )
+        .unwrap();
+        let err = Preset::new(dir.join("main.toml")).unwrap_err();
+        assert!(err.contains("includes itself"), "{}", err);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
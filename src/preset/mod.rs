@@ -1,77 +1,210 @@
-use crate::controllers;
-use crate::repl::commands;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use std::error::Error;
-
 use std::{fs, path};
 
+mod decode;
+mod device;
+pub mod export;
+pub mod format;
 mod getters;
+mod include;
+pub mod params;
 mod print;
 mod run;
+pub mod schema;
 mod verify;
 
-#[derive(Deserialize, Debug)]
+use include::Included;
+
+/// Types of the commands of a preset
+pub const COMMAND_TYPES: [&str; 6] = [
+    "write",
+    "write_with_resp",
+    "read",
+    "notify",
+    "indicate",
+    "unsubscribe",
+];
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 pub struct Preset {
-    #[serde(skip_deserializing)]
+    #[serde(skip)]
     fp: path::PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include: Option<Vec<include::Include>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub device: Option<Device>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     services: Option<HashMap<String, Service>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     commands: Option<HashMap<String, Command>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<HashMap<String, FunctionDef>>")]
     functions: Option<HashMap<String, Function>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default)]
 pub struct Device {
+    #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     address: Option<String>,
-    #[serde(skip_deserializing)]
+
+    /// Regular expression the advertised name must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name_regex: Option<String>,
+
+    /// UUID of a service the peripheral must advertise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service_uuid: Option<String>,
+
+    /// Company id of the manufacturer data the peripheral must advertise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manufacturer_id: Option<u16>,
+
+    /// Payload the manufacturer data must start with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manufacturer_data: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_rssi: Option<i16>,
+
+    /// Time to scan in seconds before trying to connect [default: scan_timeout in config]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scan_timeout: Option<usize>,
+
+    /// Number of scans to retry when no peripheral matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retries: Option<usize>,
+
+    #[serde(skip)]
     pub autoconnect: Option<bool>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 pub struct Service {
     uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     characteristics: Option<HashMap<String, Characteristic>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 pub struct Characteristic {
     uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<Vec<decode::Field>>,
 }
 
-fn format_default() -> String {
-    "hex".to_string()
-}
-
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 pub struct Command {
+    #[schemars(schema_with = "schema::command_type")]
     command_type: String,
     service: String,
     characteristic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     payload: Option<String>,
-
-    #[serde(default = "format_default")]
-    format: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "schema::format")]
+    format: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "FunctionDef")]
 pub struct Function {
-    commands_delay_ms: Vec<u64>,
-    commands: Vec<String>,
+    steps: Vec<Step>,
+}
+
+/// Functions are written as a list of steps or, in the old format, as a list of commands with
+/// the delay to wait after each of them
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(rename = "Function")]
+enum FunctionDef {
+    Steps {
+        steps: Vec<Step>,
+    },
+    Legacy {
+        commands_delay_ms: Vec<u64>,
+        commands: Vec<String>,
+    },
+}
+
+impl TryFrom<FunctionDef> for Function {
+    type Error = String;
+
+    fn try_from(def: FunctionDef) -> Result<Self, Self::Error> {
+        match def {
+            FunctionDef::Steps { steps } => Ok(Function { steps }),
+            FunctionDef::Legacy {
+                commands_delay_ms,
+                commands,
+            } => {
+                if commands.len() != commands_delay_ms.len() {
+                    return Err(
+                        "'commands' and 'commands_delay_ms' don't have the same length".to_owned(),
+                    );
+                }
+
+                let steps = commands
+                    .into_iter()
+                    .zip(commands_delay_ms)
+                    .flat_map(|(command, delay_ms)| {
+                        [Step::Command { command }, Step::Delay { delay_ms }]
+                    })
+                    .collect();
+                Ok(Function { steps })
+            }
+        }
+    }
+}
+
+/// One step of a function
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum Step {
+    /// Run a command, with the values of its parameters: 'set_color r=255'
+    Command { command: String },
+
+    /// Run another function
+    Call { call: String },
+
+    /// Wait for a value of the characteristic of a notify or indicate command
+    WaitNotification {
+        wait_notification: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+
+    /// Wait before the next step
+    Delay { delay_ms: u64 },
+
+    /// Run steps multiple times
+    Repeat { repeat: usize, steps: Vec<Step> },
+
+    /// Compare the value read by a read command with a payload
+    AssertRead {
+        assert_read: String,
+        equals: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        on_success: Option<Vec<Step>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        on_failure: Option<Vec<Step>>,
+    },
 }
 
 impl Preset {
-    fn parse_file(fp: path::PathBuf) -> Result<Preset, String> {
-        let contents = match fs::read_to_string(&fp) {
+    /// Read a preset file without including the presets it includes
+    pub fn read_file(fp: &path::Path) -> Result<Preset, String> {
+        let contents = match fs::read_to_string(fp) {
             Ok(c) => c,
             Err(_) => {
                 return Err(format!("Could not read file: '{}'", fp.to_string_lossy()));
             }
         };
 
-        let mut pr: Preset = match toml::from_str(&contents) {
+        let mut pr = match Self::parse_str(&contents, fp) {
             Ok(d) => d,
             Err(e) => {
                 return Err(format!(
@@ -81,15 +214,36 @@ impl Preset {
                 ))
             }
         };
-        pr.fp = fp;
+        pr.fp = fp.to_path_buf();
+        Ok(pr)
+    }
+
+    fn parse_file(fp: path::PathBuf) -> Result<Preset, String> {
+        let mut included = Included::new(&fp);
+        Self::parse_file_with_includes(fp, None, &mut included)
+    }
+
+    /// Parse a preset and the ones it includes, namespace is the full namespace it gets
+    fn parse_file_with_includes(
+        fp: path::PathBuf,
+        namespace: Option<&str>,
+        included: &mut Included,
+    ) -> Result<Preset, String> {
+        let mut pr = Self::read_file(&fp)?;
+        pr.resolve_includes(namespace, included)?;
         Ok(pr)
     }
 
     pub fn new(fp: std::path::PathBuf) -> Result<Preset, String> {
-        println!("Loading {}", fp.to_string_lossy());
+        eprintln!("Loading {}", fp.to_string_lossy());
+        Self::load(fp)
+    }
 
+    /// Parse and validate a preset file
+    pub fn load(fp: std::path::PathBuf) -> Result<Preset, String> {
         let pr = Self::parse_file(fp)?;
-        pr.verify();
+        pr.verify()
+            .map_err(|e| format!("Invalid preset {:?}: {}", pr.fp.to_string_lossy(), e))?;
 
         Ok(pr)
     }
@@ -100,23 +254,6 @@ impl Preset {
         }
         false
     }
-
-    pub async fn autoconnect(
-        &self,
-        bt: &mut dyn controllers::BleController,
-    ) -> Result<(), Box<dyn Error>> {
-        commands::scan::run(bt, 5, false, false).await.unwrap();
-        if let Some(name) = &self.device.as_ref().unwrap().name {
-            commands::connect::by_name(bt, name).await?;
-        } else {
-            commands::connect::by_address(
-                bt,
-                self.device.as_ref().unwrap().address.as_ref().unwrap(),
-            )
-            .await?;
-        }
-        Ok(())
-    }
 }
 
 #[cfg(test)]
@@ -125,7 +262,9 @@ mod tests {
 
     #[test]
     fn test_parse_all_base_presets() {
-        let test_files: [&str; 5] = [
+        let test_files: [&str; 7] = [
+            "presets/common/dis.toml",
+            "presets/common/battery.toml",
             "presets/battery.toml",
             "presets/nus.toml",
             "presets/color.toml",
@@ -138,4 +277,133 @@ mod tests {
             assert!(preset.is_ok(), "{:?}", preset);
         }
     }
+
+    const STEPS_PRESET: &str = r#"
+        [services.svc]
+        uuid = "180f"
+        characteristics.level = { uuid = "2a19" }
+
+        [commands.read_level]
+        command_type = "read"
+        service = "svc"
+        characteristic = "level"
+
+        [commands.sub_level]
+        command_type = "notify"
+        service = "svc"
+        characteristic = "level"
+
+        [commands.set_level]
+        command_type = "write"
+        service = "svc"
+        characteristic = "level"
+        payload = "{level:u8}"
+
+        [functions.legacy]
+        commands_delay_ms = [100, 0]
+        commands = ["read_level", "set_level level=3"]
+    "#;
+
+    #[test]
+    fn test_parse_function_steps() {
+        let preset = format!(
+            "{}{}",
+            STEPS_PRESET,
+            r#"
+            [functions.provision]
+            steps = [
+                { command = "sub_level" },
+                { wait_notification = "sub_level", timeout_ms = 1000 },
+                { repeat = 2, steps = [{ call = "legacy" }, { delay_ms = 10 }] },
+                { assert_read = "read_level", equals = "u8:3", on_failure = [{ command = "set_level level=3" }] },
+            ]
+            "#
+        );
+        let pr: Preset = toml::from_str(&preset).unwrap();
+        pr.verify().unwrap();
+
+        let functions = pr.functions.as_ref().unwrap();
+        assert_eq!(functions["provision"].steps.len(), 4);
+        assert!(matches!(
+            functions["provision"].steps[3],
+            Step::AssertRead { ref on_failure, .. } if on_failure.is_some()
+        ));
+
+        // legacy functions are converted to commands followed by delays
+        assert!(matches!(
+            functions["legacy"].steps[..],
+            [
+                Step::Command { .. },
+                Step::Delay { delay_ms: 100 },
+                Step::Command { .. },
+                Step::Delay { delay_ms: 0 }
+            ]
+        ));
+
+        let invalid = format!(
+            "{}{}",
+            STEPS_PRESET, "[functions.bad]\ncommands_delay_ms = [1]\ncommands = []\n"
+        );
+        assert!(toml::from_str::<Preset>(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_verify_recursive_function() {
+        let preset = format!(
+            "{}{}",
+            STEPS_PRESET,
+            r#"
+            [functions.a]
+            steps = [{ call = "b" }]
+
+            [functions.b]
+            steps = [{ repeat = 2, steps = [{ call = "a" }] }]
+            "#
+        );
+        let err = toml::from_str::<Preset>(&preset).unwrap().verify();
+        assert!(err.unwrap_err().contains("calls itself"));
+    }
+
+    #[test]
+    fn test_verify_step_command_type() {
+        let preset = format!(
+            "{}{}",
+            STEPS_PRESET,
+            r#"
+            [functions.a]
+            steps = [{ wait_notification = "read_level" }]
+            "#
+        );
+        let err = toml::from_str::<Preset>(&preset).unwrap().verify();
+        assert!(err.unwrap_err().contains("must be of type"));
+
+        // commands referencing undefined services are reported
+        let err = toml::from_str::<Preset>(
+            r#"
+            [commands.read_level]
+            command_type = "read"
+            service = "svc"
+            characteristic = "level"
+            "#,
+        )
+        .unwrap()
+        .verify();
+        assert!(err.unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn test_verify_payload_of_writes_only() {
+        let preset = STEPS_PRESET.replace(
+            "command_type = \"notify\"",
+            "command_type = \"notify\"\n        payload = \"hello\"",
+        );
+        let pr = toml::from_str::<Preset>(&preset).unwrap();
+        assert!(pr.verify().is_ok());
+
+        let preset = STEPS_PRESET
+            .replace("{level:u8}", "hello")
+            .replace("set_level level=3", "set_level");
+        let err = toml::from_str::<Preset>(&preset).unwrap().verify();
+        assert!(err.unwrap_err().contains("hello"));
+    }
 }
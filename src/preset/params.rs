@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use super::Preset;
+use crate::utils::payload;
+
+/// Types available for the parameters of a command payload
+pub const PARAM_TYPES: [&str; 13] = [
+    "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "f64", "hex", "b64", "utf8",
+];
+
+/// A parameter of a command, declared in its payload as `{name:type}` or `{name:type=default}`
+#[derive(Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub param_type: String,
+    pub default: Option<String>,
+}
+
+impl Param {
+    fn parse(placeholder: &str) -> Result<Param, String> {
+        let (declaration, default) = match placeholder.split_once('=') {
+            Some((d, default)) => (d, Some(default.trim().to_owned())),
+            None => (placeholder, None),
+        };
+
+        let Some((name, param_type)) = declaration.split_once(':') else {
+            return Err(format!(
+                "invalid parameter '{{{}}}', expected {{name:type}}",
+                placeholder
+            ));
+        };
+        let (name, param_type) = (name.trim(), param_type.trim());
+
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("invalid parameter name: '{}'", name));
+        }
+
+        let base = param_type
+            .strip_suffix("le")
+            .or_else(|| param_type.strip_suffix("be"))
+            .unwrap_or(param_type);
+        if !PARAM_TYPES.contains(&base) {
+            return Err(format!(
+                "parameter '{}' invalid type: '{}'. It must be one of {:?}",
+                name, param_type, PARAM_TYPES
+            ));
+        }
+
+        Ok(Param {
+            name: name.to_owned(),
+            param_type: param_type.to_owned(),
+            default,
+        })
+    }
+
+    /// Payload syntax of a value of the parameter, e.g. `u16le:1200`. The value must be a single
+    /// literal of the type, so that it can't repeat or add bytes
+    fn to_payload(&self, value: &str) -> Result<String, String> {
+        if self.param_type == "utf8" {
+            return Ok(format!("{:?}", value));
+        }
+
+        let invalid = |reason: String| {
+            format!(
+                "Invalid value '{}' for parameter '{}': {}",
+                value, self.name, reason
+            )
+        };
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || "*()\"'".contains(c)) {
+            return Err(invalid(format!(
+                "expected a single {} value",
+                self.param_type
+            )));
+        }
+
+        let literal = format!("{}:{}", self.param_type, value);
+        payload::parse(&literal).map_err(invalid)?;
+        Ok(literal)
+    }
+
+    /// A valid value of the parameter type, used to check payloads
+    fn sample_value(&self) -> &str {
+        match self.param_type.as_str() {
+            "hex" => "00",
+            "b64" => "AA==",
+            "utf8" => "a",
+            _ => "0",
+        }
+    }
+}
+
+impl std::fmt::Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.default {
+            Some(default) => write!(f, "{}:{}={}", self.name, self.param_type, default),
+            None => write!(f, "{}:{}", self.name, self.param_type),
+        }
+    }
+}
+
+/// Split a payload into its literal parts and its placeholders
+fn split_placeholders(payload: &str) -> Result<Vec<(bool, &str)>, String> {
+    let mut parts = Vec::new();
+    let mut rest = payload;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return Err("missing closing brace".to_owned());
+        };
+        parts.push((false, &rest[..start]));
+        parts.push((true, &rest[start + 1..start + end]));
+        rest = &rest[start + end + 1..];
+    }
+
+    if rest.contains('}') {
+        return Err("unexpected closing brace".to_owned());
+    }
+    parts.push((false, rest));
+
+    Ok(parts)
+}
+
+/// Parameters declared in a payload, in order of appearance
+pub fn parse_params(payload: &str) -> Result<Vec<Param>, String> {
+    let mut params: Vec<Param> = Vec::new();
+
+    for (is_placeholder, part) in split_placeholders(payload)? {
+        if !is_placeholder {
+            continue;
+        }
+        let param = Param::parse(part)?;
+
+        match params.iter().find(|p| p.name == param.name) {
+            Some(p) if p.param_type != param.param_type => {
+                return Err(format!(
+                    "parameter '{}' declared with different types",
+                    param.name
+                ))
+            }
+            Some(_) => (),
+            None => params.push(param),
+        }
+    }
+
+    Ok(params)
+}
+
+/// Replace the placeholders of a payload with the arguments or the default values
+pub fn substitute(payload: &str, args: &HashMap<String, String>) -> Result<String, String> {
+    let params = parse_params(payload)?;
+
+    if let Some(name) = args.keys().find(|k| !params.iter().any(|p| &p.name == *k)) {
+        return Err(format!("Unknown parameter: '{}'", name));
+    }
+
+    let mut out = String::new();
+    for (is_placeholder, part) in split_placeholders(payload)? {
+        if !is_placeholder {
+            out.push_str(part);
+            continue;
+        }
+
+        let param = Param::parse(part)?;
+        let Some(value) = args.get(&param.name).or(param.default.as_ref()) else {
+            return Err(format!("Missing parameter: '{}'", param.name));
+        };
+        out.push_str(&param.to_payload(value)?);
+    }
+
+    Ok(out)
+}
+
+/// Check that a payload gives valid bytes with the default or sample values of its parameters
+pub fn verify(payload: &str) -> Result<(), String> {
+    let args: HashMap<String, String> = parse_params(payload)?
+        .iter()
+        .map(|p| {
+            let value = p.default.as_deref().unwrap_or(p.sample_value());
+            (p.name.clone(), value.to_owned())
+        })
+        .collect();
+
+    payload::parse(&substitute(payload, &args)?)?;
+    Ok(())
+}
+
+/// Parse arguments written as `name=value`
+pub fn parse_args(args: &[String]) -> Result<HashMap<String, String>, String> {
+    args.iter()
+        .map(|a| match a.split_once('=') {
+            Some((name, value)) => Ok((name.to_owned(), value.to_owned())),
+            None => Err(format!("Invalid parameter: '{}', expected name=value", a)),
+        })
+        .collect()
+}
+
+/// Split a command of a function like 'set_color r=255 g=0' into its name and arguments
+pub fn split_command(command: &str) -> Result<(&str, HashMap<String, String>), String> {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args: Vec<String> = words.map(|w| w.to_owned()).collect();
+
+    Ok((name, parse_args(&args)?))
+}
+
+impl Preset {
+    /// Parameters of a command, empty if the command has no payload
+    pub fn get_command_params(&self, command_name: &str) -> Vec<Param> {
+        self.commands
+            .as_ref()
+            .and_then(|c| c.get(command_name))
+            .and_then(|c| c.payload.as_ref())
+            .and_then(|p| parse_params(p).ok())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> HashMap<String, String> {
+        parse_args(&list.iter().map(|a| a.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let params = parse_params("0x03 {r:u8} {g:u8=0} {r:u8} {name:utf8}").unwrap();
+        assert_eq!(
+            params
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<String>>(),
+            vec!["r:u8", "g:u8=0", "name:utf8"]
+        );
+
+        assert!(parse_params("0x01").unwrap().is_empty());
+        assert!(parse_params("{r:u24}").is_err());
+        assert!(parse_params("{r}").is_err());
+        assert!(parse_params("{r:u8").is_err());
+        assert!(parse_params("{r:u8} {r:u16}").is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let payload = "0x03 {r:u8} {g:u8=0} {b:u16be=0x100}";
+
+        assert_eq!(
+            substitute(payload, &args(&["r=255"])).unwrap(),
+            "0x03 u8:255 u8:0 u16be:0x100"
+        );
+        assert_eq!(
+            payload::parse(&substitute(payload, &args(&["r=1", "g=2", "b=3"])).unwrap()).unwrap(),
+            vec![0x03, 1, 2, 0, 3]
+        );
+        assert_eq!(
+            substitute("{s:utf8}", &args(&["s=a \"b\""])).unwrap(),
+            "\"a \\\"b\\\"\""
+        );
+
+        // a value is a single literal of the parameter type
+        assert!(substitute(payload, &args(&["r=1*100"])).is_err());
+        assert!(substitute(payload, &args(&["r=1 u8:5"])).is_err());
+        assert!(substitute(payload, &args(&["r=(1)"])).is_err());
+        assert!(substitute(payload, &args(&["r=256"])).is_err());
+        assert!(substitute(payload, &args(&["r="])).is_err());
+
+        assert!(substitute(payload, &args(&[])).is_err());
+        assert!(substitute(payload, &args(&["r=1", "x=2"])).is_err());
+        assert!(parse_args(&["r".to_owned()]).is_err());
+
+        let (name, a) = split_command("set_color r=255 g=0").unwrap();
+        assert_eq!(name, "set_color");
+        assert_eq!(a, args(&["r=255", "g=0"]));
+    }
+
+    #[test]
+    fn test_verify() {
+        assert!(verify("0x03 {r:u8} {g:u8=0}").is_ok());
+        assert!(verify("{r:u8=256}").is_err());
+        assert!(verify("0xzz {r:u8}").is_err());
+    }
+}
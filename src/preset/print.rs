@@ -1,6 +1,48 @@
-use super::Preset;
+use super::{Preset, Step};
 use comfy_table::{Attribute, Cell, Table};
 
+/// Describe the steps of a function, one per line, nested steps are indented
+fn steps_lines(steps: &[Step], indent: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let pad = "  ".repeat(indent);
+
+    for step in steps {
+        match step {
+            Step::Command { command } => lines.push(format!("{}command {}", pad, command)),
+            Step::Call { call } => lines.push(format!("{}call {}", pad, call)),
+            Step::WaitNotification {
+                wait_notification,
+                timeout_ms,
+            } => lines.push(match timeout_ms {
+                Some(t) => format!("{}wait_notification {} ({} ms)", pad, wait_notification, t),
+                None => format!("{}wait_notification {}", pad, wait_notification),
+            }),
+            Step::Delay { delay_ms } => lines.push(format!("{}delay {} ms", pad, delay_ms)),
+            Step::Repeat { repeat, steps } => {
+                lines.push(format!("{}repeat {}", pad, repeat));
+                lines.extend(steps_lines(steps, indent + 1));
+            }
+            Step::AssertRead {
+                assert_read,
+                equals,
+                on_success,
+                on_failure,
+            } => {
+                lines.push(format!("{}assert_read {} == {}", pad, assert_read, equals));
+                if let Some(steps) = on_success {
+                    lines.push(format!("{}on_success", pad));
+                    lines.extend(steps_lines(steps, indent + 1));
+                }
+                if let Some(steps) = on_failure {
+                    lines.push(format!("{}on_failure", pad));
+                    lines.extend(steps_lines(steps, indent + 1));
+                }
+            }
+        }
+    }
+    lines
+}
+
 impl Preset {
     pub fn print(&self) {
         let mut table = Table::new();
@@ -35,6 +77,18 @@ impl Preset {
                         vec_service[0].push_str("\n\nCharacteristic:\n");
                         vec_service[0].push_str(" - Name:\n - UUID");
                         vec_service[1].push_str(&format!("\n\n\n{}\n{}", key, charac.uuid));
+
+                        if let Some(fields) = &charac.fields {
+                            vec_service[0].push_str("\n - Fields");
+                            vec_service[1].push_str(&format!(
+                                "\n{}",
+                                fields
+                                    .iter()
+                                    .map(|f| f.name())
+                                    .collect::<Vec<&str>>()
+                                    .join(", ")
+                            ));
+                        }
                     }
                 }
                 table.add_row(vec_service);
@@ -56,12 +110,9 @@ impl Preset {
                     col2.push_str(&format!("\n{}", data.payload.as_ref().unwrap()));
                 }
 
-                if data.command_type == "read"
-                    || data.command_type == "indicate"
-                    || data.command_type == "notify"
-                {
+                if let Some(format) = &data.format {
                     col1.push_str("\nFormat");
-                    col2.push_str(&format!("\n{}", data.format));
+                    col2.push_str(&format!("\n{}", format));
                 }
 
                 table.add_row(vec![col1, col2]);
@@ -73,8 +124,8 @@ impl Preset {
             table.add_row(vec![Cell::new("Functions").add_attribute(Attribute::Bold)]);
             for (key, data) in functions {
                 table.add_row(vec![
-                    "Name\nDelays\nCommands",
-                    &format!("{}\n{:?}\n{:?}", key, data.commands_delay_ms, data.commands),
+                    "Name\nSteps".to_owned(),
+                    format!("{}\n{}", key, steps_lines(&data.steps, 0).join("\n")),
                 ]);
             }
         }
@@ -100,12 +151,22 @@ impl Preset {
                     col2.push_str(&format!("\n{}", data.payload.as_ref().unwrap()));
                 }
 
-                if data.command_type == "read"
-                    || data.command_type == "indicate"
-                    || data.command_type == "notify"
-                {
+                let params = self.get_command_params(key);
+                if !params.is_empty() {
+                    col1.push_str("\nParameters");
+                    col2.push_str(&format!(
+                        "\n{}",
+                        params
+                            .iter()
+                            .map(|p| p.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ));
+                }
+
+                if let Some(format) = &data.format {
                     col1.push_str("\nFormat");
-                    col2.push_str(&format!("\n{}", data.format));
+                    col2.push_str(&format!("\n{}", format));
                 }
 
                 table.add_row(vec![col1, col2]);
@@ -1,18 +1,30 @@
-use super::Preset;
+use super::{params, Command, Preset, Step};
 
+use crate::bluetooth_numbers::characteristic_decoders;
 use crate::controllers;
 use crate::repl::commands;
+use crate::utils::output::Output;
+use crate::utils::{payload, print_bytes};
+use std::collections::HashMap;
 use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
 
 use std::time::Duration;
 use tokio::time;
 
+/// Steps are run recursively so their future must be boxed
+type StepsFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>>;
+
+/// Timeout of the wait_notification steps when none is given
+const DEFAULT_WAIT_TIMEOUT_MS: u64 = 5000;
+
 impl Preset {
-    pub async fn run_command(
+    /// Get a command, the uuids of its service and characteristic and the formatter of its values
+    fn get_command_target(
         &self,
-        bt: &mut dyn controllers::BleController,
         command_name: &str,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(&Command, String, String, print_bytes::Formatter), Box<dyn Error>> {
         // check if there are no commands in preset
         if self.commands.is_none() {
             Err("No commands in preset")?;
@@ -41,6 +53,37 @@ impl Preset {
             .uuid
             .clone();
 
+        // values are printed with the format of the command, the layout of the characteristic
+        // or the decoder of a standard characteristic
+        let formatter = match &command.format {
+            Some(format) => print_bytes::formatter(format),
+            None => self
+                .get_formatter(&service_uuid, &characteristic_uuid)
+                .or_else(|| {
+                    characteristic_decoders::get_characteristic_formatter(&characteristic_uuid)
+                })
+                .unwrap_or_else(|| print_bytes::formatter("hex")),
+        };
+
+        Ok((command, service_uuid, characteristic_uuid, formatter))
+    }
+
+    pub async fn run_command(
+        &self,
+        bt: &mut dyn controllers::BleController,
+        command_name: &str,
+        args: &HashMap<String, String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (command, service_uuid, characteristic_uuid, formatter) =
+            self.get_command_target(command_name)?;
+
+        // fill the parameters of the payload with the arguments
+        let payload = match &command.payload {
+            Some(payload) => params::substitute(payload, args)?,
+            None if !args.is_empty() => Err("This command has no parameters")?,
+            None => String::new(),
+        };
+
         // execute command
         match command.command_type.as_str() {
             "write" => {
@@ -48,7 +91,8 @@ impl Preset {
                     bt,
                     &service_uuid,
                     &characteristic_uuid,
-                    command.payload.as_ref().unwrap(),
+                    None,
+                    &payload,
                     false,
                 )
                 .await?;
@@ -58,25 +102,40 @@ impl Preset {
                     bt,
                     &service_uuid,
                     &characteristic_uuid,
-                    command.payload.as_ref().unwrap(),
+                    None,
+                    &payload,
                     true,
                 )
                 .await?;
             }
             "read" => {
-                commands::read::read(bt, &service_uuid, &characteristic_uuid, &command.format)
-                    .await?;
+                let value =
+                    commands::read::read(bt, &service_uuid, &characteristic_uuid, None).await?;
+                commands::read::print_value(
+                    &service_uuid,
+                    &characteristic_uuid,
+                    &value,
+                    &formatter,
+                    Output::Text,
+                );
             }
             "notify" => {
-                commands::notify::notify(bt, &service_uuid, &characteristic_uuid, &command.format)
-                    .await?;
+                commands::notify::notify(
+                    bt,
+                    &service_uuid,
+                    &characteristic_uuid,
+                    formatter,
+                    Output::Text,
+                )
+                .await?;
             }
             "indicate" => {
                 commands::indicate::indicate(
                     bt,
                     &service_uuid,
                     &characteristic_uuid,
-                    &command.format,
+                    formatter,
+                    Output::Text,
                 )
                 .await?;
             }
@@ -105,12 +164,94 @@ impl Preset {
             return Err(format!("Command not found {}", function_name))?;
         };
 
-        // run function
-        for (index, command_name) in function.commands.iter().enumerate() {
-            println!("Running {} ...", command_name);
-            self.run_command(bt, command_name).await?;
-            println!("Waiting {} ms", function.commands_delay_ms[index]);
-            time::sleep(Duration::from_millis(function.commands_delay_ms[index])).await;
+        self.run_steps(bt, &function.steps).await
+    }
+
+    fn run_steps<'a>(
+        &'a self,
+        bt: &'a mut dyn controllers::BleController,
+        steps: &'a [Step],
+    ) -> StepsFuture<'a> {
+        Box::pin(async move {
+            for step in steps {
+                self.run_step(bt, step).await?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn run_step(
+        &self,
+        bt: &mut dyn controllers::BleController,
+        step: &Step,
+    ) -> Result<(), Box<dyn Error>> {
+        match step {
+            Step::Command { command } => {
+                eprintln!("Running {} ...", command);
+                let (command_name, args) = params::split_command(command)?;
+                self.run_command(bt, command_name, &args).await?;
+            }
+            Step::Call { call } => {
+                eprintln!("Calling {} ...", call);
+                self.run_function(bt, call).await?;
+            }
+            Step::WaitNotification {
+                wait_notification,
+                timeout_ms,
+            } => {
+                let (_, service_uuid, characteristic_uuid, formatter) =
+                    self.get_command_target(wait_notification)?;
+                let timeout_ms = timeout_ms.unwrap_or(DEFAULT_WAIT_TIMEOUT_MS);
+
+                eprintln!("Waiting notification of {} ...", wait_notification);
+                let value = bt
+                    .wait_notification(
+                        &service_uuid,
+                        &characteristic_uuid,
+                        Duration::from_millis(timeout_ms),
+                    )
+                    .await?;
+                println!("Received {}", formatter(&value));
+            }
+            Step::Delay { delay_ms } => {
+                eprintln!("Waiting {} ms", delay_ms);
+                time::sleep(Duration::from_millis(*delay_ms)).await;
+            }
+            Step::Repeat { repeat, steps } => {
+                for index in 0..*repeat {
+                    eprintln!("Iteration {}/{}", index + 1, repeat);
+                    self.run_steps(bt, steps).await?;
+                }
+            }
+            Step::AssertRead {
+                assert_read,
+                equals,
+                on_success,
+                on_failure,
+            } => {
+                let (_, service_uuid, characteristic_uuid, formatter) =
+                    self.get_command_target(assert_read)?;
+                let expected = payload::parse(equals)?;
+                let value = bt.read(&service_uuid, &characteristic_uuid).await?;
+
+                if value == expected {
+                    println!("{}: {} OK", assert_read, formatter(&value));
+                    if let Some(steps) = on_success {
+                        self.run_steps(bt, steps).await?;
+                    }
+                } else {
+                    println!(
+                        "{}: {} doesn't match {}",
+                        assert_read,
+                        formatter(&value),
+                        formatter(&expected)
+                    );
+                    match on_failure {
+                        Some(steps) => self.run_steps(bt, steps).await?,
+                        None => Err(format!("Assertion failed on '{}'", assert_read))?,
+                    }
+                }
+            }
         }
 
         Ok(())
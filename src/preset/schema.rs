@@ -0,0 +1,74 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject};
+
+use super::decode::FIELD_TYPES;
+use super::{Preset, COMMAND_TYPES};
+use crate::config::FORMATS;
+
+/// Schema of a string that must be one of a list of values
+fn string_enum<I: IntoIterator<Item = String>>(values: I) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(values.into_iter().map(serde_json::Value::from).collect()),
+        ..Default::default()
+    }
+    .into()
+}
+
+pub(super) fn command_type(_: &mut SchemaGenerator) -> Schema {
+    string_enum(COMMAND_TYPES.map(String::from))
+}
+
+pub(super) fn format(_: &mut SchemaGenerator) -> Schema {
+    string_enum(FORMATS.map(String::from))
+}
+
+/// Types of the fields of a layout, with the 'le' and 'be' suffixes of numbers
+pub(super) fn field_type(_: &mut SchemaGenerator) -> Schema {
+    string_enum(FIELD_TYPES.iter().flat_map(|t| {
+        let suffixes: &[&str] = match *t {
+            "u8" | "i8" | "bool" | "utf8" | "bytes" => &[""],
+            _ => &["", "le", "be"],
+        };
+        suffixes.iter().map(move |s| format!("{}{}", t, s))
+    }))
+}
+
+/// JSON Schema of the preset format
+pub fn preset_schema() -> RootSchema {
+    schemars::schema_for!(Preset)
+}
+
+/// JSON Schema of the preset format, pretty printed
+pub fn preset_schema_json() -> String {
+    serde_json::to_string_pretty(&preset_schema()).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shipped_schema_is_up_to_date() {
+        let shipped = std::fs::read_to_string("presets/preset.schema.json").unwrap();
+        assert_eq!(
+            shipped,
+            preset_schema_json(),
+            "run 'bluerepl schema > presets/preset.schema.json'"
+        );
+    }
+
+    #[test]
+    fn test_schema_enumerations() {
+        let schema = serde_json::to_value(preset_schema()).unwrap();
+        let command = &schema["definitions"]["Command"]["properties"];
+
+        assert_eq!(command["command_type"]["enum"][5], "unsubscribe");
+        assert_eq!(command["format"]["enum"][4], "hexdump");
+        assert!(schema["definitions"]["Command"]["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|r| r != "format"));
+    }
+}
@@ -1,70 +1,171 @@
-use super::Preset;
+use super::{params, Command, Preset, Step, COMMAND_TYPES};
+use crate::config::FORMATS;
+use crate::utils::payload;
+use std::collections::HashMap;
 
 impl Preset {
-    pub fn is_autoconnect_possible(&self) -> bool {
-        let Some(device) = &self.device else {
-            return false;
+    /// Check that a command run by a function exists and is of one of the expected types
+    fn verify_step_command<'a>(
+        &'a self,
+        cmd_name: &str,
+        command_types: &[&str],
+    ) -> Result<&'a Command, String> {
+        let Some(cmd_data) = self.commands.as_ref().and_then(|c| c.get(cmd_name)) else {
+            return Err(format!("command '{}' doesn't exits", cmd_name));
         };
-        if device.name.is_none() && device.address.is_none() {
-            return false;
+
+        if !command_types.is_empty() && !command_types.contains(&cmd_data.command_type.as_str()) {
+            return Err(format!(
+                "command '{}' must be of type {:?}",
+                cmd_name, command_types
+            ));
+        }
+        Ok(cmd_data)
+    }
+
+    fn verify_steps(&self, steps: &[Step]) -> Result<(), String> {
+        for step in steps {
+            match step {
+                Step::Command { command } => {
+                    let (cmd_name, args) = params::split_command(command)
+                        .map_err(|e| format!("command '{}': {}", command, e))?;
+                    let cmd_data = self.verify_step_command(cmd_name, &[])?;
+
+                    // check the arguments given to parameterised commands
+                    let payload = match &cmd_data.payload {
+                        Some(pl) if cmd_data.command_type == "write" => {
+                            params::substitute(pl, &args).and_then(|pl| payload::parse(&pl))
+                        }
+                        Some(pl) => params::substitute(pl, &args).map(|_| Vec::new()),
+                        None if !args.is_empty() => {
+                            Err("this command has no parameters".to_owned())
+                        }
+                        None => Ok(Vec::new()),
+                    };
+                    if let Err(e) = payload {
+                        return Err(format!("command '{}': {}", command, e));
+                    }
+                }
+                Step::Call { call } => {
+                    if !self
+                        .functions
+                        .as_ref()
+                        .is_some_and(|f| f.contains_key(call))
+                    {
+                        return Err(format!("function '{}' doesn't exits", call));
+                    }
+                }
+                Step::WaitNotification {
+                    wait_notification, ..
+                } => {
+                    self.verify_step_command(wait_notification, &["notify", "indicate"])?;
+                }
+                Step::Delay { .. } => (),
+                Step::Repeat { steps, .. } => self.verify_steps(steps)?,
+                Step::AssertRead {
+                    assert_read,
+                    equals,
+                    on_success,
+                    on_failure,
+                } => {
+                    self.verify_step_command(assert_read, &["read"])?;
+                    if let Err(e) = payload::parse(equals) {
+                        return Err(format!("invalid value '{}': {}", equals, e));
+                    }
+                    self.verify_steps(on_success.as_deref().unwrap_or_default())?;
+                    self.verify_steps(on_failure.as_deref().unwrap_or_default())?;
+                }
+            }
         }
-        true
+        Ok(())
+    }
+
+    /// Return true if the steps call the function, directly or through other functions
+    fn calls_function<'a>(
+        &'a self,
+        steps: &'a [Step],
+        function_name: &str,
+        visited: &mut Vec<&'a str>,
+    ) -> bool {
+        steps.iter().any(|step| match step {
+            Step::Call { call } if call == function_name => true,
+            Step::Call { call } if !visited.contains(&call.as_str()) => {
+                visited.push(call);
+                match self.functions.as_ref().and_then(|f| f.get(call)) {
+                    Some(f) => self.calls_function(&f.steps, function_name, visited),
+                    None => false,
+                }
+            }
+            Step::Repeat { steps, .. } => self.calls_function(steps, function_name, visited),
+            Step::AssertRead {
+                on_success,
+                on_failure,
+                ..
+            } => {
+                self.calls_function(
+                    on_success.as_deref().unwrap_or_default(),
+                    function_name,
+                    visited,
+                ) || self.calls_function(
+                    on_failure.as_deref().unwrap_or_default(),
+                    function_name,
+                    visited,
+                )
+            }
+            _ => false,
+        })
     }
 
-    pub fn verify(&self) {
+    pub fn is_autoconnect_possible(&self) -> bool {
+        self.device.as_ref().is_some_and(|d| d.is_identifiable())
+    }
+
+    pub fn verify(&self) -> Result<(), String> {
+        if let Some(device) = &self.device {
+            device.verify()?;
+        }
+
         // check if services and characteristics typed in commands descriptions are defined in
         // the preset
         if let Some(commands) = &self.commands {
+            let no_services = HashMap::new();
+            let services = self.services.as_ref().unwrap_or(&no_services);
+
             // check services
             for cmd in commands {
-                if !self.services.as_ref().unwrap().contains_key(&cmd.1.service) {
-                    panic!(
+                if !services.contains_key(&cmd.1.service) {
+                    return Err(format!(
                         "Service '{}' in command '{}' not found",
                         cmd.1.service, cmd.0
-                    );
+                    ));
                 }
 
                 // check characteristics
-                for ser in self.services.as_ref().unwrap() {
+                for ser in services {
                     if cmd.1.service == *ser.0
                         && !ser
                             .1
                             .characteristics
                             .as_ref()
-                            .unwrap()
-                            .contains_key(&cmd.1.characteristic)
+                            .is_some_and(|c| c.contains_key(&cmd.1.characteristic))
                     {
-                        panic!(
+                        return Err(format!(
                             "Characteristic '{}' in command '{}' not found",
                             cmd.1.characteristic, cmd.0
-                        );
+                        ));
                     }
                 }
             }
         }
 
-        // check that the function commands array is the same length as the function delay array
+        // check the steps of the functions
         if let Some(functions) = &self.functions {
-            for f in functions {
-                if f.1.commands.len() != f.1.commands_delay_ms.len() {
-                    panic!("In function {} 'commands' and 'commands_delay_ms' don't have the same length", f.0)
+            for (name, function) in functions {
+                if let Err(e) = self.verify_steps(&function.steps) {
+                    return Err(format!("In function '{}' {}", name, e));
                 }
-            }
-        }
-
-        // check that commands used in functions exist
-        if let Some(functions) = &self.functions {
-            for f in functions {
-                for cmd_name in &f.1.commands {
-                    if !self
-                        .commands
-                        .as_ref()
-                        .unwrap()
-                        .iter()
-                        .any(|c| c.0 == cmd_name)
-                    {
-                        panic!("In function '{}' command '{}' doesn't exits", f.0, cmd_name);
-                    }
+                if self.calls_function(&function.steps, name, &mut Vec::new()) {
+                    return Err(format!("Function '{}' calls itself", name));
                 }
             }
         }
@@ -74,47 +175,64 @@ impl Preset {
         if let Some(commands) = &self.commands {
             for (cmd_name, cmd_data) in commands {
                 if cmd_data.command_type == "write" && cmd_data.payload.is_none() {
-                    panic!("In command '{}' missing payload", cmd_name);
+                    return Err(format!("In command '{}' missing payload", cmd_name));
+                }
+
+                // the payload of the other command types is not used
+                if let (Some(pl), "write") = (&cmd_data.payload, cmd_data.command_type.as_str()) {
+                    if let Err(e) = params::verify(pl) {
+                        return Err(format!("In command '{}' invalid payload: {}", cmd_name, e));
+                    }
                 }
             }
         }
 
         // check that the command_type field in commands exists
         if let Some(commands) = &self.commands {
-            let available_commands_types: Vec<&str> = vec![
-                "write",
-                "write_with_resp",
-                "read",
-                "notify",
-                "indicate",
-                "unsubscribe",
-            ];
-
             for (cmd_name, cmd_data) in commands {
-                if !available_commands_types
-                    .iter()
-                    .any(|c| *c == cmd_data.command_type)
-                {
-                    panic!(
+                if !COMMAND_TYPES.contains(&cmd_data.command_type.as_str()) {
+                    return Err(format!(
                         "In command '{}' invalid command_type: '{}'. It must be one of {:?}",
-                        cmd_name, cmd_data.command_type, available_commands_types
-                    );
+                        cmd_name, cmd_data.command_type, COMMAND_TYPES
+                    ));
                 }
             }
         }
 
         // check that the format field in commands exists
         if let Some(commands) = &self.commands {
-            let available_formats: Vec<&str> = vec!["bin", "dec", "hex", "text", "hexdump"];
-
             for (cmd_name, cmd_data) in commands {
-                if !available_formats.iter().any(|c| *c == cmd_data.format) {
-                    panic!(
+                let Some(format) = &cmd_data.format else {
+                    continue;
+                };
+                if !FORMATS.contains(&format.as_str()) {
+                    return Err(format!(
                         "In command '{}' invalid format: '{}'. It must be one of {:?}",
-                        cmd_name, cmd_data.format, available_formats
-                    );
+                        cmd_name, format, FORMATS
+                    ));
                 }
             }
         }
+
+        // check the layouts of the characteristics values
+        if let Some(services) = &self.services {
+            for (ser_name, ser_data) in services {
+                let Some(characteristics) = &ser_data.characteristics else {
+                    continue;
+                };
+                for (char_name, char_data) in characteristics {
+                    for field in char_data.fields.iter().flatten() {
+                        if let Err(e) = field.verify() {
+                            return Err(format!(
+                                "In characteristic '{}' of service '{}' {}",
+                                char_name, ser_name, e
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
     }
 }
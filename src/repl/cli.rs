@@ -1,5 +1,21 @@
 use clap::{arg, Arg, Command};
 
+use crate::config;
+use crate::utils::output;
+
+/// -o flag of the commands which can print their results as json
+fn output_arg() -> Arg {
+    arg!(-o --output <output> "Print the results as text or json [default: json with --json]")
+        .required(false)
+        .value_parser(output::OUTPUTS)
+}
+
+/// Address of a peripheral seen before, to use its cached gatt without being connected
+fn cached_device_arg() -> Arg {
+    arg!(-d --device <address> "Use the cached gatt of a peripheral seen before instead of the connected one")
+        .required(false)
+}
+
 pub fn cli() -> Command {
     // strip out usage
     const PARSER_TEMPLATE: &str = "\
@@ -27,6 +43,15 @@ pub fn cli() -> Command {
             .about("Clear the terminal")
             .help_template(COMMAND_TEMPLATE))
 
+        // mtu
+        .subcommand(
+            Command::new("mtu")
+            .about("Print the ATT MTU of the connection or request a new one")
+            .args(&[
+                Arg::new("request").help("MTU to request, when the backend supports it").required(false).value_parser(clap::value_parser!(u16).range(23..)),
+                output_arg(),
+            ]).help_template(COMMAND_TEMPLATE))
+
         // sleep
         .subcommand(
             Command::new("sleep")
@@ -48,9 +73,13 @@ pub fn cli() -> Command {
             .about("Write a value to a characteristic")
             .args(&[
                 arg!(-r --resp ... "Write with response (default write is write without response)"),
-                Arg::new("service").help("The service that contains the characteristic to write").required(true),
-                Arg::new("characteristic").help("The characteristic to write").required(true),
-                Arg::new("payload").help("The payload to write").required(true)
+                Arg::new("service").help("The service that contains the characteristic to write, or #<handle> of the characteristic value").required(true),
+                Arg::new("characteristic").help("The characteristic to write (the payload when a handle is given)").required(false),
+                Arg::new("payload").help("The payload to write").required(false),
+                // no --stdin like the one-shot command, stdin holds the commands of the repl
+                arg!(--file <path> "Write the content of a file in chunks instead of a payload").required(false),
+                arg!(--"chunk-size" <bytes> "Size of the chunks written [default: the MTU minus 3, or 20]").required(false).value_parser(clap::value_parser!(usize)),
+                arg!(--delay <ms> "Time to wait between two chunks in milliseconds [default: 0]").required(false).value_parser(clap::value_parser!(u64)),
             ]).help_template(COMMAND_TEMPLATE))
 
         // read
@@ -58,9 +87,10 @@ pub fn cli() -> Command {
             Command::new("read")
             .about("Read the value of a characteristic")
             .args(&[
-                Arg::new("service").help("The service that contains the characteristic to write").required(true),
-                Arg::new("characteristic").help("The characteristic to write").required(true),
-                arg!(-f --format ... "Format to print the read value").required(false).value_name("format").default_value("hex").value_parser(["bin", "hex", "dec", "text", "hexdump"]),
+                Arg::new("service").help("The service that contains the characteristic to read, or #<handle> of the characteristic value").required(true),
+                Arg::new("characteristic").help("The characteristic to read").required(false),
+                arg!(-f --format <format> "Format to print the read value").required(false).value_parser(config::FORMATS),
+                output_arg(),
             ]).help_template(COMMAND_TEMPLATE))
 
         // scan
@@ -68,9 +98,10 @@ pub fn cli() -> Command {
             Command::new("scan")
             .about("Search for BLE devices around")
             .args(&[
-                Arg::new("timeout").help("Time to scan in seconds").required(false).default_value("5").value_parser(clap::value_parser!(usize)),
+                Arg::new("timeout").help("Time to scan in seconds [default: scan_timeout in config]").required(false).value_parser(clap::value_parser!(usize)),
                 arg!(-a --all ... "Show unnamed peripheral"),
                 arg!(-l --list ... "Show last scan list (doesn't run a new scan)"),
+                output_arg(),
             ]).help_template(COMMAND_TEMPLATE))
 
         // info
@@ -79,8 +110,28 @@ pub fn cli() -> Command {
             .subcommand_required(true)
             .about("Print informations about a specified topic")
             .subcommands(vec![
-                Command::new("adapter").about("Print informations about BLE adapter in use"),
-                Command::new("gatt").about("Print informations about the gatt of the connected peripheral"),
+                Command::new("adapter").about("Print informations about BLE adapter in use").arg(output_arg()),
+                Command::new("gatt").about("Print informations about the gatt of the connected peripheral").args(&[output_arg(), cached_device_arg()]),
+            ]),
+            ).help_template(COMMAND_TEMPLATE)
+
+        // gatt
+        .subcommand(
+            Command::new("gatt")
+            .subcommand_required(true)
+            .about("Save, compare or refresh the gatt of the connected peripheral")
+            .subcommands(vec![
+                Command::new("save").about("Write the gatt and the readable values of the connected peripheral to a json file").args(&[
+                    Arg::new("file").help("Path of the json file to create").required(true),
+                    arg!(--force "Overwrite the file if it exists"),
+                    ],
+                ),
+                Command::new("diff").about("Print the attributes added, removed or changed between two saved gatts").args(&[
+                    Arg::new("a").help("Path of the first saved gatt").required(true),
+                    Arg::new("b").help("Path of the second saved gatt, or 'live' for the connected peripheral").required(true),
+                    ],
+                ),
+                Command::new("refresh").about("Discover the services and characteristics of the connected peripheral again"),
             ]),
             ).help_template(COMMAND_TEMPLATE)
 
@@ -89,9 +140,9 @@ pub fn cli() -> Command {
             Command::new("connect")
             .about("Connect to a BLE peripheral")
             .args(&[
-                arg!(-n --name ... "Connection using the name of the peripheral").exclusive(true).required(true),
-                arg!(-m --mac ... "Connection using the mac address of the peripheral").exclusive(true).required(true),
-                arg!(-i --id ... "Connection using the id of the peripheral in the scan list").exclusive(true).required(true).value_parser(clap::value_parser!(usize)),
+                arg!(-n --name <name> "Connection using the name of the peripheral").exclusive(true).required(true),
+                arg!(-m --mac <mac> "Connection using the mac address of the peripheral").exclusive(true).required(true),
+                arg!(-i --id <id> "Connection using the id of the peripheral in the scan list").exclusive(true).required(true).value_parser(clap::value_parser!(usize)),
                 Arg::new("identifier").help("Parse identifier and use it to connect with name, mac or id").exclusive(true).required(true),
             ]).help_template(COMMAND_TEMPLATE))
 
@@ -101,6 +152,16 @@ pub fn cli() -> Command {
             .about("Disconnect from BLE peripheral")
             .help_template(COMMAND_TEMPLATE))
 
+        // dfu
+        .subcommand(
+            Command::new("dfu")
+            .about("Update the firmware of the connected peripheral with a Nordic DFU package")
+            .args(&[
+                Arg::new("package").help("Path of the zip package created by nrfutil").required(true),
+                arg!(--prn <packets> "Number of packets between two checksums notified by the peripheral [default: 0, disabled]").required(false).value_parser(clap::value_parser!(u16)),
+                arg!(--timeout <seconds> "Time to wait for each response of the peripheral [default: 10]").required(false).value_parser(clap::value_parser!(u64)),
+            ]).help_template(COMMAND_TEMPLATE))
+
         // indicate
         .subcommand(
             Command::new("indicate")
@@ -108,22 +169,42 @@ pub fn cli() -> Command {
             .args(&[
                 Arg::new("service").help("The service that contains the characteristic to subscribe to").required(true),
                 Arg::new("characteristic").help("The characteristic to subscribe to").required(true),
-                arg!(-f --format ... "Format to print the read value").required(false).value_name("format").default_value("hex").value_parser(["bin", "hex", "dec", "text", "hexdump"]),
+                arg!(-f --format <format> "Format to print the read value").required(false).value_parser(config::FORMATS),
+                output_arg(),
             ]).help_template(COMMAND_TEMPLATE))
 
         // preset
         .subcommand(
             Command::new("preset")
-            .about("Print preset informations or run preset commands/functions")
+            .about("Print, load, export or convert presets, run preset commands/functions")
             .subcommands(vec![
                 Command::new("command").about("Run preset command").args(&[
                     Arg::new("command_name").help("The command to run").required(false),
+                    Arg::new("params").help("Parameters of the command, as name=value").required(false).num_args(1..),
                     ],
                 ),
                 Command::new("function").about("Run preset function").args(&[
                     Arg::new("function_name").help("The function to run").required(true),
                     ],
                 ),
+                Command::new("load").about("Load a preset file").args(&[
+                    Arg::new("file").help("Path of the preset file").required(true),
+                    ],
+                ),
+                Command::new("reload").about("Read the preset file loaded again"),
+                Command::new("unload").about("Unload the preset"),
+                Command::new("export").about("Write a preset describing the gatt of the connected peripheral").args(&[
+                    Arg::new("file").help("Path of the preset file to create (.toml, .json or .yaml)").required(true),
+                    arg!(--force "Overwrite the file if it exists"),
+                    cached_device_arg(),
+                    ],
+                ),
+                Command::new("convert").about("Convert a preset between the toml, json and yaml formats").args(&[
+                    Arg::new("input").help("Path of the preset file to convert").required(true),
+                    Arg::new("output").help("Path of the converted preset, its extension gives the format").required(true),
+                    arg!(--force "Overwrite the output file if it exists"),
+                    ],
+                ),
             ])
             .subcommand_required(false)
             .help_template(COMMAND_TEMPLATE))
@@ -135,9 +216,51 @@ pub fn cli() -> Command {
             .args(&[
                 Arg::new("service").help("The service that contains the characteristic to subscribe to").required(true),
                 Arg::new("characteristic").help("The characteristic to subscribe to").required(true),
-                arg!(-f --format ... "Format to print the read value").required(false).value_name("format").default_value("hex").value_parser(["bin", "hex", "dec", "text", "hexdump"]),
+                arg!(-f --format <format> "Format to print the read value").required(false).value_parser(config::FORMATS),
+                output_arg(),
             ]).help_template(COMMAND_TEMPLATE))
 
+        // smp
+        .subcommand(
+            Command::new("smp")
+            .subcommand_required(true)
+            .about("Manage a peripheral with the Simple Management Protocol (MCUmgr)")
+            .arg(arg!(--timeout <seconds> "Time to wait for each response of the peripheral [default: 10]").required(false).global(true).value_parser(clap::value_parser!(u64)))
+            .arg(arg!(--"write-size" <bytes> "Largest write, bigger requests are split [default: the MTU minus 3, or 20]").required(false).global(true).value_parser(clap::value_parser!(usize)))
+            .subcommands(vec![
+                Command::new("image").about("List, upload, test or confirm firmware images").subcommand_required(true).subcommands(vec![
+                    Command::new("list").about("Print the images in the slots of the peripheral").arg(output_arg()),
+                    Command::new("upload").about("Upload an image, an interrupted upload of the same image resumes").args(&[
+                        Arg::new("file").help("Path of the signed image").required(true),
+                        arg!(--image <number> "Number of the image to upload [default: 0]").required(false).value_parser(clap::value_parser!(u32)),
+                        arg!(--"packet-size" <bytes> "Size of the upload requests [default: the MTU minus 3, at least 128]").required(false).value_parser(clap::value_parser!(usize)),
+                    ]),
+                    Command::new("test").about("Boot an image once at the next reset").args(&[
+                        Arg::new("hash").help("Hash of the image, as printed by smp image list").required(true),
+                        output_arg(),
+                    ]),
+                    Command::new("confirm").about("Boot an image for good, the running one by default").args(&[
+                        Arg::new("hash").help("Hash of the image, as printed by smp image list").required(false),
+                        output_arg(),
+                    ]),
+                ]),
+                Command::new("reset").about("Reset the peripheral"),
+                Command::new("echo").about("Send a text that the peripheral sends back").args(&[
+                    Arg::new("text").help("The text to send").required(true),
+                    output_arg(),
+                ]),
+                Command::new("stat").about("Print the statistics groups, or the counters of one of them").args(&[
+                    Arg::new("name").help("Name of the statistics group").required(false),
+                    output_arg(),
+                ]),
+                Command::new("taskstat").about("Print the statistics of the tasks of the OS").arg(output_arg()),
+                Command::new("shell").about("Run a command of the shell of the peripheral").args(&[
+                    output_arg(),
+                    Arg::new("argv").help("The command and its arguments").required(true).num_args(1..).trailing_var_arg(true).allow_hyphen_values(true),
+                ]),
+            ])
+            .help_template(COMMAND_TEMPLATE))
+
         // unsubscribe
         .subcommand(
             Command::new("unsubscribe")
@@ -10,9 +10,9 @@ pub async fn by_name(
 ) -> Result<(), Box<dyn Error>> {
     match bt.get_scan_list().iter().find(|e| e.name == name) {
         Some(p) => {
-            println!("Connecting with name: {}", name);
+            eprintln!("Connecting with name: {}", name);
             bt.connect(&p.address_uuid).await?;
-            println!("Connected!");
+            eprintln!("Connected!");
         }
         None => Err("Name not found")?,
     }
@@ -25,9 +25,9 @@ pub async fn by_index(
 ) -> Result<(), Box<dyn Error>> {
     match bt.get_scan_list().iter().find(|e| e.id == id) {
         Some(p) => {
-            println!("Connecting with id: {}", id);
+            eprintln!("Connecting with id: {}", id);
             bt.connect(&p.address_uuid).await?;
-            println!("Connected!");
+            eprintln!("Connected!");
         }
         None => Err("Id not found")?,
     }
@@ -40,9 +40,9 @@ pub async fn by_address(
 ) -> Result<(), Box<dyn Error>> {
     match bt.get_scan_list().iter().find(|e| e.address_uuid == addr) {
         Some(p) => {
-            println!("Connecting with address: {}", addr);
+            eprintln!("Connecting with address: {}", addr);
             bt.connect(&p.address_uuid).await?;
-            println!("Connected!");
+            eprintln!("Connected!");
         }
         None => Err("Address not found")?,
     }
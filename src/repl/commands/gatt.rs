@@ -0,0 +1,311 @@
+use crate::controllers;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path;
+
+use crate::controllers::CharacteristicProperties;
+use crate::utils::print_bytes;
+
+/// Discovered database of a peripheral with the values of its readable characteristics
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Snapshot {
+    pub name: String,
+    pub address: String,
+    pub services: Vec<ServiceSnapshot>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ServiceSnapshot {
+    pub uuid: String,
+    pub characteristics: Vec<CharacteristicSnapshot>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CharacteristicSnapshot {
+    pub uuid: String,
+    pub properties: Vec<String>,
+    pub descriptors: Vec<String>,
+    /// Value in hex, only for readable characteristics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// Read the gatt of the connected peripheral and the values of its readable characteristics
+pub async fn snapshot(bt: &mut dyn controllers::BleController) -> Result<Snapshot, Box<dyn Error>> {
+    let infos = bt.get_peripheral_infos().await?;
+    let mut services = Vec::new();
+
+    for s in &infos.services {
+        let mut characteristics = Vec::new();
+
+        for c in &s.characteristics {
+            let mut value = None;
+            if c.properties.contains(CharacteristicProperties::READ) {
+                match bt.read(&s.uuid, &c.uuid).await {
+                    Ok(v) => value = Some(print_bytes::bytes_to_str(&v, "hex")),
+                    Err(e) => println!("Could not read characteristic {}: {}", c.uuid, e),
+                }
+            }
+
+            characteristics.push(CharacteristicSnapshot {
+                uuid: c.uuid.clone(),
+                properties: c
+                    .properties
+                    .iter_names()
+                    .map(|(n, _)| n.to_owned())
+                    .collect(),
+                descriptors: c.descriptors.iter().map(|d| d.uuid.clone()).collect(),
+                value,
+            });
+        }
+
+        services.push(ServiceSnapshot {
+            uuid: s.uuid.clone(),
+            characteristics,
+        });
+    }
+
+    Ok(Snapshot {
+        name: infos.periph_name,
+        address: infos.periph_mac,
+        services,
+    })
+}
+
+fn read_snapshot(fp: &path::Path) -> Result<Snapshot, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(fp)
+        .map_err(|e| format!("Could not read file '{}': {}", fp.to_string_lossy(), e))?;
+    let snapshot = serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid gatt snapshot '{}': {}", fp.to_string_lossy(), e))?;
+    Ok(snapshot)
+}
+
+/// Keys identifying attributes that can share a uuid: the uuid, followed by the index among the
+/// attributes with this uuid for the next ones ('uuid', 'uuid[1]' ...)
+fn keys<'a>(uuids: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    uuids
+        .map(|uuid| {
+            let n = seen.entry(uuid).or_insert(0);
+            let key = match *n {
+                0 => uuid.clone(),
+                n => format!("{}[{}]", uuid, n),
+            };
+            *n += 1;
+            key
+        })
+        .collect()
+}
+
+/// Differences between two lists of uuids: '+ kind uuid' and '- kind uuid'
+fn diff_uuids(kind: &str, parent: &str, a: &[String], b: &[String]) -> Vec<String> {
+    let removed = a.iter().filter(|u| !b.contains(u)).map(|u| ('-', u));
+    let added = b.iter().filter(|u| !a.contains(u)).map(|u| ('+', u));
+    removed
+        .chain(added)
+        .map(|(sign, u)| format!("{} {} {}{}", sign, kind, parent, u))
+        .collect()
+}
+
+fn diff_characteristic(
+    path: &str,
+    a: &CharacteristicSnapshot,
+    b: &CharacteristicSnapshot,
+) -> Vec<String> {
+    let mut diffs = Vec::new();
+
+    if a.properties != b.properties {
+        diffs.push(format!(
+            "~ characteristic {} properties: {:?} -> {:?}",
+            path, a.properties, b.properties
+        ));
+    }
+    diffs.extend(diff_uuids(
+        "descriptor",
+        &format!("{}/", path),
+        &keys(a.descriptors.iter()),
+        &keys(b.descriptors.iter()),
+    ));
+    if a.value != b.value {
+        let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "none".to_owned());
+        diffs.push(format!(
+            "~ characteristic {} value: {} -> {}",
+            path,
+            value(&a.value),
+            value(&b.value)
+        ));
+    }
+    diffs
+}
+
+/// Attributes added (+), removed (-) or changed (~) from a to b. Services and characteristics
+/// are identified by their uuid and their index among the ones with the same uuid
+pub fn diff(a: &Snapshot, b: &Snapshot) -> Vec<String> {
+    let services_a = keys(a.services.iter().map(|s| &s.uuid));
+    let services_b = keys(b.services.iter().map(|s| &s.uuid));
+    let mut diffs = diff_uuids("service", "", &services_a, &services_b);
+
+    for (service, sa) in services_a.iter().zip(&a.services) {
+        let Some(index) = services_b.iter().position(|s| s == service) else {
+            continue;
+        };
+        let sb = &b.services[index];
+
+        let characteristics_a = keys(sa.characteristics.iter().map(|c| &c.uuid));
+        let characteristics_b = keys(sb.characteristics.iter().map(|c| &c.uuid));
+        diffs.extend(diff_uuids(
+            "characteristic",
+            &format!("{}/", service),
+            &characteristics_a,
+            &characteristics_b,
+        ));
+
+        for (characteristic, ca) in characteristics_a.iter().zip(&sa.characteristics) {
+            if let Some(index) = characteristics_b.iter().position(|c| c == characteristic) {
+                diffs.extend(diff_characteristic(
+                    &format!("{}/{}", service, characteristic),
+                    ca,
+                    &sb.characteristics[index],
+                ));
+            }
+        }
+    }
+    diffs
+}
+
+pub async fn save(
+    bt: &mut dyn controllers::BleController,
+    fp: &path::Path,
+) -> Result<(), Box<dyn Error>> {
+    let snapshot = snapshot(bt).await?;
+    std::fs::write(fp, serde_json::to_string_pretty(&snapshot)? + "\n")
+        .map_err(|e| format!("Could not write file '{}': {}", fp.to_string_lossy(), e))?;
+    println!("Gatt written to {}", fp.to_string_lossy());
+    Ok(())
+}
+
+/// Print the differences between a snapshot and another one, or the connected peripheral when
+/// b is 'live'
+pub async fn print_diff(
+    bt: &mut dyn controllers::BleController,
+    a: &path::Path,
+    b: &str,
+) -> Result<(), Box<dyn Error>> {
+    let a = read_snapshot(a)?;
+    let b = if b == "live" {
+        if !bt.is_connected() {
+            Err("You must be connected to a peripheral to compare with the live gatt")?;
+        }
+        snapshot(bt).await?
+    } else {
+        read_snapshot(path::Path::new(b))?
+    };
+
+    let diffs = diff(&a, &b);
+    if diffs.is_empty() {
+        println!("No differences");
+    }
+    for d in diffs {
+        println!("{}", d);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn characteristic(uuid: &str, value: Option<&str>) -> CharacteristicSnapshot {
+        CharacteristicSnapshot {
+            uuid: uuid.to_owned(),
+            properties: vec!["READ".to_owned()],
+            descriptors: vec!["00002902-0000-1000-8000-00805f9b34fb".to_owned()],
+            value: value.map(|v| v.to_owned()),
+        }
+    }
+
+    fn snapshot(services: Vec<(&str, Vec<CharacteristicSnapshot>)>) -> Snapshot {
+        Snapshot {
+            name: "my_device".to_owned(),
+            address: "AA:BB:CC:DD:EE:FF".to_owned(),
+            services: services
+                .into_iter()
+                .map(|(uuid, characteristics)| ServiceSnapshot {
+                    uuid: uuid.to_owned(),
+                    characteristics,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = snapshot(vec![
+            (
+                "s1",
+                vec![
+                    characteristic("c1", Some("[0x01]")),
+                    characteristic("c2", None),
+                ],
+            ),
+            ("s2", vec![]),
+        ]);
+
+        let mut c2 = characteristic("c2", None);
+        c2.properties.push("NOTIFY".to_owned());
+        c2.descriptors.clear();
+        let b = snapshot(vec![
+            (
+                "s1",
+                vec![
+                    characteristic("c1", Some("[0x02]")),
+                    c2,
+                    characteristic("c3", None),
+                ],
+            ),
+            ("s3", vec![]),
+        ]);
+
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                "- service s2",
+                "+ service s3",
+                "+ characteristic s1/c3",
+                "~ characteristic s1/c1 value: [0x01] -> [0x02]",
+                "~ characteristic s1/c2 properties: [\"READ\"] -> [\"READ\", \"NOTIFY\"]",
+                "- descriptor s1/c2/00002902-0000-1000-8000-00805f9b34fb",
+            ]
+        );
+
+        // characteristics sharing a uuid are compared in order
+        let a = snapshot(vec![(
+            "s1",
+            vec![
+                characteristic("c1", Some("[0x01]")),
+                characteristic("c1", Some("[0x02]")),
+            ],
+        )]);
+        let b = snapshot(vec![(
+            "s1",
+            vec![
+                characteristic("c1", Some("[0x01]")),
+                characteristic("c1", Some("[0x03]")),
+                characteristic("c1", None),
+            ],
+        )]);
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                "+ characteristic s1/c1[2]",
+                "~ characteristic s1/c1[1] value: [0x02] -> [0x03]",
+            ]
+        );
+
+        // a snapshot written and read again has no differences
+        let json = serde_json::to_string(&a).unwrap();
+        assert!(diff(&a, &serde_json::from_str(&json).unwrap()).is_empty());
+    }
+}
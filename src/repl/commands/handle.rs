@@ -0,0 +1,100 @@
+use crate::controllers;
+use std::error::Error;
+
+/// Handle of an attribute typed as '#0x002a' or '#42', None if the argument isn't a handle
+pub fn parse(arg: &str) -> Result<Option<u16>, String> {
+    let Some(handle) = arg.strip_prefix('#') else {
+        return Ok(None);
+    };
+
+    let parsed = match handle.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => handle.parse::<u16>(),
+    };
+
+    match parsed {
+        Ok(handle) => Ok(Some(handle)),
+        Err(_) => Err(format!("Invalid handle: '{}'", arg)),
+    }
+}
+
+/// Service and characteristic uuids of the characteristic whose value has this handle
+pub async fn resolve(
+    bt: &mut dyn controllers::BleController,
+    handle: u16,
+) -> Result<(String, String), Box<dyn Error>> {
+    if !bt.is_connected() {
+        Err("You must be connected to a peripheral to run this command")?;
+    }
+
+    let infos = bt.get_peripheral_infos().await?;
+    find(&infos, handle)
+}
+
+fn find(
+    infos: &controllers::BlePeripheralInfo,
+    handle: u16,
+) -> Result<(String, String), Box<dyn Error>> {
+    let mut has_handles = false;
+
+    for s in &infos.services {
+        for c in &s.characteristics {
+            has_handles |= c.handle.is_some();
+            if c.handle == Some(handle) {
+                return Ok((s.uuid.clone(), c.uuid.clone()));
+            }
+        }
+    }
+
+    if !has_handles {
+        Err("The BLE backend doesn't report the attribute handles")?
+    }
+    Err(format!(
+        "No characteristic value with handle 0x{:04x}",
+        handle
+    ))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use controllers::{BlePeripheralInfo, Characteristic, CharacteristicProperties, Service};
+
+    #[test]
+    fn test_parse_and_find_handle() {
+        assert_eq!(parse("#0x002a"), Ok(Some(0x2a)));
+        assert_eq!(parse("#42"), Ok(Some(42)));
+        assert_eq!(parse("180f"), Ok(None));
+        assert!(parse("#0xzz").is_err());
+
+        let characteristic = |uuid: &str, handle| Characteristic {
+            uuid: uuid.to_owned(),
+            properties: CharacteristicProperties::READ,
+            descriptors: vec![],
+            handle,
+        };
+        let mut infos = BlePeripheralInfo {
+            periph_name: "my_device".to_owned(),
+            periph_mac: "AA:BB:CC:DD:EE:FF".to_owned(),
+            rssi: -50,
+            mtu: None,
+            services: vec![Service {
+                uuid: "180f".to_owned(),
+                characteristics: vec![
+                    characteristic("2a19", Some(0x0003)),
+                    characteristic("2a1a", Some(0x0006)),
+                ],
+                handles: Some((0x0001, 0x0007)),
+            }],
+        };
+
+        assert_eq!(
+            find(&infos, 0x0006).unwrap(),
+            ("180f".to_owned(), "2a1a".to_owned())
+        );
+        assert!(find(&infos, 0x0004).is_err());
+
+        infos.services[0].characteristics = vec![characteristic("2a19", None)];
+        assert!(find(&infos, 0x0003).is_err());
+    }
+}
@@ -1,15 +1,21 @@
 use crate::controllers;
+use crate::utils::output::Output;
+use crate::utils::print_bytes::Formatter;
 use std::error::Error;
 
+use super::notify::printer;
+
 pub async fn indicate(
     bt: &mut dyn controllers::BleController,
     service: &str,
     characteristic: &str,
-    format: &str,
+    formatter: Formatter,
+    output: Output,
 ) -> Result<(), Box<dyn Error>> {
     if !bt.is_connected() {
         Err("You must be connected to a peripheral to run this command")?;
     }
 
-    bt.indicate(service, characteristic, format).await
+    bt.indicate(service, characteristic, Some(printer(formatter, output)))
+        .await
 }
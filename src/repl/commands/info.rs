@@ -1,28 +1,156 @@
 use crate::controllers;
 
+use serde::Serialize;
+use serde_json::json;
 use std::error::Error;
 
-use crate::controllers::BlePeripheralInfo;
 use crate::repl::commands;
+use crate::utils::output::{self, Output};
+use crate::utils::print_bytes;
 use comfy_table::{Attribute, Cell, Table};
 
-use crate::bluetooth_numbers::{characteristic_uuids, services_uuids};
+use crate::bluetooth_numbers::{characteristic_decoders, characteristic_uuids, services_uuids};
 use crate::Preset;
 
-async fn print_gatt_infos(
+/// Gatt of the connected peripheral with the names and values of its attributes
+#[derive(Serialize, Debug)]
+pub struct GattInfos {
+    pub name: String,
+    pub address: String,
+    pub rssi: i16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    pub services: Vec<ServiceInfos>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ServiceInfos {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handles: Option<(u16, u16)>,
+    pub characteristics: Vec<CharacteristicInfos>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CharacteristicInfos {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u16>,
+    pub properties: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+
+    /// Value decoded, as text or as hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
+
+    /// Values printed as hex are cropped in tables
+    #[serde(skip)]
+    is_hex: bool,
+}
+
+/// Read the gatt of the connected peripheral, the values of readable characteristics are decoded
+/// with the layout from the preset or the decoder of a standard characteristic
+pub async fn gatt_infos(
     bt: &mut dyn controllers::BleController,
-    infos: &BlePeripheralInfo,
     p: &Option<Preset>,
-) {
+) -> Result<GattInfos, Box<dyn Error>> {
+    let infos = bt.get_peripheral_infos().await?;
+    describe_gatt(&infos, Some(bt), p).await
+}
+
+/// Names of the attributes of a gatt, the values are only read when a controller is given
+async fn describe_gatt(
+    infos: &controllers::BlePeripheralInfo,
+    mut bt: Option<&mut dyn controllers::BleController>,
+    p: &Option<Preset>,
+) -> Result<GattInfos, Box<dyn Error>> {
+    let mut services = Vec::new();
+
+    for s in &infos.services {
+        let mut characteristics = Vec::new();
+
+        for c in &s.characteristics {
+            let identifier = characteristic_uuids::get_characteristic_identifier_from_uuid(&c.uuid);
+            let mut characteristic = CharacteristicInfos {
+                uuid: c.uuid.clone(),
+                handle: c.handle,
+                properties: c
+                    .properties
+                    .iter_names()
+                    .map(|(n, _)| n.to_owned())
+                    .collect(),
+                name: characteristic_uuids::get_characteristic_name_from_uuid(&s.uuid, &c.uuid, p),
+                identifier: identifier.clone(),
+                value: None,
+                bytes: None,
+                is_hex: false,
+            };
+
+            if let Some(bt) = bt.as_deref_mut().filter(|_| {
+                c.properties
+                    .contains(controllers::CharacteristicProperties::READ)
+            }) {
+                let bytes = commands::read::read(bt, &s.uuid, &c.uuid, c.handle).await?;
+
+                let value = if let Some(formatter) = p
+                    .as_ref()
+                    .and_then(|p| p.get_formatter(&s.uuid, &c.uuid))
+                    .or_else(|| characteristic_decoders::get_characteristic_formatter(&c.uuid))
+                {
+                    formatter(&bytes)
+                } else if identifier.as_ref().is_some_and(|i| i.contains("string")) {
+                    print_bytes::bytes_to_str(&bytes, "text")
+                } else {
+                    characteristic.is_hex = true;
+                    print_bytes::bytes_to_str(&bytes, "hex")
+                };
+
+                characteristic.value = Some(value);
+                characteristic.bytes = Some(bytes);
+            }
+            characteristics.push(characteristic);
+        }
+
+        services.push(ServiceInfos {
+            uuid: s.uuid.clone(),
+            name: services_uuids::get_service_name_from_uuid(&s.uuid, p),
+            identifier: services_uuids::get_service_identifier_from_uuid(&s.uuid),
+            handles: s.handles,
+            characteristics,
+        });
+    }
+
+    Ok(GattInfos {
+        name: infos.periph_name.clone(),
+        address: infos.periph_mac.clone(),
+        rssi: infos.rssi,
+        mtu: infos.mtu,
+        services,
+    })
+}
+
+fn print_gatt_table(infos: &GattInfos) {
     let mut table = Table::new();
 
     table.set_header(vec![
         Cell::new("Peripheral:").add_attribute(Attribute::Bold),
-        Cell::new(&infos.periph_name),
+        Cell::new(&infos.name),
     ]);
 
-    table.add_row(vec!["Device address", &infos.periph_mac.to_string()]);
+    table.add_row(vec!["Device address", &infos.address]);
     table.add_row(vec!["RSSI", &format!("{}", infos.rssi,)]);
+    if let Some(mtu) = infos.mtu {
+        table.add_row(vec!["MTU", &format!("{}", mtu)]);
+    }
 
     table.add_row(vec![Cell::new("Service(s)").add_attribute(Attribute::Bold)]);
 
@@ -31,65 +159,55 @@ async fn print_gatt_infos(
         let mut fmt_service: String = s.uuid.to_string();
 
         // Service name
-        if let Some(name) = services_uuids::get_service_name_from_uuid(&s.uuid, p) {
+        if let Some(name) = &s.name {
             fmt_service.push_str(&format!("\n{}", name));
             str_service.push_str("\nName");
         }
 
         // Service identifier
-        if let Some(identifier) = services_uuids::get_service_identifier_from_uuid(&s.uuid) {
+        if let Some(identifier) = &s.identifier {
             fmt_service.push_str(&format!("\n{}", identifier));
             str_service.push_str("\nIdentifier");
         }
 
+        // Service handles
+        if let Some((start, end)) = s.handles {
+            fmt_service.push_str(&format!("\n0x{:04x} - 0x{:04x}", start, end));
+            str_service.push_str("\nHandles");
+        }
+
         let mut vec_service: Vec<String> = vec![str_service, fmt_service];
 
-        for c in &s.characteriscics {
+        for c in &s.characteristics {
             vec_service[0].push_str("\n\nCharacteristic:\n");
             vec_service[0].push_str(" - UUID:\n - Properties");
-            vec_service[1].push_str(&format!("\n\n\n{}\n{:?}", c.uuid, c.properties));
+            vec_service[1].push_str(&format!("\n\n\n{}\n{}", c.uuid, c.properties.join(" | ")));
+
+            // Characteristic value handle
+            if let Some(handle) = c.handle {
+                vec_service[0].push_str("\n - Handle");
+                vec_service[1].push_str(&format!("\n0x{:04x}", handle))
+            }
 
             // Characteristic name
-            if let Some(name) =
-                characteristic_uuids::get_characteristic_name_from_uuid(&s.uuid, &c.uuid, p)
-            {
+            if let Some(name) = &c.name {
                 vec_service[0].push_str("\n - Name");
                 vec_service[1].push_str(&format!("\n{}", name))
             }
 
             // Characteristic identifier
-            if let Some(identifier) =
-                characteristic_uuids::get_characteristic_identifier_from_uuid(&c.uuid)
-            {
+            if let Some(identifier) = &c.identifier {
                 vec_service[0].push_str("\n - Identifier");
                 vec_service[1].push_str(&format!("\n{}", identifier))
             }
 
             // Characteristic value
-            if c.properties
-                .contains(controllers::CharacteristicProperties::READ)
-            {
-                let mut val_as_str = String::new();
+            if let Some(value) = &c.value {
+                let mut val_as_str = value.clone();
 
-                if let Some(identifier) =
-                    characteristic_uuids::get_characteristic_identifier_from_uuid(&c.uuid)
-                {
-                    if identifier.contains("string") {
-                        val_as_str = commands::read::read_as_str(bt, &s.uuid, &c.uuid, "text")
-                            .await
-                            .unwrap();
-                    }
-                }
-
-                if val_as_str.is_empty() {
-                    val_as_str = commands::read::read_as_str(bt, &s.uuid, &c.uuid, "hex")
-                        .await
-                        .unwrap();
-
-                    // crop value if it's too long
-                    if val_as_str.len() > 45 {
-                        val_as_str = val_as_str[0..44].to_owned() + " ...";
-                    }
+                // crop value if it's too long
+                if c.is_hex && val_as_str.len() > 45 {
+                    val_as_str = val_as_str[0..44].to_owned() + " ...";
                 }
 
                 vec_service[0].push_str("\n - Value");
@@ -105,16 +223,44 @@ async fn print_gatt_infos(
 pub async fn gatt(
     bt: &mut dyn controllers::BleController,
     p: &Option<Preset>,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let infos = gatt_infos(bt, p).await?;
+
+    match output {
+        Output::Text => print_gatt_table(&infos),
+        Output::Json => output::print_json(&infos),
+    }
+
+    Ok(())
+}
+
+/// Print a gatt from the cache, without the values of the characteristics
+pub async fn cached_gatt(
+    infos: &controllers::BlePeripheralInfo,
+    p: &Option<Preset>,
+    output: Output,
 ) -> Result<(), Box<dyn Error>> {
-    let infos: BlePeripheralInfo = bt.get_peripheral_infos().await?;
-    print_gatt_infos(bt, &infos, p).await;
+    let infos = describe_gatt(infos, None, p).await?;
+
+    match output {
+        Output::Text => print_gatt_table(&infos),
+        Output::Json => output::print_json(&infos),
+    }
 
     Ok(())
 }
 
-pub async fn adapter(bt: &mut dyn controllers::BleController) -> Result<(), Box<dyn Error>> {
+pub async fn adapter(
+    bt: &mut dyn controllers::BleController,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
     let infos = bt.get_adapter_infos().await?;
-    println!("{}", infos);
+
+    match output {
+        Output::Text => println!("{}", infos),
+        Output::Json => output::print_json(&json!({ "adapter": infos })),
+    }
 
     Ok(())
 }
@@ -1,11 +1,15 @@
 pub mod clear;
 pub mod connect;
 pub mod disconnect;
+pub mod gatt;
+pub mod handle;
 pub mod indicate;
 pub mod info;
+pub mod mtu;
 pub mod notify;
 pub mod read;
 pub mod scan;
 pub mod sleep;
+pub mod smp;
 pub mod unsubscribe;
 pub mod write;
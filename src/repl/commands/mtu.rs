@@ -0,0 +1,31 @@
+use crate::controllers;
+use serde_json::json;
+use std::error::Error;
+
+use crate::utils::output::{self, Output};
+
+/// Print the ATT MTU of the connection, a new one is requested first when a value is given
+pub async fn mtu(
+    bt: &mut dyn controllers::BleController,
+    request: Option<u16>,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    if !bt.is_connected() {
+        Err("You must be connected to a peripheral to run this command")?;
+    }
+
+    let mtu = match request {
+        Some(request) => Some(bt.request_mtu(request).await?),
+        None => bt.get_mtu().await?,
+    };
+
+    match output {
+        Output::Text => match mtu {
+            Some(mtu) => println!("MTU: {} ({} bytes per write)", mtu, mtu.saturating_sub(3)),
+            None => println!("The MTU isn't reported by the BLE backend"),
+        },
+        Output::Json => output::print_json(&json!({ "mtu": mtu })),
+    }
+
+    Ok(())
+}
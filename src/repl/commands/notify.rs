@@ -1,15 +1,61 @@
-use crate::controllers;
+use crate::controllers::{self, NotificationPrinter};
+use crate::utils::output::{self, Output};
+use crate::utils::print_bytes::Formatter;
+use serde_json::json;
 use std::error::Error;
+use std::sync::Arc;
+
+/// JSON object printed for a notified or indicated value
+pub fn notification_json(
+    characteristic: &str,
+    value: &[u8],
+    formatter: &Formatter,
+) -> serde_json::Value {
+    json!({
+        "characteristic": characteristic,
+        "value": formatter(value),
+        "bytes": value,
+    })
+}
+
+/// Printer of the values notified or indicated by a characteristic
+pub fn printer(formatter: Formatter, output: Output) -> NotificationPrinter {
+    Arc::new(move |characteristic: &str, value: &[u8]| match output {
+        Output::Json => output::print_json(&notification_json(characteristic, value, &formatter)),
+        Output::Text => println!(
+            "Notification from [{}]: {}",
+            characteristic,
+            formatter(value)
+        ),
+    })
+}
 
 pub async fn notify(
     bt: &mut dyn controllers::BleController,
     service: &str,
     characteristic: &str,
-    format: &str,
+    formatter: Formatter,
+    output: Output,
 ) -> Result<(), Box<dyn Error>> {
     if !bt.is_connected() {
         Err("You must be connected to a peripheral to run this command")?;
     }
 
-    bt.notify(service, characteristic, format).await
+    bt.notify(service, characteristic, Some(printer(formatter, output)))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::print_bytes;
+
+    #[test]
+    fn test_notification_json() {
+        let json = notification_json("2a19", &[0x64], &print_bytes::formatter("dec"));
+        assert_eq!(
+            json.to_string(),
+            r#"{"bytes":[100],"characteristic":"2a19","value":"[100]"}"#
+        );
+    }
 }
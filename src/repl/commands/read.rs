@@ -1,35 +1,67 @@
 use crate::controllers;
+use serde_json::json;
 use std::error::Error;
 
-use crate::utils::print_bytes;
+use crate::utils::output::{self, Output};
+use crate::utils::print_bytes::Formatter;
 
+/// Read a characteristic, by the handle of its value when one is given
 pub async fn read(
     bt: &mut dyn controllers::BleController,
     service: &str,
     characteristic: &str,
-    format: &str,
-) -> Result<(), Box<dyn Error>> {
+    handle: Option<u16>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
     if !bt.is_connected() {
         Err("You must be connected to a peripheral to run this command")?;
     }
 
-    let bytes_read = bt.read(service, characteristic).await?;
-    println!("{}", print_bytes::bytes_to_str(&bytes_read, format));
+    match handle {
+        Some(handle) => bt.read_handle(handle).await,
+        None => bt.read(service, characteristic).await,
+    }
+}
 
-    Ok(())
+/// JSON object printed for a value read
+pub fn value_json(
+    service: &str,
+    characteristic: &str,
+    value: &[u8],
+    formatter: &Formatter,
+) -> serde_json::Value {
+    json!({
+        "service": service,
+        "characteristic": characteristic,
+        "value": formatter(value),
+        "bytes": value,
+    })
 }
 
-pub async fn read_as_str(
-    bt: &mut dyn controllers::BleController,
+pub fn print_value(
     service: &str,
     characteristic: &str,
-    format: &str,
-) -> Result<String, Box<dyn Error>> {
-    if !bt.is_connected() {
-        Err("You must be connected to a peripheral to run this command")?;
+    value: &[u8],
+    formatter: &Formatter,
+    output: Output,
+) {
+    match output {
+        Output::Text => println!("{}", formatter(value)),
+        Output::Json => output::print_json(&value_json(service, characteristic, value, formatter)),
     }
+}
 
-    let bytes_read = bt.read(service, characteristic).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::print_bytes;
 
-    Ok(print_bytes::bytes_to_str(&bytes_read, format))
+    #[test]
+    fn test_value_json() {
+        let json = value_json("180f", "2a19", &[0x64], &print_bytes::formatter("hex"));
+        assert_eq!(json["service"], "180f");
+        assert_eq!(json["characteristic"], "2a19");
+        assert_eq!(json["value"], print_bytes::bytes_to_str(&vec![0x64], "hex"));
+        assert_eq!(json["bytes"], json!([100]));
+        assert_eq!(json.as_object().unwrap().len(), 4);
+    }
 }
@@ -1,23 +1,46 @@
 use crate::controllers;
 
 use crate::bluetooth_numbers;
+use crate::utils::output::{self, Output};
 use controllers::BlePeripheral;
 
 use comfy_table::Table;
 
 use std::error::Error;
 
-pub fn print_scan_list(list: &Vec<BlePeripheral>, show_all: bool) -> Result<(), Box<dyn Error>> {
+/// Peripherals of a scan list, the ones without name are skipped unless show_all is set
+pub fn filter(list: &[BlePeripheral], show_all: bool) -> Vec<&BlePeripheral> {
+    list.iter()
+        .filter(|p| show_all || p.name != "unknown")
+        .collect()
+}
+
+/// JSON array printed for a scan list
+pub fn scan_list_json(list: &[&BlePeripheral]) -> serde_json::Value {
+    serde_json::to_value(list).expect("peripherals should be serializable")
+}
+
+pub fn print_scan_list(
+    list: &[BlePeripheral],
+    show_all: bool,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let list = filter(list, show_all);
+
+    if output == Output::Json {
+        output::print_json(&scan_list_json(&list));
+        return Ok(());
+    }
+
+    if list.is_empty() {
+        Err("Empty scan list")?;
+    }
+
     let mut table = Table::new();
 
     table.add_row(vec!["ID", "Name", "UUID", "Company", "RSSI"]);
 
-    let mut empty_list: bool = true;
-
     for p in list {
-        if !show_all && p.name == "unknown" {
-            continue;
-        }
         table.add_row(vec![
             &p.id.to_string(),
             &p.name,
@@ -26,11 +49,6 @@ pub fn print_scan_list(list: &Vec<BlePeripheral>, show_all: bool) -> Result<(),
                 .unwrap_or_else(|| "".to_owned()),
             &p.rssi.to_string(),
         ]);
-        empty_list = false
-    }
-
-    if empty_list {
-        Err("Empty scan list")?;
     }
 
     println!("{table}");
@@ -38,17 +56,46 @@ pub fn print_scan_list(list: &Vec<BlePeripheral>, show_all: bool) -> Result<(),
     Ok(())
 }
 
+/// Scan for peripherals and return the scan list
 pub async fn run(
     bt: &mut dyn controllers::BleController,
     timeout: usize,
-    print_results: bool,
-    show_all: bool,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<Vec<BlePeripheral>, Box<dyn Error>> {
     bt.scan(timeout).await?;
+    Ok(bt.get_scan_list())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_scan_list_json() {
+        let peripheral = |id, name: &str| BlePeripheral {
+            id,
+            name: name.to_owned(),
+            address_uuid: "AA:BB:CC:DD:EE:FF".to_owned(),
+            rssi: -40,
+            company_id: 89,
+            services: vec!["180f".to_owned()],
+            manufacturer_data: HashMap::from([(89, vec![1, 2])]),
+        };
+        let list = vec![peripheral(0, "unknown"), peripheral(1, "sensor")];
 
-    if print_results {
-        print_scan_list(&bt.get_scan_list(), show_all)
-    } else {
-        Ok(())
+        let json = scan_list_json(&filter(&list, false));
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "id": 1,
+                "name": "sensor",
+                "address_uuid": "AA:BB:CC:DD:EE:FF",
+                "rssi": -40,
+                "company_id": 89,
+                "services": ["180f"],
+                "manufacturer_data": { "89": [1, 2] },
+            }])
+        );
+        assert_eq!(filter(&list, true).len(), 2);
     }
 }
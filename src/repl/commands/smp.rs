@@ -0,0 +1,225 @@
+use crate::controllers;
+use comfy_table::Table;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path;
+
+use crate::smp::{self, ImageSlot, SmpClient, SmpOptions};
+use crate::utils::output::{self, Output};
+
+fn print_images(images: &[ImageSlot], output: Output) {
+    match output {
+        Output::Text => {
+            let mut table = Table::new();
+            table.add_row(vec!["Image", "Slot", "Version", "Flags", "Hash"]);
+            for i in images {
+                table.add_row(vec![
+                    &i.image.to_string(),
+                    &i.slot.to_string(),
+                    &i.version,
+                    &i.flags().join(" "),
+                    &i.hash,
+                ]);
+            }
+            println!("{table}");
+        }
+        Output::Json => output::print_json(&json!({ "images": images })),
+    }
+}
+
+fn print_counters(counters: &BTreeMap<String, i64>, output: Output) {
+    match output {
+        Output::Text => {
+            let mut table = Table::new();
+            for (name, value) in counters {
+                table.add_row(vec![name, &value.to_string()]);
+            }
+            println!("{table}");
+        }
+        Output::Json => output::print_json(counters),
+    }
+}
+
+/// Print the images in the slots of the peripheral
+pub async fn image_list(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let images = SmpClient::new(bt, options).await?.image_list().await?;
+    print_images(&images, output);
+    Ok(())
+}
+
+/// Upload an image file to the secondary slot, an interrupted upload of the same file resumes
+pub async fn image_upload(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+    file: &path::Path,
+    image: u32,
+    packet_size: Option<usize>,
+) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(file)
+        .map_err(|e| format!("Could not read file '{}': {}", file.to_string_lossy(), e))?;
+
+    let mut client = SmpClient::new(bt, options).await?;
+    let packet_size = packet_size.unwrap_or_else(|| client.default_packet_size());
+
+    eprintln!(
+        "Uploading {} bytes in requests of {} bytes",
+        data.len(),
+        packet_size
+    );
+    client.image_upload(&data, image, packet_size).await?;
+
+    // the hash of the image is the one of its signed part, the peripheral reports it
+    let images = client.image_list().await?;
+    match images.iter().find(|i| i.image == image && i.slot == 1) {
+        Some(uploaded) => {
+            println!(
+                "Uploaded version {} with hash {}",
+                uploaded.version, uploaded.hash
+            );
+            println!(
+                "Run 'smp image test {}' then reset to boot it",
+                uploaded.hash
+            );
+        }
+        None => {
+            print_images(&images, Output::Text);
+            println!(
+                "Run 'smp image test <hash>' with the hash of the new image then reset to boot it"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Boot an image once at the next reset, or for good when confirm is set. Without hash the
+/// running image is confirmed
+pub async fn image_state(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+    hash: Option<&String>,
+    confirm: bool,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let hash = hash.map(|h| smp::parse_hex(h)).transpose()?;
+
+    let images = SmpClient::new(bt, options)
+        .await?
+        .image_state(hash.as_deref(), confirm)
+        .await?;
+    print_images(&images, output);
+    Ok(())
+}
+
+pub async fn reset(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+) -> Result<(), Box<dyn Error>> {
+    SmpClient::new(bt, options).await?.reset().await?;
+    eprintln!("The peripheral is resetting");
+    Ok(())
+}
+
+pub async fn echo(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+    text: &str,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let echoed = SmpClient::new(bt, options).await?.echo(text).await?;
+
+    match output {
+        Output::Text => println!("{}", echoed),
+        Output::Json => output::print_json(&json!({ "echo": echoed })),
+    }
+    Ok(())
+}
+
+/// Print the statistics groups, or the counters of one of them
+pub async fn stat(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+    name: Option<&String>,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let mut client = SmpClient::new(bt, options).await?;
+
+    match name {
+        Some(name) => print_counters(&client.stat_read(name).await?, output),
+        None => {
+            let groups = client.stat_list().await?;
+            match output {
+                Output::Text => groups.iter().for_each(|g| println!("{}", g)),
+                Output::Json => output::print_json(&json!({ "stat_list": groups })),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the statistics of the tasks of the OS
+pub async fn taskstat(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let tasks = SmpClient::new(bt, options).await?.taskstat().await?;
+
+    match output {
+        Output::Text => {
+            let columns: Vec<&String> = tasks
+                .values()
+                .flat_map(|stats| stats.keys())
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .collect();
+
+            let mut table = Table::new();
+            let mut header = vec!["Task".to_owned()];
+            header.extend(columns.iter().map(|c| c.to_string()));
+            table.add_row(header);
+
+            for (name, stats) in &tasks {
+                let mut row = vec![name.clone()];
+                row.extend(
+                    columns
+                        .iter()
+                        .map(|c| stats.get(*c).map(|v| v.to_string()).unwrap_or_default()),
+                );
+                table.add_row(row);
+            }
+            println!("{table}");
+        }
+        Output::Json => output::print_json(&json!({ "tasks": tasks })),
+    }
+    Ok(())
+}
+
+/// Run a command of the shell of the peripheral and print its output
+pub async fn shell(
+    bt: &mut dyn controllers::BleController,
+    options: SmpOptions,
+    argv: &[String],
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let result = SmpClient::new(bt, options).await?.shell(argv).await?;
+
+    match output {
+        Output::Text => {
+            print!("{}", result.output);
+            if !result.output.is_empty() && !result.output.ends_with('\n') {
+                println!();
+            }
+        }
+        Output::Json => output::print_json(&result),
+    }
+
+    if result.ret != 0 {
+        Err(format!("The command returned {}", result.ret))?
+    }
+    Ok(())
+}
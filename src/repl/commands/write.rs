@@ -1,12 +1,63 @@
 use crate::controllers;
 use std::error::Error;
+use std::io::{self, Read, Write};
+use std::path;
+use std::time::{Duration, Instant};
 
-use str_to_bytes::str_to_bytes;
+use crate::utils::payload;
+
+/// Size of the chunks when the MTU isn't known: the minimum ATT MTU (23) minus the 3 bytes of the
+/// write header
+pub const DEFAULT_CHUNK_SIZE: usize = 20;
+
+/// How data too big for a single write is split
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChunkOptions {
+    /// Maximum number of bytes per write, None to use the MTU minus 3
+    pub size: Option<usize>,
+
+    /// Time waited between two writes
+    pub delay: Duration,
+}
+
+/// Largest chunk that fits in a write: the MTU minus the 3 bytes of the write header, or
+/// DEFAULT_CHUNK_SIZE when the backend doesn't report the MTU
+pub async fn mtu_chunk_size(bt: &mut dyn controllers::BleController) -> usize {
+    match bt.get_mtu().await {
+        Ok(Some(mtu)) if mtu > 3 => mtu as usize - 3,
+        _ => DEFAULT_CHUNK_SIZE,
+    }
+}
+
+/// Value given to a write command
+pub enum WriteValue {
+    /// Payload typed, written at once
+    Payload(String),
+
+    /// Bytes from a file or stdin, written in chunks
+    Data(Vec<u8>, ChunkOptions),
+}
+
+/// Write to a characteristic, by the handle of its value when one is given
+async fn write_bytes(
+    bt: &mut dyn controllers::BleController,
+    service: &str,
+    characteristic: &str,
+    handle: Option<u16>,
+    bytes: &[u8],
+    response: bool,
+) -> Result<(), Box<dyn Error>> {
+    match handle {
+        Some(handle) => bt.write_handle(handle, bytes, response).await,
+        None => bt.write(service, characteristic, bytes, response).await,
+    }
+}
 
 pub async fn write(
     bt: &mut dyn controllers::BleController,
     service: &str,
     characteristic: &str,
+    handle: Option<u16>,
     payload: &str,
     response: bool,
 ) -> Result<(), Box<dyn Error>> {
@@ -14,7 +65,113 @@ pub async fn write(
         Err("You must be connected to a peripheral to run this command")?;
     }
 
-    let pl: Vec<u8> = str_to_bytes(payload)?;
+    let pl: Vec<u8> = payload::parse(payload)?;
 
-    bt.write(service, characteristic, &pl, response).await
+    eprintln!("Writing {:?} to characteristic {}", pl, characteristic);
+    write_bytes(bt, service, characteristic, handle, &pl, response).await
+}
+
+/// Read the data to write from a file or from stdin until its end
+pub fn read_data(file: Option<&path::Path>, stdin: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    match file {
+        Some(fp) => std::fs::read(fp)
+            .map_err(|e| format!("Could not read file '{}': {}", fp.to_string_lossy(), e).into()),
+        None if stdin => {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            Ok(data)
+        }
+        None => Err("No file or stdin to read the data from")?,
+    }
+}
+
+/// Write data split in chunks, the progress and throughput are printed and the transfer stops at
+/// the first write that fails
+pub async fn write_chunks(
+    bt: &mut dyn controllers::BleController,
+    service: &str,
+    characteristic: &str,
+    handle: Option<u16>,
+    data: &[u8],
+    response: bool,
+    options: ChunkOptions,
+) -> Result<(), Box<dyn Error>> {
+    if !bt.is_connected() {
+        Err("You must be connected to a peripheral to run this command")?;
+    }
+    let size = match options.size {
+        Some(size) => size,
+        None => mtu_chunk_size(bt).await,
+    };
+    if size == 0 {
+        Err("The chunk size must be at least 1 byte")?;
+    }
+
+    eprintln!(
+        "Writing {} bytes to characteristic {} in chunks of {} bytes",
+        data.len(),
+        characteristic,
+        size
+    );
+
+    let start = Instant::now();
+    let mut written = 0;
+
+    for (index, chunk) in data.chunks(size).enumerate() {
+        if index > 0 && !options.delay.is_zero() {
+            tokio::time::sleep(options.delay).await;
+        }
+
+        if let Err(e) = write_bytes(bt, service, characteristic, handle, chunk, response).await {
+            eprintln!();
+            Err(format!("Write failed after {} bytes: {}", written, e))?;
+        }
+        written += chunk.len();
+
+        eprint!(
+            "\r{}/{} bytes ({}%)",
+            written,
+            data.len(),
+            written * 100 / data.len()
+        );
+        let _ = io::stderr().flush();
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    eprintln!();
+    eprintln!(
+        "Wrote {} bytes in {:.2} s ({:.0} bytes/s)",
+        written,
+        elapsed,
+        written as f64 / elapsed.max(f64::EPSILON)
+    );
+    Ok(())
+}
+
+/// Write a payload at once or data in chunks
+pub async fn write_value(
+    bt: &mut dyn controllers::BleController,
+    service: &str,
+    characteristic: &str,
+    handle: Option<u16>,
+    value: &WriteValue,
+    response: bool,
+) -> Result<(), Box<dyn Error>> {
+    match value {
+        WriteValue::Payload(payload) => {
+            write(bt, service, characteristic, handle, payload, response).await
+        }
+        WriteValue::Data(data, options) => {
+            write_chunks(
+                bt,
+                service,
+                characteristic,
+                handle,
+                data,
+                response,
+                *options,
+            )
+            .await
+        }
+    }
 }
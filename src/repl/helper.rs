@@ -0,0 +1,694 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::bluetooth_numbers::compare_uuid::normalize_uuid;
+use crate::controllers::{BlePeripheralInfo, CharacteristicProperties};
+use crate::preset::Preset;
+use crate::utils::payload;
+
+use super::cli;
+
+const RED: &str = "\x1b[31m";
+const GREY: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// What a word of the line is, once matched against the cli definition
+#[derive(Debug, PartialEq)]
+enum WordKind {
+    /// A command or subcommand name
+    Command,
+    /// A flag (-r, --format ...)
+    Flag,
+    /// The value given to a flag
+    FlagValue,
+    /// A positional argument, identified by its id in the cli definition
+    Positional(String),
+    /// Anything the cli definition doesn't know about
+    Unknown,
+}
+
+struct Word<'l> {
+    start: usize,
+    text: &'l str,
+    kind: WordKind,
+}
+
+/// Result of the analysis of a line
+struct Analysis<'l, 'c> {
+    words: Vec<Word<'l>>,
+    /// Deepest (sub)command matched
+    command: Option<&'c clap::Command>,
+    /// Name of the top level command
+    command_name: Option<String>,
+    /// Number of positional arguments already given to `command`
+    positionals: usize,
+}
+
+/// Hint displayed after the cursor
+pub struct ReplHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for ReplHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+/// rustyline helper giving hints, highlighting and validation of the line being typed
+pub struct ReplHelper {
+    cli: clap::Command,
+    preset_commands: Vec<String>,
+    preset_functions: Vec<String>,
+    preset_params: HashMap<String, Vec<String>>,
+    preset_services: HashMap<String, String>,
+    preset_characteristics: HashMap<(String, String), String>,
+
+    /// Properties of the characteristics by normalised (service, characteristic) uuids, the
+    /// characteristic uuids aren't unique across services
+    characteristics: HashMap<(String, String), CharacteristicProperties>,
+    color: bool,
+}
+
+/// Split a line in words like shlex does but keep the position and the quotes of each word
+fn split_words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (idx, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if quote != Some('\'') => {
+                escaped = true;
+                start.get_or_insert(idx);
+            }
+            '"' | '\'' if quote.is_none() => {
+                quote = Some(c);
+                start.get_or_insert(idx);
+            }
+            c if Some(c) == quote => quote = None,
+            c if c.is_whitespace() && quote.is_none() => {
+                if let Some(s) = start.take() {
+                    words.push((s, &line[s..idx]));
+                }
+            }
+            _ => {
+                start.get_or_insert(idx);
+            }
+        }
+    }
+
+    if let Some(s) = start {
+        words.push((s, &line[s..]));
+    }
+    words
+}
+
+/// Remove the shell quoting of a word
+fn unquote(word: &str) -> String {
+    shlex::split(word)
+        .and_then(|v| v.into_iter().next())
+        .unwrap_or_else(|| word.to_owned())
+}
+
+fn find_flag<'c>(cmd: &'c clap::Command, word: &str) -> Option<&'c clap::Arg> {
+    let word = word.split('=').next().unwrap_or(word);
+
+    if let Some(long) = word.strip_prefix("--") {
+        return cmd.get_arguments().find(|a| a.get_long() == Some(long));
+    }
+
+    let mut chars = word.chars().skip(1);
+    let short = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+    cmd.get_arguments().find(|a| a.get_short() == Some(short))
+}
+
+fn flag_takes_value(arg: &clap::Arg) -> bool {
+    arg.get_num_args()
+        .map(|n| n.takes_values())
+        .unwrap_or(false)
+}
+
+/// Operations a characteristic must allow to be used with a command
+fn required_properties(command: &str) -> Option<CharacteristicProperties> {
+    match command {
+        "read" => Some(CharacteristicProperties::READ),
+        "write" => {
+            Some(CharacteristicProperties::WRITE | CharacteristicProperties::WRITE_WITHOUT_RESPONSE)
+        }
+        "notify" => Some(CharacteristicProperties::NOTIFY),
+        "indicate" => Some(CharacteristicProperties::INDICATE),
+        "unsubscribe" => {
+            Some(CharacteristicProperties::NOTIFY | CharacteristicProperties::INDICATE)
+        }
+        _ => None,
+    }
+}
+
+impl ReplHelper {
+    pub fn new() -> ReplHelper {
+        let mut cli = cli::cli();
+        cli.build();
+
+        ReplHelper {
+            cli,
+            preset_commands: Vec::new(),
+            preset_functions: Vec::new(),
+            preset_params: HashMap::new(),
+            preset_services: HashMap::new(),
+            preset_characteristics: HashMap::new(),
+            characteristics: HashMap::new(),
+            color: true,
+        }
+    }
+
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    /// Update the commands, functions and characteristics names known from the preset
+    pub fn set_preset(&mut self, preset: &Option<Preset>) {
+        self.preset_commands.clear();
+        self.preset_functions.clear();
+        self.preset_params.clear();
+        self.preset_services.clear();
+        self.preset_characteristics.clear();
+
+        if let Some(pr) = preset {
+            self.preset_commands = pr.get_command_names();
+            self.preset_functions = pr.get_function_names();
+            self.preset_params = self
+                .preset_commands
+                .iter()
+                .map(|c| {
+                    let params = pr.get_command_params(c).into_iter().map(|p| p.name);
+                    (c.clone(), params.collect())
+                })
+                .collect();
+            self.preset_services = pr.get_services_uuids();
+            self.preset_characteristics = pr.get_characteristics_uuids();
+        }
+    }
+
+    /// Update the characteristics properties known from the gatt of the connected peripheral
+    pub fn set_peripheral_infos(&mut self, infos: Option<&BlePeripheralInfo>) {
+        self.characteristics.clear();
+
+        if let Some(infos) = infos {
+            for s in &infos.services {
+                for c in &s.characteristics {
+                    let key = (normalize_uuid(&s.uuid), normalize_uuid(&c.uuid));
+                    self.characteristics.insert(key, c.properties);
+                }
+            }
+        }
+    }
+
+    fn analyze<'l>(&self, line: &'l str) -> Analysis<'l, '_> {
+        let mut analysis = Analysis {
+            words: Vec::new(),
+            command: None,
+            command_name: None,
+            positionals: 0,
+        };
+        let mut expect_value = false;
+
+        for (start, text) in split_words(line) {
+            let kind = match analysis.command {
+                None if analysis.words.is_empty() => match self.cli.find_subcommand(text) {
+                    Some(c) => {
+                        analysis.command = Some(c);
+                        analysis.command_name = Some(c.get_name().to_owned());
+                        WordKind::Command
+                    }
+                    None => WordKind::Unknown,
+                },
+                None => WordKind::Unknown,
+                Some(_) if expect_value => {
+                    expect_value = false;
+                    WordKind::FlagValue
+                }
+                Some(cmd) if text.starts_with('-') && text.len() > 1 => {
+                    match find_flag(cmd, text) {
+                        Some(arg) => {
+                            expect_value = flag_takes_value(arg) && !text.contains('=');
+                            WordKind::Flag
+                        }
+                        None => WordKind::Unknown,
+                    }
+                }
+                Some(cmd) if cmd.has_subcommands() && analysis.positionals == 0 => {
+                    match cmd.find_subcommand(text) {
+                        Some(c) => {
+                            analysis.command = Some(c);
+                            WordKind::Command
+                        }
+                        None => WordKind::Unknown,
+                    }
+                }
+                Some(cmd) => {
+                    analysis.positionals += 1;
+                    // the last positional can take multiple values
+                    let arg = cmd
+                        .get_positionals()
+                        .nth(analysis.positionals - 1)
+                        .or_else(|| {
+                            cmd.get_positionals()
+                                .last()
+                                .filter(|a| a.get_num_args().is_some_and(|n| n.max_values() > 1))
+                        });
+                    match arg {
+                        Some(arg) => WordKind::Positional(arg.get_id().to_string()),
+                        None => WordKind::Unknown,
+                    }
+                }
+            };
+            analysis.words.push(Word { start, text, kind });
+        }
+
+        analysis
+    }
+
+    /// Normalised service and characteristic uuids of the names or uuids typed
+    fn resolve_characteristic(&self, service: &str, characteristic: &str) -> (String, String) {
+        let characteristic = self
+            .preset_characteristics
+            .get(&(service.to_owned(), characteristic.to_owned()))
+            .map(|uuid| uuid.as_str())
+            .unwrap_or(characteristic);
+        let service = self
+            .preset_services
+            .get(service)
+            .map(|uuid| uuid.as_str())
+            .unwrap_or(service);
+        (normalize_uuid(service), normalize_uuid(characteristic))
+    }
+
+    /// Return an error message if a word of the line is invalid
+    fn word_error(&self, analysis: &Analysis, word: &Word) -> Option<String> {
+        let text = unquote(word.text);
+
+        match &word.kind {
+            // only the command name is reported when the command is unknown
+            WordKind::Unknown if analysis.command_name.is_none() => {
+                if analysis.words.first().map(|w| w.start) == Some(word.start) {
+                    Some(format!("Unknown command: '{}'", text))
+                } else {
+                    None
+                }
+            }
+            WordKind::Unknown => Some(format!("Unexpected argument: '{}'", text)),
+            WordKind::Positional(id) => match id.as_str() {
+                "payload" => payload::parse(&text)
+                    .err()
+                    .map(|e| format!("Invalid payload '{}': {}", text, e)),
+                "command_name" if !self.preset_commands.contains(&text) => {
+                    Some(format!("Unknown preset command: '{}'", text))
+                }
+                "function_name" if !self.preset_functions.contains(&text) => {
+                    Some(format!("Unknown preset function: '{}'", text))
+                }
+                "params" if !text.contains('=') => Some(format!(
+                    "Invalid parameter: '{}', expected name=value",
+                    text
+                )),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Return true if the word is a characteristic that doesn't allow the command typed
+    fn is_forbidden_characteristic(&self, analysis: &Analysis, index: usize) -> bool {
+        let Some(required) = analysis
+            .command_name
+            .as_deref()
+            .and_then(required_properties)
+        else {
+            return false;
+        };
+
+        if analysis.words[index].kind != WordKind::Positional("characteristic".to_owned()) {
+            return false;
+        }
+
+        let Some(service) = analysis.words[..index]
+            .iter()
+            .find(|w| w.kind == WordKind::Positional("service".to_owned()))
+        else {
+            return false;
+        };
+
+        let key = self
+            .resolve_characteristic(&unquote(service.text), &unquote(analysis.words[index].text));
+
+        match self.characteristics.get(&key) {
+            Some(properties) => !properties.intersects(required),
+            None => false,
+        }
+    }
+
+    /// Names that can complete the word being typed
+    fn candidates(&self, analysis: &Analysis, word: &Word) -> Vec<String> {
+        match &word.kind {
+            WordKind::Unknown if analysis.words.len() == 1 => self
+                .cli
+                .get_subcommands()
+                .map(|c| c.get_name().to_owned())
+                .collect(),
+            WordKind::Unknown => match analysis.command {
+                Some(cmd) if analysis.positionals == 0 => cmd
+                    .get_subcommands()
+                    .map(|c| c.get_name().to_owned())
+                    .collect(),
+                _ => Vec::new(),
+            },
+            WordKind::Positional(id) if id == "command_name" => self.preset_commands.clone(),
+            WordKind::Positional(id) if id == "function_name" => self.preset_functions.clone(),
+            WordKind::Positional(id) if id == "params" => analysis
+                .words
+                .iter()
+                .find(|w| w.kind == WordKind::Positional("command_name".to_owned()))
+                .and_then(|w| self.preset_params.get(&unquote(w.text)))
+                .map(|params| params.iter().map(|p| format!("{}=", p)).collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Usage of the positional arguments that are still expected
+    fn missing_arguments(&self, analysis: &Analysis) -> Option<String> {
+        let cmd = analysis.command?;
+
+        // a characteristic given by its handle replaces the service and the characteristic, this
+        // is why they are optional for read and write
+        let by_handle = analysis.words.iter().any(|w| {
+            w.kind == WordKind::Positional("service".to_owned()) && w.text.starts_with('#')
+        });
+
+        // the data of a file replaces the payload
+        let from_data = analysis
+            .words
+            .iter()
+            .any(|w| w.kind == WordKind::Flag && w.text == "--file");
+
+        let missing: Vec<String> = cmd
+            .get_positionals()
+            .filter(|a| !(by_handle && a.get_id() == "characteristic"))
+            .filter(|a| !(from_data && a.get_id() == "payload"))
+            .skip(analysis.positionals)
+            .map(|a| {
+                if a.is_required_set()
+                    || ["characteristic", "payload"].contains(&a.get_id().as_str())
+                {
+                    format!("<{}>", a.get_id())
+                } else {
+                    format!("[{}]", a.get_id())
+                }
+            })
+            .collect();
+
+        if missing.is_empty() {
+            None
+        } else {
+            Some(missing.join(" "))
+        }
+    }
+}
+
+impl Default for ReplHelper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+}
+
+impl Hinter for ReplHelper {
+    type Hint = ReplHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<ReplHint> {
+        if pos < line.len() || line.trim().is_empty() {
+            return None;
+        }
+
+        let analysis = self.analyze(line);
+        let typing_word = !line.ends_with(char::is_whitespace);
+
+        // complete the word being typed
+        if typing_word {
+            let word = analysis.words.last()?;
+            let candidates: Vec<String> = self
+                .candidates(&analysis, word)
+                .into_iter()
+                .filter(|c| c.starts_with(word.text) && c.len() > word.text.len())
+                .collect();
+
+            if let [candidate] = candidates.as_slice() {
+                let rest = candidate[word.text.len()..].to_owned();
+                return Some(ReplHint {
+                    display: rest.clone(),
+                    completion: Some(rest),
+                });
+            }
+        }
+
+        // the line can still be submitted, the error is only shown
+        if let Some(e) = analysis
+            .words
+            .iter()
+            .find_map(|w| self.word_error(&analysis, w))
+        {
+            return Some(ReplHint {
+                display: format!("  {}", e),
+                completion: None,
+            });
+        }
+
+        let missing = self.missing_arguments(&analysis)?;
+        Some(ReplHint {
+            display: if typing_word {
+                format!(" {}", missing)
+            } else {
+                missing
+            },
+            completion: None,
+        })
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if !self.color {
+            return Cow::Borrowed(line);
+        }
+
+        let analysis = self.analyze(line);
+        let mut out = String::with_capacity(line.len());
+        let mut last = 0;
+
+        for (index, word) in analysis.words.iter().enumerate() {
+            if self.word_error(&analysis, word).is_none()
+                && !self.is_forbidden_characteristic(&analysis, index)
+            {
+                continue;
+            }
+            let end = word.start + word.text.len();
+            out.push_str(&line[last..word.start]);
+            out.push_str(RED);
+            out.push_str(word.text);
+            out.push_str(RESET);
+            last = end;
+        }
+
+        if last == 0 {
+            return Cow::Borrowed(line);
+        }
+        out.push_str(&line[last..]);
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if !self.color {
+            return Cow::Borrowed(hint);
+        }
+
+        Cow::Owned(format!("{}{}{}", GREY, hint, RESET))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        kind != CmdKind::MoveCursor
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        // the other errors are highlighted and reported by the command itself
+        if shlex::split(ctx.input()).is_none() {
+            return Ok(ValidationResult::Invalid(Some(
+                "\nParsing error: Invalid quoting".to_owned(),
+            )));
+        }
+
+        Ok(ValidationResult::Valid(None))
+    }
+}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words("write  a \"b c\" 'd'"),
+            vec![(0, "write"), (7, "a"), (9, "\"b c\""), (15, "'d'")]
+        );
+        assert_eq!(split_words("  "), vec![]);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let helper = ReplHelper::new();
+
+        let analysis = helper.analyze("read -f text svc chr extra");
+        let kinds: Vec<&WordKind> = analysis.words.iter().map(|w| &w.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &WordKind::Command,
+                &WordKind::Flag,
+                &WordKind::FlagValue,
+                &WordKind::Positional("service".to_owned()),
+                &WordKind::Positional("characteristic".to_owned()),
+                &WordKind::Unknown,
+            ]
+        );
+
+        let analysis = helper.analyze("preset command foo");
+        assert_eq!(
+            analysis.words[2].kind,
+            WordKind::Positional("command_name".to_owned())
+        );
+
+        let analysis = helper.analyze("preset command set_color r=1 g=2");
+        assert_eq!(
+            analysis.words[4].kind,
+            WordKind::Positional("params".to_owned())
+        );
+        assert!(helper.word_error(&analysis, &analysis.words[4]).is_none());
+        let analysis = helper.analyze("preset command set_color r");
+        assert!(helper.word_error(&analysis, &analysis.words[3]).is_some());
+    }
+
+    #[test]
+    fn test_missing_arguments() {
+        let helper = ReplHelper::new();
+
+        let analysis = helper.analyze("write svc ");
+        assert_eq!(
+            helper.missing_arguments(&analysis),
+            Some("<characteristic> <payload>".to_owned())
+        );
+
+        let analysis = helper.analyze("write #0x002a ");
+        assert_eq!(
+            helper.missing_arguments(&analysis),
+            Some("<payload>".to_owned())
+        );
+        let analysis = helper.analyze("write svc --file data.bin ");
+        assert_eq!(
+            helper.missing_arguments(&analysis),
+            Some("<characteristic>".to_owned())
+        );
+        let analysis = helper.analyze("read #0x002a ");
+        assert_eq!(helper.missing_arguments(&analysis), None);
+
+        let analysis = helper.analyze("scan ");
+        assert_eq!(
+            helper.missing_arguments(&analysis),
+            Some("[timeout]".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_highlight() {
+        let mut helper = ReplHelper::new();
+
+        assert_eq!(helper.highlight("scan 2", 0), "scan 2");
+        assert_eq!(
+            helper.highlight("foo 2", 0),
+            format!("{}foo{} 2", RED, RESET)
+        );
+        assert_eq!(
+            helper.highlight("write a b 0xzz", 0),
+            format!("write a b {}0xzz{}", RED, RESET)
+        );
+        assert_eq!(
+            helper.highlight("preset command foo", 0),
+            format!("preset command {}foo{}", RED, RESET)
+        );
+
+        helper.characteristics.insert(
+            (
+                "0000180f-0000-1000-8000-00805f9b34fb".to_owned(),
+                "00002a19-0000-1000-8000-00805f9b34fb".to_owned(),
+            ),
+            CharacteristicProperties::READ | CharacteristicProperties::NOTIFY,
+        );
+        helper.characteristics.insert(
+            (
+                "0000181a-0000-1000-8000-00805f9b34fb".to_owned(),
+                "00002a19-0000-1000-8000-00805f9b34fb".to_owned(),
+            ),
+            CharacteristicProperties::WRITE,
+        );
+        assert_eq!(
+            helper.highlight("read 180f 00002a19-0000-1000-8000-00805f9b34fb", 0),
+            "read 180f 00002a19-0000-1000-8000-00805f9b34fb"
+        );
+        assert_eq!(
+            helper.highlight("write 180f 00002A19-0000-1000-8000-00805F9B34FB 1", 0),
+            format!(
+                "write 180f {}00002A19-0000-1000-8000-00805F9B34FB{} 1",
+                RED, RESET
+            )
+        );
+        assert_eq!(
+            helper.highlight("write 180f 2A19 1", 0),
+            format!("write 180f {}2A19{} 1", RED, RESET)
+        );
+        // the same characteristic uuid in another service has its own properties
+        assert_eq!(
+            helper.highlight("write 181a 2a19 1", 0),
+            "write 181a 2a19 1"
+        );
+        assert_eq!(
+            helper.highlight("read 181a 2a19", 0),
+            format!("read 181a {}2a19{}", RED, RESET)
+        );
+    }
+}
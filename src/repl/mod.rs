@@ -3,41 +3,139 @@ use rustyline::history::FileHistory;
 use rustyline::Editor;
 
 mod cli;
+mod helper;
 
 pub mod commands;
 
+use crate::bluetooth_numbers::characteristic_decoders;
+use crate::config::Config;
 use crate::controllers;
-use crate::preset::Preset;
+use crate::dfu;
+use crate::preset::{export, params, Preset};
+use crate::smp;
+use crate::utils::output::Output;
+use crate::utils::print_bytes::{self, Formatter};
+use commands::write::{ChunkOptions, WriteValue};
+use controllers::gatt_cache::{self, CachedGatt};
 use controllers::BleController;
+use helper::ReplHelper;
 use std::error::Error;
+use std::path;
+use std::time::Duration;
+
+/// Escape the '#' followed by a digit starting a word outside of quotes, shlex would read it as a
+/// comment but it starts an attribute handle like #0x002a. The other ones still start comments
+fn escape_handles(line: &str) -> String {
+    let mut escaped = String::with_capacity(line.len());
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let handle = chars.peek().is_some_and(|next| next.is_ascii_digit());
+        match c {
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            c if Some(c) == quote => quote = None,
+            '#' if quote.is_none() && previous.is_whitespace() && handle => escaped.push('\\'),
+            _ => (),
+        }
+        escaped.push(c);
+        previous = c;
+    }
+    escaped
+}
 
 pub struct Repl<'a> {
     bt: &'a mut dyn BleController,
-    editor: Editor<(), FileHistory>,
+    editor: Editor<ReplHelper, FileHistory>,
     preset: Option<Preset>,
+    config: Config,
+    output: Output,
+    last_address: Option<String>,
 }
 
-const HISTORY_FP: &str = ".bluerepl_history.txt";
-
 impl Repl<'_> {
-    pub async fn new(bt: &mut dyn BleController) -> Repl {
+    pub async fn new(bt: &mut dyn BleController, config: Config) -> Repl<'_> {
+        let mut editor = Editor::<ReplHelper, FileHistory>::new().unwrap();
+        let mut helper = ReplHelper::new();
+        helper.set_color(config.color);
+        editor.set_helper(Some(helper));
+
         Repl {
             bt,
-            editor: Editor::<(), FileHistory>::new().unwrap(),
+            editor,
             preset: None,
+            config,
+            output: Output::Text,
+            last_address: None,
         }
     }
 
+    /// Output of the commands run without the -o flag
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
     pub fn set_preset(&mut self, pr: Preset) {
         self.preset = Some(pr);
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.set_preset(&self.preset);
+        }
+    }
+
+    fn unset_preset(&mut self) {
+        self.preset = None;
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.set_preset(&self.preset);
+        }
+    }
+
+    /// Gatt of a peripheral seen before from the gatt cache
+    fn cached_gatt(&self, address: &str) -> Result<CachedGatt, Box<dyn Error>> {
+        let Some(dir) = self.config.gatt_cache_dir() else {
+            Err("The gatt cache is disabled in the configuration")?
+        };
+        match gatt_cache::load(&dir, address) {
+            Some(gatt) => Ok(gatt),
+            None => Err(format!("No gatt cached for peripheral {}", address))?,
+        }
+    }
+
+    /// Give the gatt of the connected peripheral to the line editor so it can check the
+    /// characteristics typed. When disconnected the cached gatt of the last peripheral or of the
+    /// preset device is used
+    async fn update_helper_gatt(&mut self) {
+        let infos = if self.bt.is_connected() {
+            let infos = self.bt.get_peripheral_infos().await.ok();
+            if let Some(infos) = &infos {
+                self.last_address = Some(infos.periph_mac.clone());
+            }
+            infos
+        } else {
+            self.last_address
+                .clone()
+                .or_else(|| self.preset.as_ref().and_then(|p| p.get_device_address()))
+                .and_then(|address| self.cached_gatt(&address).ok())
+                .map(|gatt| gatt.to_infos())
+        };
+
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.set_peripheral_infos(infos.as_ref());
+        }
     }
 
     fn get_line(&mut self) -> String {
-        let readline = self.editor.readline(">> ");
+        let readline = self.editor.readline(&self.config.prompt);
         match readline {
             Ok(line) => {
                 let _ = self.editor.add_history_entry(line.as_str());
-                self.editor.save_history(HISTORY_FP).unwrap();
+                if let Err(e) = self.editor.save_history(&self.config.history_file) {
+                    eprintln!(
+                        "Could not save the history to '{}': {}",
+                        self.config.history_file.to_string_lossy(),
+                        e
+                    );
+                }
                 line
             }
             Err(ReadlineError::Interrupted) => {
@@ -69,6 +167,34 @@ impl Repl<'_> {
         }
     }
 
+    /// Formatter for the value of a characteristic: the format typed, the layout of the
+    /// characteristic in the preset, the decoder of a standard characteristic or the default
+    /// format
+    fn get_formatter(
+        &self,
+        service: &str,
+        characteristic: &str,
+        format: Option<&String>,
+    ) -> Formatter {
+        if let Some(format) = format {
+            return print_bytes::formatter(format);
+        }
+
+        if let Some(preset) = &self.preset {
+            if let Some(formatter) = preset.get_formatter(service, characteristic) {
+                return formatter;
+            }
+        }
+
+        if let Some(formatter) =
+            characteristic_decoders::get_characteristic_formatter(characteristic)
+        {
+            return formatter;
+        }
+
+        print_bytes::formatter(&self.config.format)
+    }
+
     async fn execute_command(&mut self, matches: clap::ArgMatches) -> Result<(), Box<dyn Error>> {
         match matches.subcommand() {
             Some(("quit", _)) => {
@@ -80,58 +206,162 @@ impl Repl<'_> {
                 commands::clear::run();
             }
 
+            Some(("mtu", mt)) => {
+                let request = mt.get_one::<u16>("request").copied();
+                let output = Output::from_name(mt.get_one::<String>("output"), self.output);
+                commands::mtu::mtu(self.bt, request, output).await?;
+            }
+
             Some(("sleep", mt)) => {
                 let time_ms = *mt.get_one::<u64>("time_ms").unwrap();
                 commands::sleep::run(time_ms);
             }
 
             Some(("write", mt)) => {
-                let mut service = mt.get_one::<String>("service").unwrap().clone();
-                let mut characteristic = mt.get_one::<String>("characteristic").unwrap().clone();
-                let payload = mt.get_one::<String>("payload").unwrap();
+                let first = mt.get_one::<String>("service").unwrap();
                 let response: bool = mt.contains_id("resp");
+                let file = mt.get_one::<String>("file").map(path::Path::new);
+
+                // a handle replaces the service and the characteristic, and the data of a file
+                // replaces the payload
+                let rest: Vec<&String> = ["characteristic", "payload"]
+                    .iter()
+                    .filter_map(|id| mt.get_one::<String>(id))
+                    .collect();
+                let handle = commands::handle::parse(first)?;
+                let from_data = file.is_some();
+                if rest.len() != usize::from(handle.is_none()) + usize::from(!from_data) {
+                    Err(
+                        "Expected <service> <characteristic> <payload> or #<handle> <payload>, \
+                        without the payload with --file",
+                    )?;
+                }
 
-                self.try_replacing_service_and_characteristics_with_preset_defs(
-                    &mut service,
-                    &mut characteristic,
-                );
-
-                commands::write::write(self.bt, &service, &characteristic, payload, response)
-                    .await?;
+                let (service, characteristic) = match handle {
+                    Some(handle) => commands::handle::resolve(self.bt, handle).await?,
+                    None => {
+                        let mut service = first.clone();
+                        let mut characteristic = rest[0].clone();
+                        self.try_replacing_service_and_characteristics_with_preset_defs(
+                            &mut service,
+                            &mut characteristic,
+                        );
+                        (service, characteristic)
+                    }
+                };
+
+                let value = if from_data {
+                    let options = ChunkOptions {
+                        size: mt.get_one::<usize>("chunk-size").copied(),
+                        delay: Duration::from_millis(
+                            mt.get_one::<u64>("delay").copied().unwrap_or(0),
+                        ),
+                    };
+                    WriteValue::Data(commands::write::read_data(file, false)?, options)
+                } else {
+                    WriteValue::Payload(rest[rest.len() - 1].clone())
+                };
+
+                commands::write::write_value(
+                    self.bt,
+                    &service,
+                    &characteristic,
+                    handle,
+                    &value,
+                    response,
+                )
+                .await?;
             }
 
             Some(("read", mt)) => {
-                let mut service = mt.get_one::<String>("service").unwrap().clone();
-                let mut characteristic = mt.get_one::<String>("characteristic").unwrap().clone();
-                let format = mt.get_one::<String>("format").unwrap();
-
-                self.try_replacing_service_and_characteristics_with_preset_defs(
-                    &mut service,
-                    &mut characteristic,
-                );
-
-                commands::read::read(self.bt, &service, &characteristic, format).await?;
+                let first = mt.get_one::<String>("service").unwrap();
+                let format = mt.get_one::<String>("format");
+
+                let handle = commands::handle::parse(first)?;
+                let (service, characteristic) =
+                    match (handle, mt.get_one::<String>("characteristic")) {
+                        (Some(handle), None) => commands::handle::resolve(self.bt, handle).await?,
+                        (None, Some(characteristic)) => {
+                            let mut service = first.clone();
+                            let mut characteristic = characteristic.clone();
+                            self.try_replacing_service_and_characteristics_with_preset_defs(
+                                &mut service,
+                                &mut characteristic,
+                            );
+                            (service, characteristic)
+                        }
+                        _ => Err("Expected <service> <characteristic> or #<handle>")?,
+                    };
+
+                let formatter = self.get_formatter(&service, &characteristic, format);
+                let output = Output::from_name(mt.get_one::<String>("output"), self.output);
+                let value =
+                    commands::read::read(self.bt, &service, &characteristic, handle).await?;
+                commands::read::print_value(&service, &characteristic, &value, &formatter, output);
             }
 
             Some(("scan", mt)) => {
                 let show_all = mt.contains_id("all");
+                let output = Output::from_name(mt.get_one::<String>("output"), self.output);
 
                 if mt.contains_id("list") {
-                    return commands::scan::print_scan_list(&self.bt.get_scan_list(), show_all);
+                    return commands::scan::print_scan_list(
+                        &self.bt.get_scan_list(),
+                        show_all,
+                        output,
+                    );
                 }
 
-                let timeout = *mt.get_one::<usize>("timeout").unwrap();
+                let timeout = *mt
+                    .get_one::<usize>("timeout")
+                    .unwrap_or(&self.config.scan_timeout);
 
-                commands::scan::run(self.bt, timeout, true, show_all).await?;
+                let list = commands::scan::run(self.bt, timeout).await?;
+                commands::scan::print_scan_list(&list, show_all, output)?;
             }
 
-            Some(("info", mt)) => match mt.subcommand_name() {
-                Some("adapter") => commands::info::adapter(self.bt).await?,
-                Some("gatt") => {
+            Some(("info", mt)) => match mt.subcommand() {
+                Some(("adapter", arg)) => {
+                    let output = Output::from_name(arg.get_one::<String>("output"), self.output);
+                    commands::info::adapter(self.bt, output).await?
+                }
+                Some(("gatt", arg)) => {
+                    let output = Output::from_name(arg.get_one::<String>("output"), self.output);
+                    if let Some(address) = arg.get_one::<String>("device") {
+                        let infos = self.cached_gatt(address)?.to_infos();
+                        commands::info::cached_gatt(&infos, &self.preset, output).await?;
+                        return Ok(());
+                    }
+                    if !self.bt.is_connected() {
+                        Err("You must be connected to a peripheral to run this command")?;
+                    }
+                    commands::info::gatt(self.bt, &self.preset, output).await?;
+                }
+                _ => panic!("Code should never be here"),
+            },
+
+            Some(("gatt", mt)) => match mt.subcommand() {
+                Some(("save", arg)) => {
+                    let fp = path::Path::new(arg.get_one::<String>("file").unwrap());
+                    if fp.exists() && !arg.get_flag("force") {
+                        Err(format!(
+                            "File '{}' already exists, use --force to overwrite it",
+                            fp.to_string_lossy()
+                        ))?;
+                    }
                     if !self.bt.is_connected() {
                         Err("You must be connected to a peripheral to run this command")?;
                     }
-                    commands::info::gatt(self.bt, &self.preset).await?;
+                    commands::gatt::save(self.bt, fp).await?;
+                }
+                Some(("diff", arg)) => {
+                    let a = path::Path::new(arg.get_one::<String>("a").unwrap());
+                    let b = arg.get_one::<String>("b").unwrap();
+                    commands::gatt::print_diff(self.bt, a, b).await?;
+                }
+                Some(("refresh", _)) => {
+                    self.bt.refresh_gatt().await?;
+                    self.update_helper_gatt().await;
                 }
                 _ => panic!("Code should never be here"),
             },
@@ -150,6 +380,7 @@ impl Repl<'_> {
                     let identifier = mt.get_one::<String>("identifier").unwrap();
                     commands::connect::auto_detect_identifier(self.bt, identifier).await?;
                 }
+                self.update_helper_gatt().await;
             }
 
             Some(("disconnect", _mt)) => {
@@ -158,32 +389,117 @@ impl Repl<'_> {
                 } else {
                     commands::disconnect::run(self.bt).await?;
                 }
+                self.update_helper_gatt().await;
+            }
+
+            Some(("dfu", mt)) => {
+                let fp = path::Path::new(mt.get_one::<String>("package").unwrap());
+                let package = dfu::Package::load(fp)?;
+
+                let mut options = dfu::DfuOptions {
+                    scan_timeout: self.config.scan_timeout,
+                    ..Default::default()
+                };
+                if let Some(prn) = mt.get_one::<u16>("prn") {
+                    options.prn = *prn;
+                }
+                if let Some(timeout) = mt.get_one::<u64>("timeout") {
+                    options.timeout = Duration::from_secs(*timeout);
+                }
+
+                // the peripheral may be left in bootloader mode, the gatt changed either way
+                let result = dfu::run(self.bt, &package, options).await;
+                self.update_helper_gatt().await;
+                result?;
+            }
+
+            Some(("smp", mt)) => {
+                let options = smp::SmpOptions {
+                    timeout: mt
+                        .get_one::<u64>("timeout")
+                        .map(|t| Duration::from_secs(*t))
+                        .unwrap_or(smp::DEFAULT_TIMEOUT),
+                    write_size: mt.get_one::<usize>("write-size").copied(),
+                };
+                let output = |arg: &clap::ArgMatches| {
+                    Output::from_name(arg.get_one::<String>("output"), self.output)
+                };
+
+                match mt.subcommand() {
+                    Some(("image", mt)) => match mt.subcommand() {
+                        Some(("list", arg)) => {
+                            commands::smp::image_list(self.bt, options, output(arg)).await?
+                        }
+                        Some(("upload", arg)) => {
+                            let file = path::Path::new(arg.get_one::<String>("file").unwrap());
+                            let image = *arg.get_one::<u32>("image").unwrap_or(&0);
+                            let packet_size = arg.get_one::<usize>("packet-size").copied();
+                            commands::smp::image_upload(self.bt, options, file, image, packet_size)
+                                .await?
+                        }
+                        Some(("test", arg)) => {
+                            let hash = arg.get_one::<String>("hash");
+                            commands::smp::image_state(self.bt, options, hash, false, output(arg))
+                                .await?
+                        }
+                        Some(("confirm", arg)) => {
+                            let hash = arg.get_one::<String>("hash");
+                            commands::smp::image_state(self.bt, options, hash, true, output(arg))
+                                .await?
+                        }
+                        _ => panic!("Code should never be here"),
+                    },
+                    Some(("reset", _)) => commands::smp::reset(self.bt, options).await?,
+                    Some(("echo", arg)) => {
+                        let text = arg.get_one::<String>("text").unwrap();
+                        commands::smp::echo(self.bt, options, text, output(arg)).await?
+                    }
+                    Some(("stat", arg)) => {
+                        let name = arg.get_one::<String>("name");
+                        commands::smp::stat(self.bt, options, name, output(arg)).await?
+                    }
+                    Some(("taskstat", arg)) => {
+                        commands::smp::taskstat(self.bt, options, output(arg)).await?
+                    }
+                    Some(("shell", arg)) => {
+                        let argv: Vec<String> =
+                            arg.get_many::<String>("argv").unwrap().cloned().collect();
+                        commands::smp::shell(self.bt, options, &argv, output(arg)).await?
+                    }
+                    _ => panic!("Code should never be here"),
+                }
             }
 
             Some(("indicate", mt)) => {
                 let mut service = mt.get_one::<String>("service").unwrap().clone();
                 let mut characteristic = mt.get_one::<String>("characteristic").unwrap().clone();
-                let format = mt.get_one::<String>("format").unwrap();
+                let format = mt.get_one::<String>("format");
 
                 self.try_replacing_service_and_characteristics_with_preset_defs(
                     &mut service,
                     &mut characteristic,
                 );
 
-                commands::indicate::indicate(self.bt, &service, &characteristic, format).await?;
+                let formatter = self.get_formatter(&service, &characteristic, format);
+                let output = Output::from_name(mt.get_one::<String>("output"), self.output);
+                commands::indicate::indicate(self.bt, &service, &characteristic, formatter, output)
+                    .await?;
             }
 
             Some(("notify", mt)) => {
                 let mut service = mt.get_one::<String>("service").unwrap().clone();
                 let mut characteristic = mt.get_one::<String>("characteristic").unwrap().clone();
-                let format = mt.get_one::<String>("format").unwrap();
+                let format = mt.get_one::<String>("format");
 
                 self.try_replacing_service_and_characteristics_with_preset_defs(
                     &mut service,
                     &mut characteristic,
                 );
 
-                commands::notify::notify(self.bt, &service, &characteristic, format).await?;
+                let formatter = self.get_formatter(&service, &characteristic, format);
+                let output = Output::from_name(mt.get_one::<String>("output"), self.output);
+                commands::notify::notify(self.bt, &service, &characteristic, formatter, output)
+                    .await?;
             }
 
             Some(("unsubscribe", mt)) => {
@@ -199,6 +515,73 @@ impl Repl<'_> {
             }
 
             Some(("preset", mt)) => {
+                match mt.subcommand() {
+                    Some(("load", arg)) => {
+                        let fp = path::Path::new(arg.get_one::<String>("file").unwrap());
+                        let pr = Preset::new(self.config.find_preset(fp))?;
+                        self.set_preset(pr);
+                        return Ok(());
+                    }
+                    Some(("reload", _)) => {
+                        let Some(preset) = &self.preset else {
+                            return Err("No preset loaded")?;
+                        };
+                        // the preset loaded is kept if the file is invalid
+                        let pr = Preset::new(preset.get_path().to_path_buf())?;
+                        self.set_preset(pr);
+                        return Ok(());
+                    }
+                    Some(("export", arg)) => {
+                        let fp = path::Path::new(arg.get_one::<String>("file").unwrap());
+                        if fp.exists() && !arg.get_flag("force") {
+                            Err(format!(
+                                "File '{}' already exists, use --force to overwrite it",
+                                fp.to_string_lossy()
+                            ))?;
+                        }
+                        // the user descriptions can only be read from a connected peripheral
+                        let (infos, descriptions) =
+                            if let Some(address) = arg.get_one::<String>("device") {
+                                (self.cached_gatt(address)?.to_infos(), Default::default())
+                            } else if self.bt.is_connected() {
+                                let infos = self.bt.get_peripheral_infos().await?;
+                                let descriptions =
+                                    export::read_user_descriptions(self.bt, &infos).await;
+                                (infos, descriptions)
+                            } else {
+                                Err("You must be connected to a peripheral to run this command")?
+                            };
+                        Preset::from_peripheral_infos(&infos, &descriptions).write_file(fp)?;
+                        println!("Preset written to {}", fp.to_string_lossy());
+                        return Ok(());
+                    }
+                    Some(("convert", arg)) => {
+                        let input = path::Path::new(arg.get_one::<String>("input").unwrap());
+                        let output = path::Path::new(arg.get_one::<String>("output").unwrap());
+                        if output.exists() && !arg.get_flag("force") {
+                            Err(format!(
+                                "File '{}' already exists, use --force to overwrite it",
+                                output.to_string_lossy()
+                            ))?;
+                        }
+
+                        // the preset is checked with its includes but they are kept as they are
+                        Preset::new(input.to_path_buf())?;
+                        Preset::read_file(input)?.write_file(output)?;
+                        println!("Preset written to {}", output.to_string_lossy());
+                        return Ok(());
+                    }
+                    Some(("unload", _)) => {
+                        if self.preset.is_none() {
+                            Err("No preset loaded")?;
+                        }
+                        self.unset_preset();
+                        println!("Preset unloaded");
+                        return Ok(());
+                    }
+                    _ => (),
+                }
+
                 if self.preset.is_none() {
                     Err("No preset loaded")?;
                 }
@@ -207,19 +590,23 @@ impl Repl<'_> {
                     self.preset.as_ref().unwrap().print();
                 } else {
                     match mt.subcommand() {
-                        Some(("command", arg)) => {
-                            let command_name = arg.get_one::<String>("command_name");
+                        Some(("command", arg)) => match arg.get_one::<String>("command_name") {
+                            None => self.preset.as_ref().unwrap().print_commands(),
+                            Some(command_name) => {
+                                let params: Vec<String> = arg
+                                    .get_many::<String>("params")
+                                    .unwrap_or_default()
+                                    .cloned()
+                                    .collect();
+                                let args = params::parse_args(&params)?;
 
-                            if command_name.is_none() {
-                                self.preset.as_ref().unwrap().print_commands();
-                            } else {
                                 self.preset
                                     .as_ref()
                                     .unwrap()
-                                    .run_command(self.bt, command_name.unwrap())
-                                    .await?;
+                                    .run_command(self.bt, command_name, &args)
+                                    .await?
                             }
-                        }
+                        },
                         Some(("function", arg)) => {
                             let function_name = arg.get_one::<String>("function_name").unwrap();
                             self.preset
@@ -240,8 +627,37 @@ impl Repl<'_> {
         Ok(())
     }
 
+    /// Parse and execute a line typed in the repl
+    async fn run_line(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+
+        let args = match shlex::split(&escape_handles(line)).ok_or("Parsing error: Invalid quoting")
+        {
+            Ok(a) => a,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+
+        match cli::cli().try_get_matches_from(&args) {
+            Err(e) => println!("{}", e),
+            Ok(matches) => {
+                if let Err(e) = self.execute_command(matches).await {
+                    eprintln!("{}", e)
+                }
+            }
+        }
+    }
+
     pub async fn start(&mut self) -> ! {
-        if self.editor.load_history(HISTORY_FP).is_err() {
+        // the history is saved after each line, in a directory that may not exist yet
+        if let Some(dir) = self.config.history_file.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if self.editor.load_history(&self.config.history_file).is_err() {
             println!("No previous history.");
         }
 
@@ -250,38 +666,63 @@ impl Repl<'_> {
                 self.preset
                     .as_ref()
                     .unwrap()
-                    .autoconnect(self.bt)
+                    .autoconnect(self.bt, self.config.scan_timeout)
                     .await
                     .unwrap();
+                self.update_helper_gatt().await;
             }
         }
 
+        for line in self.config.startup_commands.clone() {
+            println!("{}{}", self.config.prompt, line);
+            self.run_line(&line).await;
+        }
+
         loop {
             let line = self.get_line();
+            self.run_line(&line).await;
+        }
+    }
+}
 
-            if line.trim().is_empty() {
-                continue;
-            }
-
-            let args = match shlex::split(&line).ok_or("Parsing error: Invalid quoting") {
-                Ok(a) => a,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    continue;
-                }
-            };
-
-            let matches = cli::cli().try_get_matches_from(&args);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_handles() {
+        assert_eq!(
+            shlex::split(&escape_handles("write #0x002a 0xff")).unwrap(),
+            vec!["write", "#0x002a", "0xff"]
+        );
+        assert_eq!(
+            shlex::split(&escape_handles("write a b '\"#1\"'")).unwrap(),
+            vec!["write", "a", "b", "\"#1\""]
+        );
+        assert_eq!(escape_handles("write a b 0x#"), "write a b 0x#");
+
+        // a '#' without a handle still starts a comment
+        assert_eq!(
+            shlex::split(&escape_handles("disconnect # disconnect from device")).unwrap(),
+            vec!["disconnect"]
+        );
+        assert_eq!(
+            shlex::split(&escape_handles("read #42 #comment")).unwrap(),
+            vec!["read", "#42"]
+        );
+    }
 
-            if matches.is_err() {
-                println!("{}", matches.unwrap_err());
-                continue;
-            } else {
-                match self.execute_command(matches.unwrap()).await {
-                    Ok(_) => (),
-                    Err(e) => eprintln!("{}", e),
-                }
-            }
-        }
+    #[test]
+    fn test_smp_shell_arguments() {
+        let matches = cli::cli()
+            .try_get_matches_from(["smp", "--timeout", "3", "shell", "-o", "json", "log", "-l"])
+            .unwrap();
+        let (_, smp) = matches.subcommand().unwrap();
+        let (_, shell) = smp.subcommand().unwrap();
+
+        assert_eq!(shell.get_one::<u64>("timeout"), Some(&3));
+        assert_eq!(shell.get_one::<String>("output").unwrap(), "json");
+        let argv: Vec<&String> = shell.get_many::<String>("argv").unwrap().collect();
+        assert_eq!(argv, ["log", "-l"]);
     }
 }
@@ -0,0 +1,177 @@
+use ciborium::Value;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use super::{field, field_integer, map, to_hex, SmpClient, HEADER_SIZE};
+use super::{GROUP_IMAGE, IMAGE_STATE, IMAGE_UPLOAD, OP_READ, OP_WRITE};
+
+/// Image in a slot of the peripheral
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageSlot {
+    pub image: u32,
+    pub slot: u32,
+    pub version: String,
+
+    /// SHA256 of the image as hex digits, it selects the image to test or confirm
+    pub hash: String,
+
+    pub bootable: bool,
+    pub pending: bool,
+    pub confirmed: bool,
+    pub active: bool,
+    pub permanent: bool,
+}
+
+impl ImageSlot {
+    fn from_value(value: &Value) -> ImageSlot {
+        let flag = |key| field(value, key).and_then(|v| v.as_bool()) == Some(true);
+
+        ImageSlot {
+            image: field_integer(value, "image").unwrap_or(0) as u32,
+            slot: field_integer(value, "slot").unwrap_or(0) as u32,
+            version: field(value, "version")
+                .and_then(|v| v.as_text())
+                .unwrap_or_default()
+                .to_owned(),
+            hash: field(value, "hash")
+                .and_then(|v| v.as_bytes())
+                .map(|h| to_hex(h))
+                .unwrap_or_default(),
+            bootable: flag("bootable"),
+            pending: flag("pending"),
+            confirmed: flag("confirmed"),
+            active: flag("active"),
+            permanent: flag("permanent"),
+        }
+    }
+
+    /// Names of the flags set
+    pub fn flags(&self) -> Vec<&'static str> {
+        [
+            (self.bootable, "bootable"),
+            (self.pending, "pending"),
+            (self.confirmed, "confirmed"),
+            (self.active, "active"),
+            (self.permanent, "permanent"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
+fn image_slots(response: &Value) -> Result<Vec<ImageSlot>, Box<dyn Error>> {
+    match field(response, "images").and_then(|i| i.as_array()) {
+        Some(images) => Ok(images.iter().map(ImageSlot::from_value).collect()),
+        None => Err("SMP response without images")?,
+    }
+}
+
+impl SmpClient<'_> {
+    /// Images in the slots of the peripheral
+    pub async fn image_list(&mut self) -> Result<Vec<ImageSlot>, Box<dyn Error>> {
+        let response = self
+            .request(OP_READ, GROUP_IMAGE, IMAGE_STATE, map(Vec::new()))
+            .await?;
+        image_slots(&response)
+    }
+
+    /// Mark an image to be booted once at the next reset, or for good when it is confirmed. The
+    /// running image is confirmed when no hash is given
+    pub async fn image_state(
+        &mut self,
+        hash: Option<&[u8]>,
+        confirm: bool,
+    ) -> Result<Vec<ImageSlot>, Box<dyn Error>> {
+        let mut entries = vec![("confirm", Value::Bool(confirm))];
+        if let Some(hash) = hash {
+            entries.push(("hash", Value::Bytes(hash.to_vec())));
+        }
+
+        let response = self
+            .request(OP_WRITE, GROUP_IMAGE, IMAGE_STATE, map(entries))
+            .await?;
+        image_slots(&response)
+    }
+
+    /// Upload an image in requests of at most packet_size bytes. The peripheral answers with the
+    /// offset it expects next, it resumes an interrupted upload of the same image
+    pub async fn image_upload(
+        &mut self,
+        data: &[u8],
+        image: u32,
+        packet_size: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        if data.is_empty() {
+            Err("The image is empty")?
+        }
+        let sha = Sha256::digest(data).to_vec();
+
+        let start = Instant::now();
+        let mut offset = 0;
+        let mut sent = 0;
+
+        while offset < data.len() {
+            // the first request describes the image
+            let mut entries = vec![("off", Value::from(offset as u64))];
+            if offset == 0 {
+                entries.push(("len", Value::from(data.len() as u64)));
+                entries.push(("sha", Value::Bytes(sha.clone())));
+                if image != 0 {
+                    entries.push(("image", Value::from(image)));
+                }
+            }
+
+            // room left by the header, the other fields, the data key and the length of the
+            // data (at most 3 bytes up to 65535 bytes)
+            let mut fields = Vec::new();
+            ciborium::into_writer(&map(entries.clone()), &mut fields)?;
+            let used = HEADER_SIZE + fields.len() + 1 + "data".len() + 3;
+            if packet_size <= used {
+                Err(format!(
+                    "The packet size ({} bytes) is too small for the upload requests",
+                    packet_size
+                ))?
+            }
+            let end = (offset + packet_size - used).min(data.len());
+            entries.push(("data", Value::Bytes(data[offset..end].to_vec())));
+
+            let response = self
+                .request(OP_WRITE, GROUP_IMAGE, IMAGE_UPLOAD, map(entries))
+                .await?;
+            let next = match field_integer(&response, "off") {
+                Some(next) if next > offset as i128 && next <= data.len() as i128 => next as usize,
+                _ => Err(format!("The upload didn't progress at {} bytes", offset))?,
+            };
+
+            if offset == 0 && next > end {
+                eprintln!("Resuming the upload at {} bytes", next);
+            } else {
+                sent += end - offset;
+            }
+            offset = next;
+
+            eprint!(
+                "\r{}/{} bytes ({}%)",
+                offset,
+                data.len(),
+                offset * 100 / data.len()
+            );
+            let _ = io::stderr().flush();
+        }
+
+        let elapsed = start.elapsed().as_secs_f64();
+        eprintln!();
+        eprintln!(
+            "Uploaded {} bytes in {:.2} s ({:.0} bytes/s)",
+            sent,
+            elapsed,
+            sent as f64 / elapsed.max(f64::EPSILON)
+        );
+        Ok(())
+    }
+}
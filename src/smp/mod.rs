@@ -0,0 +1,357 @@
+use ciborium::Value;
+use std::error::Error;
+use std::time::Duration;
+
+use crate::bluetooth_numbers::compare_uuid::normalize_uuid;
+use crate::controllers::BleController;
+use crate::repl::commands;
+
+mod image;
+mod os;
+
+pub use image::ImageSlot;
+pub use os::ShellOutput;
+
+/// SMP service and characteristic, requests are written without response and the responses are
+/// notified
+pub const SERVICE_UUID: &str = "8d53dc1d-1db7-4cd3-868b-8a527460aa84";
+pub const CHARACTERISTIC_UUID: &str = "da2e7828-fbce-4e01-ae9e-261174997c48";
+
+/// Operations of the header
+pub const OP_READ: u8 = 0;
+pub const OP_READ_RESPONSE: u8 = 1;
+pub const OP_WRITE: u8 = 2;
+pub const OP_WRITE_RESPONSE: u8 = 3;
+
+/// Management groups
+pub const GROUP_OS: u16 = 0;
+pub const GROUP_IMAGE: u16 = 1;
+pub const GROUP_STAT: u16 = 2;
+pub const GROUP_SHELL: u16 = 9;
+
+/// Commands of the OS group
+pub const OS_ECHO: u8 = 0;
+pub const OS_TASKSTAT: u8 = 2;
+pub const OS_RESET: u8 = 5;
+
+/// Commands of the image group
+pub const IMAGE_STATE: u8 = 0;
+pub const IMAGE_UPLOAD: u8 = 1;
+
+/// Commands of the stat group
+pub const STAT_READ: u8 = 0;
+pub const STAT_LIST: u8 = 1;
+
+/// Command of the shell group
+pub const SHELL_EXEC: u8 = 0;
+
+pub const HEADER_SIZE: usize = 8;
+
+/// Time waited for a response when none is given
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Smallest size of the upload requests when the MTU is smaller, they are split in several writes
+pub const MIN_PACKET_SIZE: usize = 128;
+
+/// Header of the SMP packets, followed by a CBOR map of `len` bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    pub op: u8,
+    pub flags: u8,
+    pub len: u16,
+    pub group: u16,
+    pub seq: u8,
+    pub id: u8,
+}
+
+impl Header {
+    pub fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let [len_high, len_low] = self.len.to_be_bytes();
+        let [group_high, group_low] = self.group.to_be_bytes();
+        [
+            self.op, self.flags, len_high, len_low, group_high, group_low, self.seq, self.id,
+        ]
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Header> {
+        match bytes {
+            [op, flags, len_high, len_low, group_high, group_low, seq, id, ..] => Some(Header {
+                // the SMP version is in bits 3 and 4 of the operation
+                op: op & 0x07,
+                flags: *flags,
+                len: u16::from_be_bytes([*len_high, *len_low]),
+                group: u16::from_be_bytes([*group_high, *group_low]),
+                seq: *seq,
+                id: *id,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Header and CBOR body of a packet
+pub fn encode(header: Header, body: &Value) -> Vec<u8> {
+    let mut payload = Vec::new();
+    ciborium::into_writer(body, &mut payload).expect("writing to a vec doesn't fail");
+
+    let header = Header {
+        len: payload.len() as u16,
+        ..header
+    };
+    let mut packet = header.to_bytes().to_vec();
+    packet.extend(payload);
+    packet
+}
+
+/// Header and CBOR body of a complete packet
+pub fn decode(packet: &[u8]) -> Result<(Header, Value), String> {
+    let header = Header::parse(packet).ok_or("SMP packet too short")?;
+    let body = packet
+        .get(HEADER_SIZE..HEADER_SIZE + header.len as usize)
+        .ok_or("SMP packet shorter than its header length")?;
+    let body = ciborium::from_reader(body).map_err(|e| format!("Invalid SMP payload: {}", e))?;
+    Ok((header, body))
+}
+
+/// CBOR map with text keys
+pub fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (Value::Text(key.to_owned()), value))
+            .collect(),
+    )
+}
+
+/// Value of a key of a CBOR map
+pub fn field<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    value
+        .as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+pub fn field_integer(value: &Value, key: &str) -> Option<i128> {
+    field(value, key)?.as_integer().map(i128::from)
+}
+
+fn error_name(rc: i128) -> &'static str {
+    match rc {
+        1 => "unknown error",
+        2 => "out of memory",
+        3 => "invalid value",
+        4 => "timeout",
+        5 => "not found",
+        6 => "bad state",
+        7 => "message too large",
+        8 => "not supported",
+        9 => "corrupted",
+        10 => "busy",
+        11 => "access denied",
+        _ => "error",
+    }
+}
+
+/// Error of a response: the rc of SMP version 1 or the err map of version 2
+fn check_error(response: &Value) -> Result<(), String> {
+    if let Some(rc) = field_integer(response, "rc").filter(|rc| *rc != 0) {
+        return Err(format!("SMP error {}: {}", rc, error_name(rc)));
+    }
+    if let Some(err) = field(response, "err") {
+        let group = field_integer(err, "group").unwrap_or(-1);
+        let rc = field_integer(err, "rc").unwrap_or(-1);
+        if rc != 0 {
+            return Err(format!("SMP error {} of group {}", rc, group));
+        }
+    }
+    Ok(())
+}
+
+/// Hash printed as hex digits
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hash given as hex digits
+pub fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.trim_start_matches("0x");
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(format!("Invalid hash '{}', expected hex digits", hex));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+/// Options of the SMP client
+#[derive(Debug, Clone, Copy)]
+pub struct SmpOptions {
+    /// Time waited for each response of the peripheral
+    pub timeout: Duration,
+
+    /// Largest write, None for the MTU minus 3 (20 bytes when the backend doesn't report the MTU)
+    pub write_size: Option<usize>,
+}
+
+impl Default for SmpOptions {
+    fn default() -> Self {
+        SmpOptions {
+            timeout: DEFAULT_TIMEOUT,
+            write_size: None,
+        }
+    }
+}
+
+/// Client of the Simple Management Protocol (MCUmgr) of the connected peripheral
+pub struct SmpClient<'a> {
+    bt: &'a mut dyn BleController,
+    seq: u8,
+    timeout: Duration,
+
+    /// Largest write, bigger requests are split and reassembled by the peripheral
+    write_size: usize,
+}
+
+impl SmpClient<'_> {
+    pub async fn new(
+        bt: &mut dyn BleController,
+        options: SmpOptions,
+    ) -> Result<SmpClient<'_>, Box<dyn Error>> {
+        if !bt.is_connected() {
+            Err("You must be connected to a peripheral to run this command")?;
+        }
+
+        let infos = bt.get_peripheral_infos().await?;
+        let found = infos
+            .services
+            .iter()
+            .filter(|s| normalize_uuid(&s.uuid) == SERVICE_UUID)
+            .flat_map(|s| &s.characteristics)
+            .any(|c| normalize_uuid(&c.uuid) == CHARACTERISTIC_UUID);
+        if !found {
+            Err("The peripheral doesn't have the SMP service")?
+        }
+
+        let write_size = match options.write_size {
+            Some(0) => Err("The write size must be at least 1 byte")?,
+            Some(size) => size,
+            None => commands::write::mtu_chunk_size(bt).await,
+        };
+        bt.notify(SERVICE_UUID, CHARACTERISTIC_UUID, None).await?;
+
+        Ok(SmpClient {
+            bt,
+            seq: 0,
+            timeout: options.timeout,
+            write_size,
+        })
+    }
+
+    /// Size of the upload requests: a single write, or MIN_PACKET_SIZE for small MTUs
+    pub fn default_packet_size(&self) -> usize {
+        self.write_size.max(MIN_PACKET_SIZE)
+    }
+
+    /// Send a request and return the body of its response, a response with an error code fails
+    pub async fn request(
+        &mut self,
+        op: u8,
+        group: u16,
+        id: u8,
+        body: Value,
+    ) -> Result<Value, Box<dyn Error>> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+
+        let header = Header {
+            op,
+            flags: 0,
+            len: 0,
+            group,
+            seq,
+            id,
+        };
+        for chunk in encode(header, &body).chunks(self.write_size) {
+            self.bt
+                .write(SERVICE_UUID, CHARACTERISTIC_UUID, chunk, false)
+                .await?;
+        }
+
+        // the response is split in notifications of the MTU size
+        let mut packet = Vec::new();
+        loop {
+            packet.extend(
+                self.bt
+                    .wait_notification(SERVICE_UUID, CHARACTERISTIC_UUID, self.timeout)
+                    .await?,
+            );
+            match Header::parse(&packet) {
+                Some(h) if packet.len() >= HEADER_SIZE + h.len as usize => break,
+                _ => (),
+            }
+        }
+
+        let (response_header, response) = decode(&packet)?;
+        if response_header.op != op + 1
+            || response_header.group != group
+            || response_header.id != id
+            || response_header.seq != seq
+        {
+            Err(format!(
+                "Unexpected SMP response: {:?} to {:?}",
+                response_header, header
+            ))?
+        }
+        check_error(&response)?;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let header = Header {
+            op: OP_WRITE,
+            flags: 0,
+            len: 0,
+            group: GROUP_OS,
+            seq: 42,
+            id: OS_ECHO,
+        };
+        let packet = encode(header, &map(vec![("d", Value::Text("hello".to_owned()))]));
+        assert_eq!(packet[..8], [2, 0, 0, 9, 0, 0, 42, 0]);
+
+        let (decoded, body) = decode(&packet).unwrap();
+        assert_eq!(decoded, Header { len: 9, ..header });
+        assert_eq!(field(&body, "d").and_then(|d| d.as_text()), Some("hello"));
+        assert!(decode(&packet[..10]).is_err());
+    }
+
+    #[test]
+    fn test_check_error() {
+        assert!(check_error(&map(vec![("rc", Value::from(0))])).is_ok());
+        assert_eq!(
+            check_error(&map(vec![("rc", Value::from(8))])),
+            Err("SMP error 8: not supported".to_owned())
+        );
+        let err = map(vec![("group", Value::from(1)), ("rc", Value::from(3))]);
+        assert_eq!(
+            check_error(&map(vec![("err", err)])),
+            Err("SMP error 3 of group 1".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(parse_hex("0x01ab"), Ok(vec![0x01, 0xab]));
+        assert_eq!(to_hex(&[0x01, 0xab]), "01ab");
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+}
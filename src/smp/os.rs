@@ -0,0 +1,118 @@
+use ciborium::Value;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use super::{field, field_integer, map, SmpClient};
+use super::{GROUP_OS, GROUP_SHELL, GROUP_STAT, OP_READ, OP_WRITE};
+use super::{OS_ECHO, OS_RESET, OS_TASKSTAT, SHELL_EXEC, STAT_LIST, STAT_READ};
+
+/// Output of a shell command run on the peripheral
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShellOutput {
+    pub output: String,
+    pub ret: i64,
+}
+
+/// Integer values of a CBOR map by key, the other values are skipped
+fn integers(value: Option<&Value>) -> BTreeMap<String, i64> {
+    value
+        .and_then(|v| v.as_map())
+        .into_iter()
+        .flatten()
+        .filter_map(|(k, v)| {
+            let value = i64::try_from(i128::from(v.as_integer()?)).ok()?;
+            Some((k.as_text()?.to_owned(), value))
+        })
+        .collect()
+}
+
+impl SmpClient<'_> {
+    /// Text sent back by the peripheral
+    pub async fn echo(&mut self, text: &str) -> Result<String, Box<dyn Error>> {
+        let response = self
+            .request(
+                OP_WRITE,
+                GROUP_OS,
+                OS_ECHO,
+                map(vec![("d", Value::Text(text.to_owned()))]),
+            )
+            .await?;
+
+        match field(&response, "r").and_then(|r| r.as_text()) {
+            Some(text) => Ok(text.to_owned()),
+            None => Err("SMP response without the echoed text")?,
+        }
+    }
+
+    /// Reset the peripheral, it closes the connection
+    pub async fn reset(&mut self) -> Result<(), Box<dyn Error>> {
+        self.request(OP_WRITE, GROUP_OS, OS_RESET, map(Vec::new()))
+            .await?;
+        Ok(())
+    }
+
+    /// Statistics of the tasks of the OS (priority, stack usage ...) by task name
+    pub async fn taskstat(
+        &mut self,
+    ) -> Result<BTreeMap<String, BTreeMap<String, i64>>, Box<dyn Error>> {
+        let response = self
+            .request(OP_READ, GROUP_OS, OS_TASKSTAT, map(Vec::new()))
+            .await?;
+
+        Ok(field(&response, "tasks")
+            .and_then(|t| t.as_map())
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, stats)| Some((name.as_text()?.to_owned(), integers(Some(stats)))))
+            .collect())
+    }
+
+    /// Names of the statistics groups
+    pub async fn stat_list(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+        let response = self
+            .request(OP_READ, GROUP_STAT, STAT_LIST, map(Vec::new()))
+            .await?;
+
+        Ok(field(&response, "stat_list")
+            .and_then(|l| l.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|name| name.as_text().map(|n| n.to_owned()))
+            .collect())
+    }
+
+    /// Counters of a statistics group
+    pub async fn stat_read(&mut self, name: &str) -> Result<BTreeMap<String, i64>, Box<dyn Error>> {
+        let response = self
+            .request(
+                OP_READ,
+                GROUP_STAT,
+                STAT_READ,
+                map(vec![("name", Value::Text(name.to_owned()))]),
+            )
+            .await?;
+        Ok(integers(field(&response, "fields")))
+    }
+
+    /// Run a command of the shell of the peripheral
+    pub async fn shell(&mut self, argv: &[String]) -> Result<ShellOutput, Box<dyn Error>> {
+        let argv = argv.iter().map(|a| Value::Text(a.clone())).collect();
+        let response = self
+            .request(
+                OP_WRITE,
+                GROUP_SHELL,
+                SHELL_EXEC,
+                map(vec![("argv", Value::Array(argv))]),
+            )
+            .await?;
+
+        Ok(ShellOutput {
+            output: field(&response, "o")
+                .and_then(|o| o.as_text())
+                .unwrap_or_default()
+                .to_owned(),
+            ret: field_integer(&response, "ret").unwrap_or(0) as i64,
+        })
+    }
+}
@@ -1,3 +1,5 @@
+pub mod output;
+pub mod payload;
 pub mod print_bytes;
 
 use std::io::Write;
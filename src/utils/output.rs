@@ -0,0 +1,52 @@
+use serde::Serialize;
+
+/// Names of the outputs of the -o flag
+pub const OUTPUTS: [&str; 2] = ["text", "json"];
+
+/// How the results of the commands are printed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Output {
+    /// Tables and text for humans
+    #[default]
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
+impl Output {
+    /// Output given by a -o flag, or the default one
+    pub fn from_name(name: Option<&String>, default: Output) -> Output {
+        match name.map(|n| n.as_str()) {
+            Some("json") => Output::Json,
+            Some("text") => Output::Text,
+            _ => default,
+        }
+    }
+}
+
+/// Print a value as a JSON object on one line
+pub fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string(value).expect("values printed should be serializable")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_from_name() {
+        assert_eq!(
+            Output::from_name(Some(&"json".to_owned()), Output::Text),
+            Output::Json
+        );
+        assert_eq!(
+            Output::from_name(Some(&"text".to_owned()), Output::Json),
+            Output::Text
+        );
+        assert_eq!(Output::from_name(None, Output::Json), Output::Json);
+    }
+}
@@ -0,0 +1,335 @@
+use base64::Engine;
+use str_to_bytes::str_to_bytes;
+
+/// Largest payload, repeats that would give more bytes are rejected before allocating them
+pub const MAX_PAYLOAD_SIZE: usize = 65536;
+
+/// Parse a payload written with the payload syntax into bytes.
+///
+/// A payload is a list of values concatenated together:
+/// - untyped numbers (`0xff`, `0b101`, `12`) and `ASCII(text)` as parsed by str_to_bytes
+/// - typed numbers `u8:3`, `u16le:1200`, `i32be:-5`, `f32le:1.5` ...
+/// - quoted utf8 strings `"some text"`
+/// - raw bytes `hex:deadbeef` and `b64:3q2+7w==`
+/// - groups `(u8:1 u16le:2)`
+///
+/// Any value or group can be repeated with `*n`, e.g. `u8:0*16` or `(u8:1 u8:2)*4`. A payload
+/// can't be longer than MAX_PAYLOAD_SIZE bytes
+pub fn parse(payload: &str) -> Result<Vec<u8>, String> {
+    let mut parser = Parser {
+        chars: payload.chars().collect(),
+        pos: 0,
+    };
+
+    let bytes = parser.parse_sequence(false)?;
+
+    if bytes.is_empty() {
+        return Err("Empty payload".to_owned());
+    }
+    Ok(bytes)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespaces(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_sequence(&mut self, nested: bool) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+
+        loop {
+            self.skip_whitespaces();
+
+            match self.peek() {
+                None if nested => return Err("Missing closing parenthesis".to_owned()),
+                None => return Ok(bytes),
+                Some(')') if nested => {
+                    self.pos += 1;
+                    return Ok(bytes);
+                }
+                Some(')') => return Err("Unexpected closing parenthesis".to_owned()),
+                Some(_) => {
+                    let value = self.parse_value()?;
+                    let count = self.parse_repeat()?;
+                    match value
+                        .len()
+                        .checked_mul(count)
+                        .and_then(|len| len.checked_add(bytes.len()))
+                    {
+                        Some(len) if len <= MAX_PAYLOAD_SIZE => bytes.extend(value.repeat(count)),
+                        _ => return Err(format!("Payload longer than {} bytes", MAX_PAYLOAD_SIZE)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Vec<u8>, String> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                self.parse_sequence(true)
+            }
+            Some('"') => self.parse_string(),
+            _ => {
+                let word = self.parse_word();
+                parse_word(&word)
+            }
+        }
+    }
+
+    /// Parse an optional `*n` suffix
+    fn parse_repeat(&mut self) -> Result<usize, String> {
+        if self.peek() != Some('*') {
+            return Ok(1);
+        }
+        self.pos += 1;
+
+        let count = self.parse_word();
+        match count.parse::<usize>() {
+            Ok(n) if n <= MAX_PAYLOAD_SIZE => Ok(n),
+            Ok(_) => Err(format!(
+                "Repeat count {} larger than {}",
+                count, MAX_PAYLOAD_SIZE
+            )),
+            Err(_) => Err(format!("Invalid repeat count: '{}'", count)),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<Vec<u8>, String> {
+        let mut text = String::new();
+        self.pos += 1;
+
+        loop {
+            match self.peek() {
+                None => return Err("Missing closing quote".to_owned()),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(text.into_bytes());
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('n') => text.push('\n'),
+                        Some('r') => text.push('\r'),
+                        Some('t') => text.push('\t'),
+                        Some('0') => text.push('\0'),
+                        Some(c) => text.push(c),
+                        None => return Err("Missing closing quote".to_owned()),
+                    }
+                    self.pos += 1;
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Read until a separator, parenthesis are kept balanced for ASCII(...)
+    fn parse_word(&mut self) -> String {
+        let mut word = String::new();
+        let mut depth = 0;
+
+        while let Some(c) = self.peek() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                '*' if depth == 0 => break,
+                c if c.is_whitespace() && depth == 0 => break,
+                _ => (),
+            }
+            word.push(c);
+            self.pos += 1;
+        }
+        word
+    }
+}
+
+fn parse_word(word: &str) -> Result<Vec<u8>, String> {
+    if let Some(text) = word
+        .strip_prefix("ASCII(")
+        .and_then(|w| w.strip_suffix(')'))
+    {
+        return Ok(text.as_bytes().to_vec());
+    }
+
+    let Some((kind, value)) = word.split_once(':') else {
+        return str_to_bytes(word).map_err(|_| format!("Invalid value: '{}'", word));
+    };
+
+    match kind {
+        "hex" => parse_hex(value),
+        "b64" => base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .map_err(|e| format!("Invalid base64 '{}': {}", value, e)),
+        "utf8" => Ok(value.as_bytes().to_vec()),
+        _ => parse_typed_number(kind, value),
+    }
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = value.chars().filter(|c| *c != '_').collect();
+
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits: '{}'", value));
+    }
+
+    digits
+        .chunks(2)
+        .map(|d| {
+            let byte: String = d.iter().collect();
+            u8::from_str_radix(&byte, 16).map_err(|_| format!("Invalid hex: '{}'", value))
+        })
+        .collect()
+}
+
+/// Parse an integer written in decimal, hexadecimal (0x) or binary (0b)
+fn parse_integer(value: &str) -> Option<i128> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, value),
+    };
+
+    let n = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i128::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i128::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i128>().ok()?
+    };
+
+    Some(if negative { -n } else { n })
+}
+
+fn parse_typed_number(kind: &str, value: &str) -> Result<Vec<u8>, String> {
+    let (base, big_endian) = if let Some(base) = kind.strip_suffix("be") {
+        (base, true)
+    } else if let Some(base) = kind.strip_suffix("le") {
+        (base, false)
+    } else {
+        (kind, false)
+    };
+
+    let invalid = || format!("Invalid {} value: '{}'", kind, value);
+
+    let mut bytes: Vec<u8> = match base {
+        "f32" => value
+            .parse::<f32>()
+            .map_err(|_| invalid())?
+            .to_le_bytes()
+            .to_vec(),
+        "f64" => value
+            .parse::<f64>()
+            .map_err(|_| invalid())?
+            .to_le_bytes()
+            .to_vec(),
+        _ => {
+            let (signed, bits) = match base.split_at_checked(1) {
+                Some(("u", bits)) => (false, bits),
+                Some(("i", bits)) => (true, bits),
+                _ => return Err(format!("Unknown type: '{}'", kind)),
+            };
+            let size = match bits {
+                "8" => 1,
+                "16" => 2,
+                "32" => 4,
+                "64" => 8,
+                _ => return Err(format!("Unknown type: '{}'", kind)),
+            };
+
+            let n = parse_integer(value).ok_or_else(invalid)?;
+            let (min, max) = if signed {
+                (-(1i128 << (size * 8 - 1)), (1i128 << (size * 8 - 1)) - 1)
+            } else {
+                (0, (1i128 << (size * 8)) - 1)
+            };
+            if n < min || n > max {
+                return Err(format!("Value out of range for {}: '{}'", kind, value));
+            }
+
+            n.to_le_bytes()[..size].to_vec()
+        }
+    };
+
+    if big_endian {
+        bytes.reverse();
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_legacy() {
+        assert_eq!(parse("0xff 0X44").unwrap(), vec![0xff, 0x44]);
+        assert_eq!(parse("12 0b11").unwrap(), vec![12, 0b11]);
+        assert_eq!(parse("0xff00ff").unwrap(), vec![0xff, 0x00, 0xff]);
+        assert_eq!(parse("ASCII(Hi mom)").unwrap(), b"Hi mom".to_vec());
+        assert!(parse("").is_err());
+        assert!(parse("0xzz").is_err());
+    }
+
+    #[test]
+    fn test_parse_typed() {
+        assert_eq!(parse("u8:3").unwrap(), vec![3]);
+        assert_eq!(parse("u16le:1200").unwrap(), vec![0xb0, 0x04]);
+        assert_eq!(parse("u16be:1200").unwrap(), vec![0x04, 0xb0]);
+        assert_eq!(parse("u16:0x1234").unwrap(), vec![0x34, 0x12]);
+        assert_eq!(parse("i32be:-5").unwrap(), vec![0xff, 0xff, 0xff, 0xfb]);
+        assert_eq!(parse("i8:-128").unwrap(), vec![0x80]);
+        assert_eq!(parse("f32le:1.5").unwrap(), vec![0x00, 0x00, 0xc0, 0x3f]);
+        assert_eq!(parse("f32be:1.5").unwrap(), vec![0x3f, 0xc0, 0x00, 0x00]);
+        assert_eq!(parse("hex:deadbeef").unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse("b64:3q2+7w==").unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse("\"utf8 text\"").unwrap(), b"utf8 text".to_vec());
+        assert_eq!(parse("\"a\\\"b\\n\"").unwrap(), b"a\"b\n".to_vec());
+
+        assert!(parse("u8:256").is_err());
+        assert!(parse("i8:128").is_err());
+        assert!(parse("u8:-1").is_err());
+        assert!(parse("u24:1").is_err());
+        assert!(parse("hex:abc").is_err());
+        assert!(parse("\"abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_repeat_and_concat() {
+        assert_eq!(parse("u8:0*4").unwrap(), vec![0, 0, 0, 0]);
+        assert_eq!(
+            parse("0x03 (u8:1 u16be:2)*2 \"ok\"").unwrap(),
+            vec![0x03, 1, 0, 2, 1, 0, 2, b'o', b'k']
+        );
+        assert_eq!(parse("\"ab\"*2").unwrap(), b"abab".to_vec());
+
+        assert!(parse("u8:1*x").is_err());
+        assert!(parse("(u8:1").is_err());
+        assert!(parse("u8:1)").is_err());
+
+        assert_eq!(parse("u8:0*65536").unwrap().len(), MAX_PAYLOAD_SIZE);
+        assert!(parse("u8:0*99999999999999").is_err());
+        assert!(parse("(u8:0*65536)*65536").is_err());
+        assert!(parse("u8:0*65536 u8:1").is_err());
+    }
+}
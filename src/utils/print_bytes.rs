@@ -1,6 +1,16 @@
 use rhexdump::prelude::*;
 
 use std::str;
+use std::sync::Arc;
+
+/// Turn a value into the text printed for it
+pub type Formatter = Arc<dyn Fn(&[u8]) -> String + Send + Sync>;
+
+/// Formatter printing values with one of the bytes_to_str formats
+pub fn formatter(format: &str) -> Formatter {
+    let format = format.to_owned();
+    Arc::new(move |bytes: &[u8]| bytes_to_str(&bytes.to_vec(), &format))
+}
 
 pub fn bytes_to_str(bytes: &Vec<u8>, format: &str) -> String {
     let mut out: String = String::new();
@@ -0,0 +1,385 @@
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+use bluerepl::controllers::simulated::{SimulatedController, SimulatedDevice, SimulatedPeripheral};
+use bluerepl::controllers::{BleController, Characteristic, CharacteristicProperties, Service};
+use bluerepl::dfu::{self, DfuOptions, Package};
+
+const ADDRESS: &str = "F0:0D:00:00:DF:01";
+const COMMAND_MAX_SIZE: u32 = 256;
+const DATA_MAX_SIZE: u32 = 4096;
+
+/// State of the simulated bootloader, the init packets are the size of their firmware
+#[derive(Default)]
+struct BootloaderState {
+    prn: u16,
+    packets: u16,
+    object_type: u8,
+    command: Vec<u8>,
+    firmware: Vec<u8>,
+    executed: usize,
+
+    /// Bytes received on the packet characteristic
+    received: usize,
+
+    /// Init packets and firmwares of the images activated
+    activated: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl BootloaderState {
+    fn object(&self) -> &Vec<u8> {
+        match self.object_type {
+            dfu::OBJECT_COMMAND => &self.command,
+            _ => &self.firmware,
+        }
+    }
+
+    fn checksum(&self) -> Vec<u8> {
+        let object = self.object();
+        let mut payload = (object.len() as u32).to_le_bytes().to_vec();
+        payload.extend(crc32fast::hash(object).to_le_bytes());
+        payload
+    }
+
+    fn control_point(&mut self, request: &[u8]) -> Vec<u8> {
+        let payload = match request {
+            [dfu::OP_SET_PRN, low, high] => {
+                self.prn = u16::from_le_bytes([*low, *high]);
+                Vec::new()
+            }
+            [dfu::OP_SELECT, object_type] => {
+                self.object_type = *object_type;
+                let max_size = match *object_type {
+                    dfu::OBJECT_COMMAND => COMMAND_MAX_SIZE,
+                    _ => DATA_MAX_SIZE,
+                };
+                let mut payload = max_size.to_le_bytes().to_vec();
+                payload.extend(self.checksum());
+                payload
+            }
+            [dfu::OP_CREATE, object_type, ..] => {
+                self.object_type = *object_type;
+                self.packets = 0;
+                match *object_type {
+                    dfu::OBJECT_COMMAND => self.command.clear(),
+                    _ => self.firmware.truncate(self.executed),
+                }
+                Vec::new()
+            }
+            [dfu::OP_CALCULATE_CHECKSUM] => self.checksum(),
+            [dfu::OP_EXECUTE] => {
+                if self.object_type == dfu::OBJECT_DATA {
+                    self.executed = self.firmware.len();
+                    let size = u32::from_le_bytes(self.command[..4].try_into().unwrap());
+                    if self.executed == size as usize {
+                        let command = std::mem::take(&mut self.command);
+                        let firmware = std::mem::take(&mut self.firmware);
+                        self.activated.push((command, firmware));
+                        self.executed = 0;
+                    }
+                }
+                Vec::new()
+            }
+            _ => return vec![dfu::OP_RESPONSE, request[0], 0x02],
+        };
+
+        let mut response = vec![dfu::OP_RESPONSE, request[0], dfu::RESULT_SUCCESS];
+        response.extend(payload);
+        response
+    }
+}
+
+struct Bootloader(Arc<Mutex<BootloaderState>>);
+
+impl SimulatedDevice for Bootloader {
+    fn read(&mut self, _service: &str, characteristic: &str) -> Result<Vec<u8>, String> {
+        Err(format!("Characteristic {} isn't readable", characteristic))
+    }
+
+    fn write(
+        &mut self,
+        _service: &str,
+        characteristic: &str,
+        value: &[u8],
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let mut state = self.0.lock().unwrap();
+
+        if characteristic == dfu::CONTROL_POINT_UUID {
+            let response = state.control_point(value);
+            return Ok(vec![(dfu::CONTROL_POINT_UUID.to_owned(), response)]);
+        }
+
+        match state.object_type {
+            dfu::OBJECT_COMMAND => state.command.extend(value),
+            _ => state.firmware.extend(value),
+        }
+        state.received += value.len();
+        state.packets += 1;
+
+        if state.prn > 0 && state.packets.is_multiple_of(state.prn) {
+            let mut response = vec![
+                dfu::OP_RESPONSE,
+                dfu::OP_CALCULATE_CHECKSUM,
+                dfu::RESULT_SUCCESS,
+            ];
+            response.extend(state.checksum());
+            return Ok(vec![(dfu::CONTROL_POINT_UUID.to_owned(), response)]);
+        }
+        Ok(Vec::new())
+    }
+}
+
+fn characteristic(uuid: &str, properties: CharacteristicProperties) -> Characteristic {
+    Characteristic {
+        uuid: uuid.to_owned(),
+        properties,
+        descriptors: Vec::new(),
+        handle: None,
+    }
+}
+
+fn bootloader(name: &str, state: Arc<Mutex<BootloaderState>>) -> SimulatedPeripheral {
+    let services = vec![Service {
+        uuid: dfu::SERVICE_UUID.to_owned(),
+        characteristics: vec![
+            characteristic(
+                dfu::CONTROL_POINT_UUID,
+                CharacteristicProperties::WRITE | CharacteristicProperties::NOTIFY,
+            ),
+            characteristic(
+                dfu::PACKET_UUID,
+                CharacteristicProperties::WRITE_WITHOUT_RESPONSE,
+            ),
+        ],
+        handles: None,
+    }];
+
+    SimulatedPeripheral {
+        name: name.to_owned(),
+        address: ADDRESS.to_owned(),
+        rssi: -40,
+        services,
+        mtu: 247,
+        device: Box::new(Bootloader(state)),
+    }
+}
+
+async fn connected_bootloader(state: Arc<Mutex<BootloaderState>>) -> SimulatedController {
+    let mut bt = SimulatedController::new(vec![bootloader("DfuTarg", state)]);
+    bt.connect(ADDRESS).await.unwrap();
+    bt
+}
+
+/// Application with the buttonless service without bonds, it records the requests written
+struct Application(Arc<Mutex<Vec<Vec<u8>>>>);
+
+impl SimulatedDevice for Application {
+    fn read(&mut self, _service: &str, characteristic: &str) -> Result<Vec<u8>, String> {
+        Err(format!("Characteristic {} isn't readable", characteristic))
+    }
+
+    fn write(
+        &mut self,
+        _service: &str,
+        characteristic: &str,
+        value: &[u8],
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        self.0.lock().unwrap().push(value.to_vec());
+        let response = vec![dfu::BUTTONLESS_RESPONSE, value[0], dfu::RESULT_SUCCESS];
+        Ok(vec![(characteristic.to_owned(), response)])
+    }
+}
+
+fn firmware(size: usize, seed: u8) -> Vec<u8> {
+    (0..size)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect()
+}
+
+fn init_packet(firmware: &[u8]) -> Vec<u8> {
+    (firmware.len() as u32).to_le_bytes().to_vec()
+}
+
+/// Zip package with the manifest written by nrfutil
+fn package(images: &[(&str, &[u8])]) -> Package {
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+    let mut manifest = serde_json::Map::new();
+    for (image_type, firmware) in images {
+        let bin_file = format!("{}.bin", image_type);
+        let dat_file = format!("{}.dat", image_type);
+        manifest.insert(
+            image_type.to_string(),
+            serde_json::json!({ "bin_file": bin_file, "dat_file": dat_file }),
+        );
+
+        zip.start_file(bin_file, options).unwrap();
+        zip.write_all(firmware).unwrap();
+        zip.start_file(dat_file, options).unwrap();
+        zip.write_all(&init_packet(firmware)).unwrap();
+    }
+    zip.start_file("manifest.json", options).unwrap();
+    zip.write_all(
+        serde_json::json!({ "manifest": manifest })
+            .to_string()
+            .as_bytes(),
+    )
+    .unwrap();
+
+    let mut reader = zip.finish().unwrap();
+    reader.set_position(0);
+    Package::from_reader(reader).unwrap()
+}
+
+#[test]
+fn test_package_order() {
+    let application = firmware(100, 1);
+    let softdevice = firmware(200, 2);
+    let pkg = package(&[("application", &application), ("softdevice", &softdevice)]);
+
+    let types: Vec<&str> = pkg.images.iter().map(|i| i.image_type.as_str()).collect();
+    assert_eq!(types, ["softdevice", "application"]);
+    assert_eq!(pkg.images[0].firmware, softdevice);
+    assert_eq!(pkg.images[0].init_packet, init_packet(&softdevice));
+}
+
+#[tokio::test]
+async fn test_dfu() {
+    let application = firmware(10_000, 1);
+    let softdevice = firmware(5_000, 2);
+    let pkg = package(&[("application", &application), ("softdevice", &softdevice)]);
+
+    let state = Arc::new(Mutex::new(BootloaderState::default()));
+    let mut bt = connected_bootloader(state.clone()).await;
+
+    let options = DfuOptions {
+        prn: 4,
+        ..Default::default()
+    };
+    dfu::run(&mut bt, &pkg, options).await.unwrap();
+    assert!(!bt.is_connected());
+
+    let state = state.lock().unwrap();
+    assert_eq!(
+        state.activated,
+        [
+            (init_packet(&softdevice), softdevice.clone()),
+            (init_packet(&application), application.clone())
+        ]
+    );
+    assert_eq!(state.received, 8 + softdevice.len() + application.len());
+}
+
+#[tokio::test]
+async fn test_dfu_resume() {
+    let application = firmware(10_000, 1);
+    let pkg = package(&[("application", &application)]);
+
+    // the transfer stopped in the middle of the second object
+    let state = Arc::new(Mutex::new(BootloaderState {
+        command: init_packet(&application),
+        firmware: application[..5_000].to_vec(),
+        executed: DATA_MAX_SIZE as usize,
+        ..Default::default()
+    }));
+    let mut bt = connected_bootloader(state.clone()).await;
+
+    dfu::run(&mut bt, &pkg, DfuOptions::default())
+        .await
+        .unwrap();
+
+    let state = state.lock().unwrap();
+    assert_eq!(
+        state.activated,
+        [(init_packet(&application), application.clone())]
+    );
+    assert_eq!(state.received, application.len() - 5_000);
+}
+
+#[tokio::test]
+async fn test_dfu_resume_corrupted() {
+    let application = firmware(10_000, 1);
+    let pkg = package(&[("application", &application)]);
+
+    let mut received = application[..5_000].to_vec();
+    received[4_500] ^= 0xff;
+    let state = Arc::new(Mutex::new(BootloaderState {
+        command: init_packet(&application),
+        firmware: received,
+        executed: DATA_MAX_SIZE as usize,
+        ..Default::default()
+    }));
+    let mut bt = connected_bootloader(state.clone()).await;
+
+    dfu::run(&mut bt, &pkg, DfuOptions::default())
+        .await
+        .unwrap();
+
+    // the corrupted object is sent again from its start
+    let state = state.lock().unwrap();
+    assert_eq!(
+        state.activated,
+        [(init_packet(&application), application.clone())]
+    );
+    assert_eq!(state.received, application.len() - DATA_MAX_SIZE as usize);
+}
+
+#[tokio::test]
+async fn test_dfu_not_connected() {
+    let application = firmware(100, 1);
+    let pkg = package(&[("application", &application)]);
+
+    let state = Arc::new(Mutex::new(BootloaderState::default()));
+    let mut bt = connected_bootloader(state).await;
+
+    bt.disconnect().await.unwrap();
+    assert!(dfu::run(&mut bt, &pkg, DfuOptions::default())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_dfu_buttonless() {
+    let application = firmware(1_000, 1);
+    let pkg = package(&[("application", &application)]);
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let app = SimulatedPeripheral {
+        name: "my_device".to_owned(),
+        address: "F0:0D:00:00:DF:00".to_owned(),
+        rssi: -40,
+        services: vec![Service {
+            uuid: dfu::SERVICE_UUID.to_owned(),
+            characteristics: vec![characteristic(
+                dfu::BUTTONLESS_UUID,
+                CharacteristicProperties::WRITE | CharacteristicProperties::INDICATE,
+            )],
+            handles: None,
+        }],
+        mtu: 23,
+        device: Box::new(Application(requests.clone())),
+    };
+
+    // the bootloader advertises with another address and the name given by the buttonless request
+    let state = Arc::new(Mutex::new(BootloaderState::default()));
+    let name = dfu::bootloader_name();
+    let mut bt = SimulatedController::new(vec![app, bootloader(&name, state.clone())]);
+    bt.connect("F0:0D:00:00:DF:00").await.unwrap();
+
+    dfu::run(&mut bt, &pkg, DfuOptions::default())
+        .await
+        .unwrap();
+
+    let mut set_name = vec![dfu::BUTTONLESS_SET_NAME, name.len() as u8];
+    set_name.extend(name.as_bytes());
+    assert_eq!(
+        *requests.lock().unwrap(),
+        [set_name, vec![dfu::BUTTONLESS_ENTER_BOOTLOADER]]
+    );
+    assert_eq!(
+        state.lock().unwrap().activated,
+        [(init_packet(&application), application.clone())]
+    );
+}
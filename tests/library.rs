@@ -0,0 +1,352 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bluerepl::controllers::gatt_cache::{self, CachedGatt};
+use bluerepl::controllers::simulated::{SimulatedController, SimulatedDevice, SimulatedPeripheral};
+use bluerepl::controllers::{
+    BleController, BlePeripheral, BlePeripheralInfo, Characteristic, CharacteristicProperties,
+    NotificationPrinter, Service,
+};
+use bluerepl::preset::params;
+use bluerepl::repl::commands::write::{self, ChunkOptions, WriteValue};
+use bluerepl::repl::commands::{handle, info, read};
+use bluerepl::utils::output::Output;
+use bluerepl::utils::payload;
+use bluerepl::utils::print_bytes;
+use bluerepl::Preset;
+
+/// Controller recording the values written
+#[derive(Default)]
+struct RecordingController {
+    written: Vec<(String, Vec<u8>)>,
+
+    /// Number of writes that succeed before the next ones fail
+    fail_after: Option<usize>,
+}
+
+#[async_trait]
+impl BleController for RecordingController {
+    async fn scan(&mut self, _scan_time_s: usize) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn get_scan_list(&self) -> Vec<BlePeripheral> {
+        Vec::new()
+    }
+
+    async fn get_adapter_infos(&self) -> Result<String, Box<dyn Error>> {
+        Ok("recording".to_owned())
+    }
+
+    async fn get_peripheral_infos(&mut self) -> Result<BlePeripheralInfo, Box<dyn Error>> {
+        Err("No peripheral")?
+    }
+
+    async fn connect(&mut self, _uuid: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        _service: &str,
+        characteristic: &str,
+        payload: &[u8],
+        _response: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.fail_after == Some(self.written.len()) {
+            Err("Write failed")?
+        }
+        self.written
+            .push((characteristic.to_owned(), payload.to_vec()));
+        Ok(())
+    }
+
+    async fn read(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(vec![85])
+    }
+
+    async fn write_handle(
+        &mut self,
+        _handle: u16,
+        _payload: &[u8],
+        _response: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        Err("No handles")?
+    }
+
+    async fn read_handle(&mut self, _handle: u16) -> Result<Vec<u8>, Box<dyn Error>> {
+        Err("No handles")?
+    }
+
+    async fn read_descriptor(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+        _descriptor: &str,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        Err("No descriptors")?
+    }
+
+    async fn notify(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+        _printer: Option<NotificationPrinter>,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn indicate(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+        _printer: Option<NotificationPrinter>,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn wait_notification(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+        _timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        Err("No notifications")?
+    }
+
+    async fn refresh_gatt(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn get_mtu(&mut self) -> Result<Option<u16>, Box<dyn Error>> {
+        Ok(Some(23))
+    }
+
+    async fn request_mtu(&mut self, mtu: u16) -> Result<u16, Box<dyn Error>> {
+        Ok(mtu)
+    }
+
+    fn is_connected(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_payload_and_format_helpers() {
+    let bytes = payload::parse("u8:0x02 u16le:1200 \"ok\"").unwrap();
+    assert_eq!(bytes, vec![0x02, 0xb0, 0x04, b'o', b'k']);
+    assert_eq!(
+        print_bytes::bytes_to_str(&bytes, "hex"),
+        "[0x02, 0xb0, 0x04, 0x6f, 0x6b]"
+    );
+}
+
+#[tokio::test]
+async fn test_run_preset_command_with_custom_controller() {
+    let pr = Preset::load(PathBuf::from("presets/neopixel_controller.toml")).unwrap();
+    let mut bt = RecordingController::default();
+
+    let (name, args) = params::split_command("set_color r=255 b=0x80").unwrap();
+    pr.run_command(&mut bt, name, &args).await.unwrap();
+
+    assert_eq!(bt.written.len(), 1);
+    assert_eq!(bt.written[0].1, vec![0x03, 0xff, 0x00, 0x80]);
+
+    // unknown commands are reported
+    assert!(pr
+        .run_command(&mut bt, "unknown", &HashMap::new())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_write_in_chunks() {
+    let data: Vec<u8> = (0..45).collect();
+    // the chunks fit in the MTU of the controller (23) by default
+    let mut bt = RecordingController::default();
+    let options = ChunkOptions::default();
+    write::write_chunks(&mut bt, "svc", "chr", None, &data, false, options)
+        .await
+        .unwrap();
+    let sizes: Vec<usize> = bt.written.iter().map(|(_, v)| v.len()).collect();
+    assert_eq!(sizes, vec![20, 20, 5]);
+    let written: Vec<u8> = bt.written.iter().flat_map(|(_, v)| v.clone()).collect();
+    assert_eq!(written, data);
+
+    let options = ChunkOptions {
+        size: Some(30),
+        delay: Duration::ZERO,
+    };
+    // the transfer stops at the first write that fails
+    let mut bt = RecordingController {
+        fail_after: Some(1),
+        ..Default::default()
+    };
+    assert!(
+        write::write_chunks(&mut bt, "svc", "chr", None, &data, true, options)
+            .await
+            .is_err()
+    );
+    assert_eq!(bt.written.len(), 1);
+}
+
+/// Peripheral whose characteristics have the same uuid, their values are stored by handle
+#[derive(Default)]
+struct DuplicateUuids {
+    values: HashMap<u16, Vec<u8>>,
+}
+
+impl SimulatedDevice for DuplicateUuids {
+    fn read(&mut self, _service: &str, characteristic: &str) -> Result<Vec<u8>, String> {
+        Err(format!("Characteristic {} isn't unique", characteristic))
+    }
+
+    fn write(
+        &mut self,
+        _service: &str,
+        characteristic: &str,
+        _value: &[u8],
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        Err(format!("Characteristic {} isn't unique", characteristic))
+    }
+
+    fn read_handle(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+        handle: u16,
+    ) -> Result<Vec<u8>, String> {
+        Ok(self.values.get(&handle).cloned().unwrap_or_default())
+    }
+
+    fn write_handle(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        self.values.insert(handle, value.to_vec());
+        Ok(Vec::new())
+    }
+}
+
+#[tokio::test]
+async fn test_read_and_write_by_handle() {
+    let characteristic = || Characteristic {
+        uuid: "2a19".to_owned(),
+        properties: CharacteristicProperties::READ | CharacteristicProperties::WRITE,
+        descriptors: Vec::new(),
+        handle: None,
+    };
+    let mut bt = SimulatedController::new(vec![SimulatedPeripheral {
+        name: "duplicates".to_owned(),
+        address: "00:00:00:00:00:02".to_owned(),
+        rssi: -40,
+        services: vec![Service {
+            uuid: "180f".to_owned(),
+            characteristics: vec![characteristic(), characteristic()],
+            handles: None,
+        }],
+        mtu: 23,
+        device: Box::new(DuplicateUuids::default()),
+    }]);
+    bt.connect("00:00:00:00:00:02").await.unwrap();
+
+    // the values follow the declarations of the characteristics
+    let infos = bt.get_peripheral_infos().await.unwrap();
+    let handles: Vec<Option<u16>> = infos.services[0]
+        .characteristics
+        .iter()
+        .map(|c| c.handle)
+        .collect();
+    assert_eq!(handles, vec![Some(3), Some(5)]);
+    assert_eq!(infos.services[0].handles, Some((1, 5)));
+    assert_eq!(
+        handle::resolve(&mut bt, 5).await.unwrap(),
+        ("180f".to_owned(), "2a19".to_owned())
+    );
+
+    let value = WriteValue::Payload("0x02".to_owned());
+    write::write_value(&mut bt, "180f", "2a19", Some(5), &value, true)
+        .await
+        .unwrap();
+    assert_eq!(
+        read::read(&mut bt, "180f", "2a19", Some(5)).await.unwrap(),
+        [2]
+    );
+    assert!(read::read(&mut bt, "180f", "2a19", Some(3))
+        .await
+        .unwrap()
+        .is_empty());
+
+    // the uuid alone doesn't tell the characteristics apart
+    assert!(read::read(&mut bt, "180f", "2a19", None).await.is_err());
+    assert!(read::read(&mut bt, "180f", "2a19", Some(4)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_cached_gatt_without_connecting() {
+    let characteristic = || Characteristic {
+        uuid: "2a19".to_owned(),
+        properties: CharacteristicProperties::READ | CharacteristicProperties::WRITE,
+        descriptors: Vec::new(),
+        handle: None,
+    };
+    let mut bt = SimulatedController::new(vec![SimulatedPeripheral {
+        name: "duplicates".to_owned(),
+        address: "00:00:00:00:00:03".to_owned(),
+        rssi: -40,
+        services: vec![Service {
+            uuid: "180f".to_owned(),
+            characteristics: vec![characteristic(), characteristic()],
+            handles: None,
+        }],
+        mtu: 23,
+        device: Box::new(DuplicateUuids::default()),
+    }]);
+    let dir = std::env::temp_dir().join(format!("bluerepl_cache_{}", std::process::id()));
+
+    // the gatt discovered while connected is the one used once disconnected
+    bt.connect("00:00:00:00:00:03").await.unwrap();
+    let live = bt.get_peripheral_infos().await.unwrap();
+    gatt_cache::save(&dir, &CachedGatt::new(&live, Some(vec![0x01]))).unwrap();
+    bt.disconnect().await.unwrap();
+
+    let cached = gatt_cache::load(&dir, "00:00:00:00:00:03").unwrap();
+    assert_eq!(cached.database_hash, Some(vec![0x01]));
+    let cached = cached.to_infos();
+    assert_eq!(
+        serde_json::to_value(&cached.services).unwrap(),
+        serde_json::to_value(&live.services).unwrap()
+    );
+    info::cached_gatt(&cached, &None, Output::Json)
+        .await
+        .unwrap();
+    let exported = Preset::from_peripheral_infos(&cached, &Default::default());
+    assert_eq!(
+        exported.get_device_address(),
+        Some("00:00:00:00:00:03".to_owned())
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,398 @@
+use ciborium::Value;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bluerepl::controllers::simulated::{SimulatedController, SimulatedDevice, SimulatedPeripheral};
+use bluerepl::controllers::{BleController, Characteristic, CharacteristicProperties, Service};
+use bluerepl::repl::commands;
+use bluerepl::smp::{self, map, SmpClient, SmpOptions};
+use bluerepl::utils::output::Output;
+
+const ADDRESS: &str = "F0:0D:00:00:5E:01";
+const OPTIONS: SmpOptions = SmpOptions {
+    timeout: Duration::from_secs(1),
+    write_size: None,
+};
+
+/// Image in a slot of the simulated server
+#[derive(Clone)]
+struct Slot {
+    slot: u32,
+    version: String,
+    hash: Vec<u8>,
+    pending: bool,
+    confirmed: bool,
+    active: bool,
+}
+
+/// State of the simulated SMP server
+#[derive(Default)]
+struct ServerState {
+    slots: Vec<Slot>,
+    upload: Vec<u8>,
+    upload_len: usize,
+    upload_sha: Vec<u8>,
+
+    /// Image bytes received in upload requests
+    received: usize,
+    largest_request: usize,
+    largest_write: usize,
+    resets: usize,
+}
+
+impl ServerState {
+    fn new() -> ServerState {
+        ServerState {
+            slots: vec![Slot {
+                slot: 0,
+                version: "1.0.0".to_owned(),
+                hash: vec![0xaa; 32],
+                pending: false,
+                confirmed: true,
+                active: true,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn images(&self) -> Value {
+        let images = self
+            .slots
+            .iter()
+            .map(|s| {
+                map(vec![
+                    ("slot", Value::from(s.slot)),
+                    ("version", Value::Text(s.version.clone())),
+                    ("hash", Value::Bytes(s.hash.clone())),
+                    ("bootable", Value::Bool(true)),
+                    ("pending", Value::Bool(s.pending)),
+                    ("confirmed", Value::Bool(s.confirmed)),
+                    ("active", Value::Bool(s.active)),
+                    ("permanent", Value::Bool(false)),
+                ])
+            })
+            .collect();
+        map(vec![("images", Value::Array(images))])
+    }
+
+    fn upload(&mut self, request: &Value) -> Value {
+        let offset = smp::field_integer(request, "off").unwrap() as usize;
+        let data = smp::field(request, "data").unwrap().as_bytes().unwrap();
+
+        if offset == 0 {
+            let len = smp::field_integer(request, "len").unwrap() as usize;
+            let sha = smp::field(request, "sha").unwrap().as_bytes().unwrap();
+
+            // the upload of the same image resumes
+            if len == self.upload_len && *sha == self.upload_sha && !self.upload.is_empty() {
+                return map(vec![("off", Value::from(self.upload.len() as u64))]);
+            }
+            self.upload.clear();
+            self.upload_len = len;
+            self.upload_sha = sha.clone();
+        }
+        if offset == self.upload.len() {
+            self.upload.extend(data);
+            self.received += data.len();
+        }
+
+        if self.upload.len() == self.upload_len {
+            self.slots.push(Slot {
+                slot: 1,
+                version: "1.1.0".to_owned(),
+                hash: Sha256::digest(&self.upload).to_vec(),
+                pending: false,
+                confirmed: false,
+                active: false,
+            });
+        }
+        map(vec![
+            ("rc", Value::from(0)),
+            ("off", Value::from(self.upload.len() as u64)),
+        ])
+    }
+
+    fn image_state(&mut self, request: &Value) -> Value {
+        let confirm = smp::field(request, "confirm").and_then(|c| c.as_bool()) == Some(true);
+        let hash = smp::field(request, "hash").and_then(|h| h.as_bytes());
+
+        let slot = match hash {
+            Some(hash) => self.slots.iter_mut().find(|s| s.hash == *hash),
+            None => self.slots.iter_mut().find(|s| s.active),
+        };
+        let Some(slot) = slot else {
+            return map(vec![("rc", Value::from(3))]);
+        };
+        match slot.active {
+            true => slot.confirmed = confirm,
+            false => slot.pending = true,
+        }
+        self.images()
+    }
+
+    fn handle(&mut self, group: u16, id: u8, request: &Value) -> Value {
+        let text = |key| smp::field(request, key).and_then(|v| v.as_text());
+
+        match (group, id) {
+            (smp::GROUP_IMAGE, smp::IMAGE_STATE) if smp::field(request, "confirm").is_some() => {
+                self.image_state(request)
+            }
+            (smp::GROUP_IMAGE, smp::IMAGE_STATE) => self.images(),
+            (smp::GROUP_IMAGE, smp::IMAGE_UPLOAD) => self.upload(request),
+            (smp::GROUP_OS, smp::OS_ECHO) => {
+                map(vec![("r", Value::Text(text("d").unwrap().to_owned()))])
+            }
+            (smp::GROUP_OS, smp::OS_RESET) => {
+                self.resets += 1;
+                map(Vec::new())
+            }
+            (smp::GROUP_OS, smp::OS_TASKSTAT) => {
+                let stats = |prio: u32, stkuse: u32| {
+                    map(vec![
+                        ("prio", Value::from(prio)),
+                        ("stkuse", Value::from(stkuse)),
+                        ("stksiz", Value::from(512)),
+                    ])
+                };
+                map(vec![(
+                    "tasks",
+                    map(vec![("idle", stats(15, 64)), ("main", stats(0, 300))]),
+                )])
+            }
+            (smp::GROUP_STAT, smp::STAT_LIST) => map(vec![(
+                "stat_list",
+                Value::Array(vec![Value::Text("ble_att".to_owned())]),
+            )]),
+            (smp::GROUP_STAT, smp::STAT_READ) if text("name") == Some("ble_att") => map(vec![
+                ("name", Value::Text("ble_att".to_owned())),
+                ("fields", map(vec![("read_req", Value::from(12))])),
+            ]),
+            (smp::GROUP_SHELL, smp::SHELL_EXEC) => {
+                let argv = smp::field(request, "argv").unwrap().as_array().unwrap();
+                let argv: Vec<&str> = argv.iter().map(|a| a.as_text().unwrap()).collect();
+                map(vec![
+                    ("o", Value::Text(format!("ran {}", argv.join(" ")))),
+                    ("ret", Value::from(u8::from(argv[0] == "false"))),
+                ])
+            }
+            _ => map(vec![("rc", Value::from(8))]),
+        }
+    }
+}
+
+/// SMP server reassembling the requests written and splitting its responses in notifications of
+/// the MTU size
+struct Server {
+    state: Arc<Mutex<ServerState>>,
+    buffer: Vec<u8>,
+    mtu: usize,
+}
+
+impl SimulatedDevice for Server {
+    fn read(&mut self, _service: &str, characteristic: &str) -> Result<Vec<u8>, String> {
+        Err(format!("Characteristic {} isn't readable", characteristic))
+    }
+
+    fn write(
+        &mut self,
+        _service: &str,
+        _characteristic: &str,
+        value: &[u8],
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let mut state = self.state.lock().unwrap();
+        state.largest_write = state.largest_write.max(value.len());
+        drop(state);
+
+        self.buffer.extend(value);
+        match smp::Header::parse(&self.buffer) {
+            Some(h) if self.buffer.len() >= smp::HEADER_SIZE + h.len as usize => (),
+            _ => return Ok(Vec::new()),
+        }
+
+        let (header, request) = smp::decode(&self.buffer)?;
+        let mut state = self.state.lock().unwrap();
+        state.largest_request = state.largest_request.max(self.buffer.len());
+        self.buffer.clear();
+        let response = state.handle(header.group, header.id, &request);
+
+        let response_header = smp::Header {
+            op: header.op + 1,
+            ..header
+        };
+        Ok(smp::encode(response_header, &response)
+            .chunks(self.mtu - 3)
+            .map(|chunk| (smp::CHARACTERISTIC_UUID.to_owned(), chunk.to_vec()))
+            .collect())
+    }
+}
+
+async fn connected_server(state: Arc<Mutex<ServerState>>, mtu: u16) -> SimulatedController {
+    let services = vec![Service {
+        uuid: smp::SERVICE_UUID.to_owned(),
+        characteristics: vec![Characteristic {
+            uuid: smp::CHARACTERISTIC_UUID.to_owned(),
+            properties: CharacteristicProperties::WRITE_WITHOUT_RESPONSE
+                | CharacteristicProperties::NOTIFY,
+            descriptors: Vec::new(),
+            handle: None,
+        }],
+        handles: None,
+    }];
+
+    let mut bt = SimulatedController::new(vec![SimulatedPeripheral {
+        name: "zephyr".to_owned(),
+        address: ADDRESS.to_owned(),
+        rssi: -40,
+        services,
+        mtu,
+        device: Box::new(Server {
+            state,
+            buffer: Vec::new(),
+            mtu: mtu as usize,
+        }),
+    }]);
+    bt.connect(ADDRESS).await.unwrap();
+    bt
+}
+
+fn image(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 7) as u8).collect()
+}
+
+#[tokio::test]
+async fn test_smp_image_upload() {
+    let state = Arc::new(Mutex::new(ServerState::new()));
+    let mut bt = connected_server(state.clone(), 23).await;
+    let data = image(3_000);
+
+    // with the minimum MTU the requests and responses are split in several packets
+    let mut client = SmpClient::new(&mut bt, OPTIONS).await.unwrap();
+    let packet_size = client.default_packet_size();
+    assert_eq!(packet_size, smp::MIN_PACKET_SIZE);
+    client.image_upload(&data, 0, packet_size).await.unwrap();
+    assert_eq!(state.lock().unwrap().upload, data);
+    assert!(state.lock().unwrap().largest_request <= packet_size);
+
+    let images = client.image_list().await.unwrap();
+    let hash = smp::to_hex(&Sha256::digest(&data));
+    assert_eq!(images.len(), 2);
+    assert_eq!(images[1].hash, hash);
+    assert_eq!(images[1].flags(), ["bootable"]);
+
+    let images = client
+        .image_state(Some(&smp::parse_hex(&hash).unwrap()), false)
+        .await
+        .unwrap();
+    assert_eq!(images[1].flags(), ["bootable", "pending"]);
+
+    let images = client.image_state(None, true).await.unwrap();
+    assert_eq!(images[0].flags(), ["bootable", "confirmed", "active"]);
+}
+
+#[tokio::test]
+async fn test_smp_image_upload_resume() {
+    let data = image(5_000);
+    let state = Arc::new(Mutex::new(ServerState {
+        upload: data[..2_000].to_vec(),
+        upload_len: data.len(),
+        upload_sha: Sha256::digest(&data).to_vec(),
+        ..ServerState::new()
+    }));
+    let mut bt = connected_server(state.clone(), 247).await;
+
+    let mut client = SmpClient::new(&mut bt, OPTIONS).await.unwrap();
+    assert_eq!(client.default_packet_size(), 244);
+    client.image_upload(&data, 0, 244).await.unwrap();
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.upload, data);
+    assert_eq!(state.received, data.len() - 2_000);
+}
+
+#[tokio::test]
+async fn test_smp_os() {
+    let state = Arc::new(Mutex::new(ServerState::new()));
+    let mut bt = connected_server(state.clone(), 65).await;
+
+    let mut client = SmpClient::new(&mut bt, OPTIONS).await.unwrap();
+    assert_eq!(client.echo("hello").await.unwrap(), "hello");
+
+    let tasks = client.taskstat().await.unwrap();
+    assert_eq!(tasks["main"]["stkuse"], 300);
+    assert_eq!(tasks["idle"]["prio"], 15);
+
+    assert_eq!(client.stat_list().await.unwrap(), ["ble_att"]);
+    assert_eq!(client.stat_read("ble_att").await.unwrap()["read_req"], 12);
+
+    let output = client
+        .shell(&["kernel".to_owned(), "uptime".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(output.output, "ran kernel uptime");
+    assert_eq!(output.ret, 0);
+
+    client.reset().await.unwrap();
+    assert_eq!(state.lock().unwrap().resets, 1);
+}
+
+#[tokio::test]
+async fn test_smp_errors() {
+    let state = Arc::new(Mutex::new(ServerState::new()));
+    let mut bt = connected_server(state, 23).await;
+
+    let mut client = SmpClient::new(&mut bt, OPTIONS).await.unwrap();
+    let e = client
+        .image_state(Some(&[0x01; 32]), false)
+        .await
+        .unwrap_err();
+    assert_eq!(e.to_string(), "SMP error 3: invalid value");
+
+    let e = client.stat_read("unknown").await.unwrap_err();
+    assert_eq!(e.to_string(), "SMP error 8: not supported");
+
+    let e = client.image_upload(&image(100), 0, 40).await.unwrap_err();
+    assert!(e.to_string().contains("too small"));
+
+    // the commands print their results
+    commands::smp::image_list(&mut bt, OPTIONS, Output::Json)
+        .await
+        .unwrap();
+
+    bt.disconnect().await.unwrap();
+    assert!(SmpClient::new(&mut bt, OPTIONS).await.is_err());
+}
+
+#[tokio::test]
+async fn test_smp_write_size_and_commands() {
+    let state = Arc::new(Mutex::new(ServerState::new()));
+    let mut bt = connected_server(state.clone(), 247).await;
+    let options = SmpOptions {
+        write_size: Some(20),
+        ..OPTIONS
+    };
+
+    // the requests are split in writes of the size given, whatever the MTU
+    let fp = std::env::temp_dir().join(format!("bluerepl_smp_{}.bin", std::process::id()));
+    std::fs::write(&fp, image(1_000)).unwrap();
+    commands::smp::image_upload(&mut bt, options, &fp, 0, None)
+        .await
+        .unwrap();
+    std::fs::remove_file(&fp).unwrap();
+    assert_eq!(state.lock().unwrap().upload, image(1_000));
+    assert_eq!(state.lock().unwrap().largest_write, 20);
+
+    // a shell command that fails is an error whatever the output
+    let argv = ["false".to_owned()];
+    for output in [Output::Text, Output::Json] {
+        assert!(commands::smp::shell(&mut bt, options, &argv, output)
+            .await
+            .is_err());
+    }
+
+    let options = SmpOptions {
+        write_size: Some(0),
+        ..OPTIONS
+    };
+    assert!(SmpClient::new(&mut bt, options).await.is_err());
+}